@@ -45,8 +45,6 @@ pub struct Apu {
 }
 
 impl Apu {
-    pub fn step(&mut self) {}
-
     pub fn audio_reg_have_been_written(
         &mut self,
     ) -> (
@@ -17,13 +17,12 @@ mod registers;
 pub mod volume_envelope;
 pub mod wave;
 
-const CYCLES_1_512_SEC: u16 = 8192;
+pub const CYCLES_1_512_SEC: u64 = 8192;
 
 pub struct AudioUnit {
     auo: CpalAudioUnitOutput,
     io_registers: Arc<RwLock<IORegisters>>,
 
-    cycle_count: u16,
     frame_step: Byte,
     was_stopped: bool,
 }
@@ -33,13 +32,21 @@ impl AudioUnit {
         Self {
             auo: au,
             io_registers,
-            cycle_count: 0,
             frame_step: 7,
             was_stopped: true,
         }
     }
 
-    pub fn step(&mut self, last_instruction_cycles: u8, muted: bool) {
+    pub fn reset(&mut self) {
+        self.auo.stop_all();
+        self.frame_step = 7;
+        self.was_stopped = true;
+    }
+
+    /**
+     * Catches up with the registers written and the frame sequencer ticks since the last call.
+     */
+    pub fn step(&mut self, frame_sequencer_ticks: u8, muted: bool) {
         self.auo.set_mute(muted);
 
         let nr52_is_on;
@@ -64,7 +71,9 @@ impl AudioUnit {
             self.frame_step = 7;
         }
 
-        self.clock_frame_sequencer(last_instruction_cycles);
+        for _ in 0..frame_sequencer_ticks {
+            self.clock_frame_sequencer();
+        }
 
         // Sound 1
         if audio_triggers.0.has_change() {
@@ -89,24 +98,19 @@ impl AudioUnit {
         self.auo.update(self.io_registers.clone());
     }
 
-    fn clock_frame_sequencer(&mut self, last_instruction_cycles: u8) {
-        self.cycle_count += last_instruction_cycles as u16;
+    fn clock_frame_sequencer(&mut self) {
+        self.frame_step = (self.frame_step + 1) % 8;
 
-        if self.cycle_count > CYCLES_1_512_SEC {
-            self.cycle_count -= CYCLES_1_512_SEC;
-            self.frame_step = (self.frame_step + 1) % 8;
-
-            if self.frame_step.is_multiple_of(2) {
-                self.auo.step_256();
-            }
+        if self.frame_step.is_multiple_of(2) {
+            self.auo.step_256();
+        }
 
-            if self.frame_step == 7 {
-                self.auo.step_64();
-            }
+        if self.frame_step == 7 {
+            self.auo.step_64();
+        }
 
-            if self.frame_step == 2 || self.frame_step == 6 {
-                self.auo.step_128(self.io_registers.clone())
-            }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.auo.step_128(self.io_registers.clone())
         }
     }
 
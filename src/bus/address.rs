@@ -62,11 +62,89 @@ impl Address {
     pub const STAT: Word = 0xFF41;
     pub const SCY_SCROLL_Y: Word = 0xFF42;
     pub const SCX_SCROLL_X: Word = 0xFF43;
+    pub const LY_LCDC_Y_COORDINATE: Word = 0xFF44;
+    pub const LYC_LY_COMPARE: Word = 0xFF45;
     pub const DMA: Word = 0xFF46;
     pub const BGP_BG_WIN_PALETTE: Word = 0xFF47;
     pub const OBP1_OBJ_PALETTE: Word = 0xFF48;
     pub const OBP2_OBJ_PALETTE: Word = 0xFF49;
     pub const WY_WINDOW_Y_POSITION: Word = 0xFF4A;
     pub const WX_WINDOW_X_POSITION: Word = 0xFF4B;
+    pub const KEY1_SPEED_SWITCH: Word = 0xFF4D;
+    pub const VBK_VRAM_BANK: Word = 0xFF4F;
+    pub const HDMA1_SOURCE_HIGH: Word = 0xFF51;
+    pub const HDMA2_SOURCE_LOW: Word = 0xFF52;
+    pub const HDMA3_DESTINATION_HIGH: Word = 0xFF53;
+    pub const HDMA4_DESTINATION_LOW: Word = 0xFF54;
+    pub const HDMA5_LENGTH_MODE_START: Word = 0xFF55;
+    pub const BCPS_BG_PALETTE_INDEX: Word = 0xFF68;
+    pub const BCPD_BG_PALETTE_DATA: Word = 0xFF69;
+    pub const OCPS_OBJ_PALETTE_INDEX: Word = 0xFF6A;
+    pub const OCPD_OBJ_PALETTE_DATA: Word = 0xFF6B;
+    pub const SVBK_WRAM_BANK: Word = 0xFF70;
     pub const IE_INTERRUPT_ENABLE: Word = 0xFFFF;
+
+    /// Name of the hardware register mapped at the given address, if any.
+    pub fn io_register_name(address: Word) -> Option<&'static str> {
+        let name = match address {
+            Self::P1_JOYPAD => "P1",
+            Self::SB_SERIAL_TRANSFER_DATA => "SB",
+            Self::SC_SIO_CONTROL => "SC",
+            Self::DIV_DIVIDER_REGISTER => "DIV",
+            Self::TIMA_TIMER_COUNTER => "TIMA",
+            Self::TMA_TIMER_MODULO => "TMA",
+            Self::TAC_TIMER_CONTROL => "TAC",
+            Self::IF_INTERRUPT_FLAG => "IF",
+            Self::NR10_SOUND_1_SWEEP => "NR10",
+            Self::NR11_SOUND_1_WAVE_PATTERN_DUTY => "NR11",
+            Self::NR12_SOUND_1_ENVELOPE => "NR12",
+            Self::NR13_SOUND_1_FR_LO => "NR13",
+            Self::NR14_SOUND_1_FR_HI => "NR14",
+            Self::NR21_SOUND_2_WAVE_PATTERN_DUTY => "NR21",
+            Self::NR22_SOUND_2_ENVELOPE => "NR22",
+            Self::NR23_SOUND_2_FR_LO => "NR23",
+            Self::NR24_SOUND_2_FR_HI => "NR24",
+            Self::NR30_SOUND_3_ON_OFF => "NR30",
+            Self::NR31_SOUND_3_LENGTH => "NR31",
+            Self::NR32_SOUND_3_OUTPUT_LEVEL => "NR32",
+            Self::NR33_SOUND_3_FR_LO => "NR33",
+            Self::NR34_SOUND_3_FR_HI => "NR34",
+            Self::NR41_SOUND_4_LENGTH => "NR41",
+            Self::NR42_SOUND_4_ENVELOPE => "NR42",
+            Self::NR43_SOUND_4_FR_RANDOMNESS => "NR43",
+            Self::NR44_SOUND_4_CONTROL => "NR44",
+            Self::NR50 => "NR50",
+            Self::NR51 => "NR51",
+            Self::NR52_SOUND => "NR52",
+            Self::WAVE_PATTERN_START..=Self::WAVE_PATTERN_END => "WAVE RAM",
+            Self::LCDC => "LCDC",
+            Self::STAT => "STAT",
+            Self::SCY_SCROLL_Y => "SCY",
+            Self::SCX_SCROLL_X => "SCX",
+            Self::LY_LCDC_Y_COORDINATE => "LY",
+            Self::LYC_LY_COMPARE => "LYC",
+            Self::DMA => "DMA",
+            Self::BGP_BG_WIN_PALETTE => "BGP",
+            Self::OBP1_OBJ_PALETTE => "OBP0",
+            Self::OBP2_OBJ_PALETTE => "OBP1",
+            Self::WY_WINDOW_Y_POSITION => "WY",
+            Self::WX_WINDOW_X_POSITION => "WX",
+            Self::KEY1_SPEED_SWITCH => "KEY1",
+            Self::VBK_VRAM_BANK => "VBK",
+            Self::HDMA1_SOURCE_HIGH => "HDMA1",
+            Self::HDMA2_SOURCE_LOW => "HDMA2",
+            Self::HDMA3_DESTINATION_HIGH => "HDMA3",
+            Self::HDMA4_DESTINATION_LOW => "HDMA4",
+            Self::HDMA5_LENGTH_MODE_START => "HDMA5",
+            Self::BCPS_BG_PALETTE_INDEX => "BCPS",
+            Self::BCPD_BG_PALETTE_DATA => "BCPD",
+            Self::OCPS_OBJ_PALETTE_INDEX => "OCPS",
+            Self::OCPD_OBJ_PALETTE_DATA => "OCPD",
+            Self::SVBK_WRAM_BANK => "SVBK",
+            Self::IE_INTERRUPT_ENABLE => "IE",
+            _ => return None,
+        };
+
+        Some(name)
+    }
 }
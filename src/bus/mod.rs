@@ -1 +1,62 @@
+use crate::debug::breakpoints::{AccessKind, MemoryAccess};
+use crate::memory::memory_sector::{ReadMemory, WriteMemory};
+use crate::{Byte, Word};
+
 pub mod address;
+
+/**
+ * Everything the CPU reaches through its address and data pins. `Motherboard` is the real one,
+ * tests can plug `Memory` alone or a flat memory instead.
+ */
+pub trait Bus: ReadMemory + WriteMemory {
+    /// Read of an opcode or an operand, which code/data logging must not count as data.
+    fn fetch_byte(&self, position: Word) -> Byte {
+        self.read_byte(position)
+    }
+
+    /// Read for debugging, which the rest of the console does not notice.
+    fn peek_byte(&self, position: Word) -> Byte {
+        self.read_byte(position)
+    }
+
+    /**
+     * `cycles` of the CPU clock went by since the last call, the rest of the console catches up
+     * with them. Returns the CPU cycles until it has something to do again, so the CPU only calls
+     * back then or before an access that depends on the exact cycle.
+     */
+    fn tick(&mut self, _cycles: u64) -> u64 {
+        u64::MAX
+    }
+
+    /// The instruction at the position is about to be executed.
+    fn log_instruction(&self, _position: Word, _length: Word) {}
+
+    /// Address ranges whose reads or writes watchpoints need to see.
+    fn set_watched_ranges(&mut self, _ranges: Vec<(AccessKind, Word, Word)>) {}
+
+    /// Watched accesses since the last call, in order.
+    fn take_accesses(&self) -> Vec<MemoryAccess> {
+        Vec::new()
+    }
+
+    fn has_bootstrap_rom(&self) -> bool {
+        false
+    }
+
+    fn erase_bootstrap_rom(&mut self) {}
+
+    fn current_rom_bank(&self) -> u16 {
+        1
+    }
+
+    /**
+     * A 16-bit register holding `value` goes through the incrementer, which puts it on the
+     * address bus, `reading` if a read from that address happens at the same time.
+     */
+    fn inc_dec(&mut self, _value: Word, _reading: bool) {}
+
+    /// STOP was run. Returns whether it switched the CPU speed, as KEY1 asked for.
+    fn switch_speed(&mut self) -> bool {
+        false
+    }
+}
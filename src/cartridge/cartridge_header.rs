@@ -7,18 +7,28 @@ use crate::cartridge::rom_size::RomSize;
 #[derive(Debug)]
 pub struct CartridgeHeader {
     pub title: String,
+    pub cgb_flag: Byte,
     pub cartridge_type: CartridgeType,
     pub rom_size: RomSize,
     pub ram_size: RamSize,
+    pub logo: [Byte; 48],
+    pub title_checksum: Byte,
+    pub licensed_by_nintendo: bool,
+    pub header_checksum: Byte,
 }
 
 impl CartridgeHeader {
     pub fn new(title: String, cartridge_type: Byte, rom_size: Byte, ram_size: Byte) -> Self {
         Self {
             title,
+            cgb_flag: 0,
             cartridge_type: cartridge_type.into(),
             rom_size: rom_size.into(),
             ram_size: ram_size.into(),
+            logo: [0; 48],
+            title_checksum: 0,
+            licensed_by_nintendo: false,
+            header_checksum: 0,
         }
     }
 
@@ -28,12 +38,30 @@ impl CartridgeHeader {
 
         let title = title_chars.iter().collect::<String>();
 
-        Self::new(
+        let header = Self::new(
             title.trim_end_matches('\0').to_string(),
             data[0x147],
             data[0x148],
             data[0x149],
-        )
+        );
+
+        Self {
+            logo: data[0x104..0x134].try_into().unwrap(),
+            cgb_flag: data[0x143],
+            // The CGB boot ROM checks the whole 16 bytes title area, even the CGB flag
+            title_checksum: data[0x134..0x144]
+                .iter()
+                .fold(0, |sum, byte| sum.wrapping_add(*byte)),
+            licensed_by_nintendo: data[0x14B] == 0x01
+                || (data[0x14B] == 0x33 && data[0x144..0x146] == *b"01"),
+            header_checksum: data[0x14D],
+            ..header
+        }
+    }
+
+    /// Whether the cartridge makes use of the CGB features, whether it also runs on DMG or not.
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & 0x80 == 0x80
     }
 }
 
@@ -26,6 +26,10 @@ impl CartridgeMemorySector {
     pub fn size(&self) -> usize {
         self.data.len()
     }
+
+    pub fn as_slice(&self) -> &[Byte] {
+        &self.data
+    }
 }
 
 impl ReadCartridgeMemory for CartridgeMemorySector {
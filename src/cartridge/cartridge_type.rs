@@ -67,6 +67,20 @@ impl From<Byte> for CartridgeType {
     }
 }
 
+impl CartridgeType {
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self,
+            Self::Rom(_, true)
+                | Self::Mbc1(_, true)
+                | Self::Mbc2(true)
+                | Self::Mmm01(_, true)
+                | Self::Mbc3(_, _, true)
+                | Self::Mbc5(_, _, true)
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,5 +1,6 @@
 use std::fs::File;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 
 use cartridge_header::CartridgeHeader;
 use cartridge_type::CartridgeType;
@@ -10,8 +11,8 @@ use crate::cartridge::cartridge_memory_sector::{
 use crate::memory::memory_sector::{ReadMemory, WriteMemory};
 use crate::{Byte, Word};
 
-mod cartridge_header;
-mod cartridge_memory_sector;
+pub mod cartridge_header;
+pub mod cartridge_memory_sector;
 mod cartridge_type;
 mod ram_size;
 mod rom_size;
@@ -25,6 +26,7 @@ pub struct Cartridge {
     selected_ram_bank: u8,
     ram: CartridgeMemorySector,
     ram_banking_mode: bool,
+    save_path: Option<PathBuf>,
 }
 
 impl Cartridge {
@@ -39,6 +41,7 @@ impl Cartridge {
             selected_ram_bank: 0,
             ram: CartridgeMemorySector::of_size(ram_size_in_bytes),
             ram_banking_mode: false,
+            save_path: None,
         }
     }
 
@@ -50,8 +53,87 @@ impl Cartridge {
             .expect("Error on reading ROM contents");
 
         let header = CartridgeHeader::new_from_data(&data);
+        let has_battery = header.cartridge_type.has_battery();
 
-        Self::new(CartridgeMemorySector::new_from_data(data), header)
+        let mut cartridge = Self::new(CartridgeMemorySector::new_from_data(data), header);
+
+        if has_battery {
+            cartridge.load_save_ram(Path::new(rom_path).with_extension("sav"));
+        }
+
+        cartridge
+    }
+
+    /**
+     * Battery backed RAM lives next to the ROM. A save of a different size is ignored, so a
+     * wrong file never gets mapped as cartridge RAM.
+     */
+    fn load_save_ram(&mut self, save_path: PathBuf) {
+        if let Ok(data) = std::fs::read(&save_path) {
+            if data.len() == self.ram.size() {
+                self.ram = CartridgeMemorySector::new_from_data(data);
+            } else {
+                println!(
+                    "Ignoring {}: expected {} bytes of save RAM, found {}",
+                    save_path.display(),
+                    self.ram.size(),
+                    data.len()
+                );
+            }
+        }
+
+        self.save_path = Some(save_path);
+    }
+
+    pub fn flush_save_ram(&self) {
+        let Some(save_path) = &self.save_path else {
+            return;
+        };
+
+        if self.ram.size() == 0 {
+            return;
+        }
+
+        if let Err(error) = std::fs::write(save_path, self.ram.as_slice()) {
+            println!("Could not write {}: {error}", save_path.display());
+        }
+    }
+
+    /**
+     * Same cartridge after the console is switched off and on: the mapper goes back to its
+     * initial banks and only battery backed RAM keeps its contents.
+     */
+    pub fn power_cycle(self) -> Self {
+        self.flush_save_ram();
+
+        let ram = if self.save_path.is_some() {
+            self.ram
+        } else {
+            CartridgeMemorySector::of_size(self.ram.size())
+        };
+
+        Self {
+            ram,
+            save_path: self.save_path,
+            ..Self::new(self.data, self.header)
+        }
+    }
+
+    pub fn selected_rom_bank(&self) -> u16 {
+        self.selected_rom_bank & self.header.rom_size.mask()
+    }
+
+    /**
+     * Position in the ROM data an address of the ROM area maps to with the current bank.
+     */
+    pub fn rom_offset(&self, address: Word) -> Option<usize> {
+        match address {
+            0x0000..=0x3FFF => Some(address as usize),
+            0x4000..=0x7FFF => {
+                Some(address as usize - 0x4000 + 0x4000 * self.selected_rom_bank() as usize)
+            }
+            _ => None,
+        }
     }
 
     pub fn print_header(&self) {
@@ -70,21 +152,15 @@ impl Default for Cartridge {
             selected_ram_bank: 1,
             ram: CartridgeMemorySector::of_size(0),
             ram_banking_mode: false,
+            save_path: None,
         }
     }
 }
 
 impl ReadMemory for Cartridge {
     fn read_byte(&self, address: Word) -> Byte {
-        if address < 0x4000 {
-            return self.data.read_byte(address as usize);
-        }
-
-        if (0x4000..0x8000).contains(&address) {
-            return self.data.read_byte(
-                address as usize - 0x4000
-                    + 0x4000 * (self.selected_rom_bank & self.header.rom_size.mask()) as usize,
-            );
+        if let Some(offset) = self.rom_offset(address) {
+            return self.data.read_byte(offset);
         }
 
         match self.header.cartridge_type {
@@ -322,6 +398,8 @@ impl Cartridge {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use assert_fs::TempDir;
+    use assert_fs::fixture::{FileWriteBin, PathChild};
     use test_case::test_case;
 
     #[test]
@@ -373,6 +451,63 @@ mod tests {
         assert_eq!(cartridge.ram_enabled, false);
     }
 
+    fn create_rom_file(directory: &TempDir, cartridge_type: Byte) -> String {
+        let mut data = vec![0; 0x8000];
+        data[0x147] = cartridge_type;
+        // 8 KiB of RAM
+        data[0x149] = 0x02;
+
+        let rom_file = directory.child("game.gb");
+        rom_file.write_binary(&data).unwrap();
+
+        rom_file.path().to_str().unwrap().to_string()
+    }
+
+    fn write_to_ram(cartridge: &mut Cartridge, value: Byte) {
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0xA000, value);
+        cartridge.write_byte(0x2000, 0x03);
+    }
+
+    #[test]
+    fn test_power_cycle_keeps_battery_ram_and_flushes_it() {
+        let directory = TempDir::new().unwrap();
+        let rom_path = create_rom_file(&directory, 0x03);
+
+        let mut cartridge = Cartridge::new_from_path(&rom_path);
+        write_to_ram(&mut cartridge, 0x42);
+
+        let mut cartridge = cartridge.power_cycle();
+
+        assert_eq!(cartridge.selected_rom_bank, 1);
+        assert!(!cartridge.ram_enabled);
+        cartridge.write_byte(0x0000, 0x0A);
+        assert_eq!(cartridge.read_byte(0xA000), 0x42);
+
+        let save = std::fs::read(directory.child("game.sav").path()).unwrap();
+        assert_eq!(save.len(), 0x2000);
+        assert_eq!(save[0], 0x42);
+
+        let mut reloaded = Cartridge::new_from_path(&rom_path);
+        reloaded.write_byte(0x0000, 0x0A);
+        assert_eq!(reloaded.read_byte(0xA000), 0x42);
+    }
+
+    #[test]
+    fn test_power_cycle_clears_ram_without_battery() {
+        let directory = TempDir::new().unwrap();
+        let rom_path = create_rom_file(&directory, 0x02);
+
+        let mut cartridge = Cartridge::new_from_path(&rom_path);
+        write_to_ram(&mut cartridge, 0x42);
+
+        let mut cartridge = cartridge.power_cycle();
+        cartridge.write_byte(0x0000, 0x0A);
+
+        assert_eq!(cartridge.read_byte(0xA000), 0x00);
+        assert!(!directory.child("game.sav").path().exists());
+    }
+
     #[test]
     fn test_read_rom_bank_byte_when_rom_bank_higher_than_available() {
         let header = CartridgeHeader::new("TEST".to_string(), 0x01, 0x01, 0);
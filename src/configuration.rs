@@ -1,18 +1,200 @@
+use crate::Word;
 use crate::cpu::Cpu;
-use clap::{Arg, Command};
+use crate::debug::breakpoints::Breakpoint;
+use crate::debug::trace::TraceCondition;
+use crate::model::Model;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::str::FromStr;
 
 #[readonly::make]
 pub struct Configuration {
     pub debug_header: bool,
     pub bootstrap_path: Option<String>,
     pub rom_file: String,
+    pub model: Model,
 
     pub user_speed_multiplier: i32,
     pub trace: bool,
+    pub trace_file: Option<String>,
+    pub trace_start: Option<TraceCondition>,
+    pub trace_stop: Option<TraceCondition>,
+    pub symbols_path: Option<String>,
+    pub profile_path: Option<String>,
+    pub code_data_log_path: Option<String>,
+    pub breakpoints: Vec<Breakpoint>,
+    pub breakpoints_path: Option<String>,
+    pub debugger: bool,
 }
 
-impl Configuration {
+/// What the executable has been asked to do: run a ROM, disassemble it or run test ROMs.
+pub enum Mode {
+    Emulate(Configuration),
+    Disassemble(DisassembleConfiguration),
+    Test(TestConfiguration),
+}
+
+#[readonly::make]
+pub struct DisassembleConfiguration {
+    pub rom_file: String,
+    pub bank: Option<u16>,
+    pub range: Option<(Word, Word)>,
+    pub symbols_path: Option<String>,
+    pub code_data_log_path: Option<String>,
+}
+
+#[readonly::make]
+pub struct TestConfiguration {
+    pub directory: String,
+    pub model: Model,
+    pub cycles: u64,
+    pub report_path: Option<String>,
+    pub expected_path: Option<String>,
+}
+
+impl Mode {
     pub fn from_command(app_name: &'static str) -> Self {
+        let command = Configuration::command(app_name)
+            .subcommand_negates_reqs(true)
+            .args_conflicts_with_subcommands(true)
+            .subcommand(DisassembleConfiguration::command())
+            .subcommand(TestConfiguration::command());
+
+        let matches = command.get_matches();
+
+        match matches.subcommand() {
+            Some(("disasm", matches)) => {
+                Self::Disassemble(DisassembleConfiguration::from_matches(matches))
+            }
+            Some(("test", matches)) => Self::Test(TestConfiguration::from_matches(matches)),
+            _ => Self::Emulate(Configuration::from_matches(&matches)),
+        }
+    }
+}
+
+impl DisassembleConfiguration {
+    fn command() -> Command {
+        Command::new("disasm")
+            .about("Writes an RGBDS compatible disassembly of a ROM to the standard output")
+            .arg(
+                Arg::new("ROMFILE")
+                    .required(true)
+                    .index(1)
+                    .help("Path of the ROM file to disassemble"),
+            )
+            .arg(
+                Arg::new("bank")
+                    .long("bank")
+                    .value_parser(clap::value_parser!(u16))
+                    .help("Only disassembles the given ROM bank"),
+            )
+            .arg(
+                Arg::new("range")
+                    .long("range")
+                    .value_parser(parse_range)
+                    .help("Only disassembles addresses in START..END (hexadecimal, END excluded)"),
+            )
+            .arg(
+                Arg::new("symbols")
+                    .long("symbols")
+                    .help("Loads an RGBDS or WLA-DX .sym file to label disassembled addresses"),
+            )
+            .arg(
+                Arg::new("cdl")
+                    .long("cdl")
+                    .help("Uses a code/data log recorded while emulating to tell code from data"),
+            )
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Self {
+        Self {
+            rom_file: matches.get_one::<String>("ROMFILE").unwrap().to_string(),
+            bank: matches.get_one::<u16>("bank").copied(),
+            range: matches.get_one::<(Word, Word)>("range").copied(),
+            symbols_path: matches.get_one::<String>("symbols").map(|x| x.to_string()),
+            code_data_log_path: matches.get_one::<String>("cdl").map(|x| x.to_string()),
+        }
+    }
+}
+
+impl TestConfiguration {
+    /// One minute of emulated time.
+    const DEFAULT_CYCLES: u64 = Cpu::CLOCK_FREQUENCY as u64 * 60;
+
+    fn command() -> Command {
+        Command::new("test")
+            .about("Runs every .gb test ROM in a directory headless and reports their results")
+            .arg(
+                Arg::new("DIRECTORY")
+                    .required(true)
+                    .index(1)
+                    .help("Directory searched recursively for test ROMs"),
+            )
+            .arg(
+                Arg::new("model")
+                    .long("model")
+                    .value_parser(Model::from_str)
+                    .help(format!(
+                        "Hardware model to emulate: {}",
+                        Model::NAMES.join(", ")
+                    )),
+            )
+            .arg(
+                Arg::new("cycles")
+                    .long("cycles")
+                    .value_parser(clap::value_parser!(u64))
+                    .help("Emulated cycles each ROM gets before timing out (default: one minute)"),
+            )
+            .arg(
+                Arg::new("report")
+                    .long("report")
+                    .help("Writes a tab separated report of the results to the given file"),
+            )
+            .arg(
+                Arg::new("expected").long("expected").help(
+                    "Report of an earlier run: only ROMs that passed there make the run fail",
+                ),
+            )
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Self {
+        Self {
+            directory: matches.get_one::<String>("DIRECTORY").unwrap().to_string(),
+            model: matches
+                .get_one::<Model>("model")
+                .copied()
+                .unwrap_or_default(),
+            cycles: matches
+                .get_one::<u64>("cycles")
+                .copied()
+                .unwrap_or(Self::DEFAULT_CYCLES),
+            report_path: matches.get_one::<String>("report").map(|x| x.to_string()),
+            expected_path: matches.get_one::<String>("expected").map(|x| x.to_string()),
+        }
+    }
+}
+
+fn parse_range(value: &str) -> Result<(Word, Word), String> {
+    let parse_address = |address: &str| {
+        let address = address.trim_start_matches('$').trim_start_matches("0x");
+
+        Word::from_str_radix(address, 16).map_err(|e| format!("Invalid address {address}: {e}"))
+    };
+
+    let (start, end) = value
+        .split_once("..")
+        .ok_or_else(|| format!("Expected START..END, got {value}"))?;
+
+    let (start, end) = (parse_address(start)?, parse_address(end)?);
+
+    if start >= end {
+        return Err(format!("Empty range {value}"));
+    }
+
+    Ok((start, end))
+}
+
+impl Configuration {
+    fn command(app_name: &'static str) -> Command {
         let command = Command::new(app_name)
             .arg(
                 Arg::new("ROMFILE")
@@ -29,6 +211,67 @@ impl Configuration {
                 Arg::new("bootstrap")
                     .long("bootstrap")
                     .help("Uses bootstrap ROM"),
+            )
+            .arg(
+                Arg::new("model")
+                    .long("model")
+                    .value_parser(Model::from_str)
+                    .help(format!(
+                        "Hardware model to emulate: {} (default: dmg)",
+                        Model::NAMES.join(", ")
+                    )),
+            )
+            .arg(
+                Arg::new("trace-file")
+                    .long("trace-file")
+                    .help("Writes a Gameboy Doctor compatible log of each executed instruction"),
+            )
+            .arg(
+                Arg::new("trace-start")
+                    .long("trace-start")
+                    .requires("trace-file")
+                    .value_parser(TraceCondition::from_str)
+                    .help("Starts the trace on pc:ADDR[-ADDR], bank:N or frame:N"),
+            )
+            .arg(
+                Arg::new("trace-stop")
+                    .long("trace-stop")
+                    .requires("trace-file")
+                    .value_parser(TraceCondition::from_str)
+                    .help("Stops the trace on pc:ADDR[-ADDR], bank:N or frame:N"),
+            )
+            .arg(
+                Arg::new("symbols")
+                    .long("symbols")
+                    .help("Loads an RGBDS or WLA-DX .sym file to label disassembled addresses"),
+            )
+            .arg(
+                Arg::new("profile")
+                    .long("profile")
+                    .help("Writes a cycle profile to the given file and its folded call stacks next to it, on exit"),
+            )
+            .arg(
+                Arg::new("cdl")
+                    .long("cdl")
+                    .help("Records executed code and read data in a code/data log, kept across sessions"),
+            )
+            .arg(
+                Arg::new("break")
+                    .long("break")
+                    .action(ArgAction::Append)
+                    .value_parser(Breakpoint::from_str)
+                    .help("Pauses on \"exec [BANK:]ADDR\", \"read ADDR[-ADDR]\" or \"write ADDR[-ADDR]\", optionally followed by \"if A == $3C\", \"hits N\" or \"once\""),
+            )
+            .arg(
+                Arg::new("breakpoints")
+                    .long("breakpoints")
+                    .help("Loads breakpoints from a file, one per line as given to --break"),
+            )
+            .arg(
+                Arg::new("debugger")
+                    .long("debugger")
+                    .num_args(0)
+                    .help("Starts paused, reading debugger commands from the standard input"),
             );
 
         #[cfg(debug_assertions)]
@@ -39,8 +282,10 @@ impl Configuration {
                 .help("Print each instruction as it executes (dev builds only)"),
         );
 
-        let matches = command.get_matches();
+        command
+    }
 
+    fn from_matches(matches: &ArgMatches) -> Self {
         #[cfg(debug_assertions)]
         let trace = matches.contains_id("trace");
         #[cfg(not(debug_assertions))]
@@ -52,9 +297,29 @@ impl Configuration {
                 .get_one::<String>("bootstrap")
                 .map(|x| x.to_string()),
             rom_file: matches.get_one::<String>("ROMFILE").unwrap().to_string(),
+            model: matches
+                .get_one::<Model>("model")
+                .copied()
+                .unwrap_or_default(),
 
             user_speed_multiplier: 1,
             trace,
+            trace_file: matches
+                .get_one::<String>("trace-file")
+                .map(|x| x.to_string()),
+            trace_start: matches.get_one::<TraceCondition>("trace-start").cloned(),
+            trace_stop: matches.get_one::<TraceCondition>("trace-stop").cloned(),
+            symbols_path: matches.get_one::<String>("symbols").map(|x| x.to_string()),
+            profile_path: matches.get_one::<String>("profile").map(|x| x.to_string()),
+            code_data_log_path: matches.get_one::<String>("cdl").map(|x| x.to_string()),
+            breakpoints: matches
+                .get_many::<Breakpoint>("break")
+                .map(|breakpoints| breakpoints.cloned().collect())
+                .unwrap_or_default(),
+            breakpoints_path: matches
+                .get_one::<String>("breakpoints")
+                .map(|x| x.to_string()),
+            debugger: matches.contains_id("debugger"),
         }
     }
 }
@@ -65,6 +330,8 @@ pub struct RuntimeConfig {
     pub available_cycles: i32,
     pub reset: bool,
     pub debug: bool,
+    pub crash_report_requested: bool,
+    pub paused: bool,
 }
 
 impl Default for RuntimeConfig {
@@ -75,6 +342,8 @@ impl Default for RuntimeConfig {
             available_cycles: Cpu::AVAILABLE_CCYCLES_PER_FRAME,
             reset: false,
             debug: false,
+            crash_report_requested: false,
+            paused: false,
         }
     }
 }
@@ -96,10 +365,6 @@ impl RuntimeConfig {
         self.available_cycles = Cpu::AVAILABLE_CCYCLES_PER_FRAME * self.user_speed_multiplier;
     }
 
-    pub fn cpu_has_available_ccycles(&self) -> bool {
-        self.available_cycles > 0
-    }
-
     pub fn is_debug(&self) -> bool {
         self.debug
     }
@@ -107,4 +372,40 @@ impl RuntimeConfig {
     pub fn toggle_debug(&mut self) {
         self.debug = !self.debug;
     }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, value: bool) {
+        self.paused = value;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn request_crash_report(&mut self) {
+        self.crash_report_requested = true;
+    }
+
+    /// Whether a crash report has been asked for since the last call.
+    pub fn take_crash_report_request(&mut self) -> bool {
+        std::mem::take(&mut self.crash_report_requested)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("0150..0200", Ok((0x0150, 0x0200)); "plain hex")]
+    #[test_case("$4000..$4100", Ok((0x4000, 0x4100)); "dollar prefix")]
+    #[test_case("0x100..0x104", Ok((0x0100, 0x0104)); "0x prefix")]
+    #[test_case("0200..0150", Err(()); "empty range")]
+    #[test_case("0150", Err(()); "missing end")]
+    fn test_parse_range(value: &str, expected: Result<(Word, Word), ()>) {
+        assert_eq!(parse_range(value).map_err(|_| ()), expected);
+    }
 }
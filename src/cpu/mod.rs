@@ -1,100 +1,424 @@
 use std::sync::Arc;
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
+use crate::bus::Bus;
 use crate::bus::address::Address;
 use crate::cpu::alu::Alu;
 use crate::cpu::registers::{ByteRegister, CpuRegisters, WordRegister};
-use crate::debug::{CPU_PC_WATCHPOINTS, DebugReason, Debuggable, OutputDebug};
-use crate::io::registers::IORegisters;
-use crate::memory::Memory;
-use crate::{Byte, Word};
+use crate::debug::DebugReason;
+use crate::debug::breakpoints::{Breakpoint, BreakpointHit, Breakpoints};
+use crate::debug::call_stack::{CallStack, Frame, StackOverflow};
+use crate::debug::history::{ExecutionHistory, HistoryEntry};
+use crate::debug::profiler::{Location, Profiler};
+use crate::disassembler::Disassembler;
+use crate::disassembler::symbols::SymbolTable;
+use crate::utils::math::{two_bytes_to_word, word_to_two_bytes};
+use crate::{Byte, SignedByte, Word};
 
 pub mod alu;
-#[cfg(debug_assertions)]
-mod disassemble;
 pub mod registers;
+#[cfg(test)]
+mod single_step_tests;
 
 pub struct Cpu {
-    memory: Arc<RwLock<Memory>>,
-    io_registers: Arc<RwLock<IORegisters>>,
+    memory: Arc<RwLock<dyn Bus + Send + Sync>>,
 
     pub registers: CpuRegisters,
     alu: Alu,
 
     pc_to_increment: i8,
     last_instruction_ccycles: u8,
+    // M-cycles of the current instruction the bus has already gone through
+    ticked_ccycles: u8,
     ime: bool,
     halted: bool,
+    locked: bool,
+    // Cycles the CPU is still kept off the bus for, by a speed switch or VRAM DMA
+    stalled_cycles: u64,
+    double_speed: bool,
+    // CPU cycles run ahead of the rest of the console, and how far it can get before it matters
+    ccycles_ahead: u64,
+    ccycles_to_event: u64,
+    caught_up: bool,
 
     last_instruction: String,
+    symbols: SymbolTable,
+
+    cycles: u64,
+    normal_speed_cycles: u64,
+    history: ExecutionHistory,
+    call_stack: CallStack,
+    profiler: Option<Arc<Mutex<Profiler>>>,
+
+    breakpoints: Breakpoints,
+    breakpoint_hit: Option<BreakpointHit>,
+    // Why the CPU locked up, until the frontend takes it
+    lock_reason: Option<DebugReason>,
+    // Stopped before the instruction at PC, which runs on the next step regardless of breakpoints
+    resuming: bool,
+}
+
+/// Bytes taken by each instruction, opcode included. Illegal opcodes count as one byte.
+#[rustfmt::skip]
+const INSTRUCTION_LENGTHS: [u8; 256] = [
+    1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1,
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1,
+    1, 1, 3, 1, 3, 1, 2, 1, 1, 1, 3, 1, 3, 1, 2, 1,
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,
+];
+
+/// How an executed instruction moved between functions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ControlTransfer {
+    Call,
+    Return,
+}
+
+impl ControlTransfer {
+    /**
+     * Taken CALL, RST and RET instructions, told apart from the skipped conditional ones by the
+     * return address they pushed or popped.
+     */
+    fn from_instruction(instruction: Byte, sp_before: Word, sp_after: Word) -> Option<Self> {
+        match instruction {
+            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC | 0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7
+            | 0xFF
+                if sp_after == sp_before.wrapping_sub(2) =>
+            {
+                Some(ControlTransfer::Call)
+            }
+            0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9 if sp_after == sp_before.wrapping_add(2) => {
+                Some(ControlTransfer::Return)
+            }
+            _ => None,
+        }
+    }
 }
 
 impl Cpu {
     pub const AVAILABLE_CCYCLES_PER_FRAME: i32 = 70221;
+    pub const CLOCK_FREQUENCY: u32 = 4_194_304;
+    /// The CPU is stopped for 2050 M-cycles while it switches speed.
+    const SPEED_SWITCH_CCYCLES: u64 = 8200;
 
-    pub fn new(
-        memory: Arc<RwLock<Memory>>,
-        io_registers: Arc<RwLock<IORegisters>>,
-        bootstrap: bool,
-    ) -> Cpu {
+    pub fn new(memory: Arc<RwLock<dyn Bus + Send + Sync>>, registers: CpuRegisters) -> Cpu {
         Cpu {
             memory,
-            io_registers,
 
-            registers: CpuRegisters::new(bootstrap),
+            registers,
             alu: Alu {},
 
             pc_to_increment: -1,
             last_instruction_ccycles: 0,
+            ticked_ccycles: 0,
             ime: false,
             halted: false,
+            locked: false,
+            stalled_cycles: 0,
+            double_speed: false,
+            ccycles_ahead: 0,
+            ccycles_to_event: 0,
+            caught_up: false,
             last_instruction: String::new(),
+            symbols: SymbolTable::default(),
+
+            cycles: 0,
+            normal_speed_cycles: 0,
+            history: ExecutionHistory::default(),
+            call_stack: CallStack::default(),
+            profiler: None,
+
+            breakpoints: Breakpoints::default(),
+            breakpoint_hit: None,
+            lock_reason: None,
+            resuming: false,
+        }
+    }
+
+    /**
+     * Back to power-on state with the given initial registers, keeping the loaded symbols and
+     * the debugging tools.
+     */
+    pub fn reset(&mut self, registers: CpuRegisters) {
+        let symbols = std::mem::take(&mut self.symbols);
+        let profiler = self.profiler.take();
+        let breakpoints = std::mem::take(&mut self.breakpoints);
+
+        *self = Cpu::new(self.memory.clone(), registers);
+        self.symbols = symbols;
+        self.profiler = profiler;
+        self.breakpoints = breakpoints;
+    }
+
+    pub fn load_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn set_profiler(&mut self, profiler: Arc<Mutex<Profiler>>) {
+        self.profiler = Some(profiler);
+    }
+
+    /// Returns the id given to the breakpoint.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        let id = self.breakpoints.add(breakpoint);
+        self.update_watched_ranges();
+
+        id
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        let removed = self.breakpoints.remove(id);
+        self.update_watched_ranges();
+
+        removed
+    }
+
+    pub fn set_breakpoint_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        let found = self.breakpoints.set_enabled(id, enabled);
+        self.update_watched_ranges();
+
+        found
+    }
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoint_hit(&self) -> Option<&BreakpointHit> {
+        self.breakpoint_hit.as_ref()
+    }
+
+    /// The breakpoint that stopped the last step, if any, for the frontend to pause on.
+    pub fn take_breakpoint_hit(&mut self) -> Option<BreakpointHit> {
+        self.breakpoint_hit.take()
+    }
+
+    /// Why the last step locked the CPU up, if it did, for the frontend to pause on.
+    pub fn take_lock_reason(&mut self) -> Option<DebugReason> {
+        self.lock_reason.take()
+    }
+
+    fn update_watched_ranges(&mut self) {
+        self.memory
+            .write()
+            .set_watched_ranges(self.breakpoints.watched_ranges());
+    }
+
+    /**
+     * Stops before the instruction at PC when a breakpoint on it is hit. Only once: the next step
+     * runs it, so emulation can go on from the breakpoint.
+     */
+    fn check_execute_breakpoints(&mut self) -> bool {
+        if std::mem::take(&mut self.resuming) || self.breakpoints.is_empty() {
+            return false;
         }
+
+        let hit = {
+            let memory = self.memory.read();
+
+            self.breakpoints.check_execute(
+                self.registers.pc,
+                memory.current_rom_bank(),
+                &self.registers,
+                &|address| memory.peek_byte(address),
+            )
+        };
+
+        if hit.is_none() {
+            return false;
+        }
+
+        self.breakpoint_hit = hit;
+        self.resuming = true;
+        self.update_watched_ranges();
+
+        true
+    }
+
+    /// Watchpoints stop after the instruction that made the access, with its effects visible.
+    fn check_watchpoints(&mut self) {
+        if self.breakpoints.is_empty() {
+            return;
+        }
+
+        let hit = {
+            let memory = self.memory.read();
+            let accesses = memory.take_accesses();
+
+            if accesses.is_empty() {
+                return;
+            }
+
+            self.breakpoints
+                .check_accesses(&accesses, &self.registers, &|address| {
+                    memory.peek_byte(address)
+                })
+        };
+
+        if hit.is_some() {
+            self.breakpoint_hit = hit;
+            self.update_watched_ranges();
+        }
+    }
+
+    pub fn is_fetching(&self) -> bool {
+        !self.halted && !self.locked
+    }
+
+    /**
+     * Whether the next step runs the instruction at PC, and has not already stopped before it at
+     * a breakpoint: it is not halted, locked or stalled either.
+     */
+    pub fn is_about_to_run(&self) -> bool {
+        self.is_fetching() && !self.is_stalled() && !self.resuming
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn is_stalled(&self) -> bool {
+        self.stalled_cycles > 0
+    }
+
+    /// Keeps the CPU from running instructions or serving interrupts for `cycles` more cycles.
+    pub fn stall(&mut self, cycles: u64) {
+        self.stalled_cycles += cycles;
+    }
+
+    /// Cycles of the CPU clock run since power on.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The same cycles counted on the normal speed clock, as the rest of the console does.
+    pub fn normal_speed_cycles(&self) -> u64 {
+        self.normal_speed_cycles
+    }
+
+    /// Whether the rest of the console caught up with the CPU since the last call.
+    pub fn take_caught_up(&mut self) -> bool {
+        std::mem::take(&mut self.caught_up)
+    }
+
+    pub fn history(&self) -> &ExecutionHistory {
+        &self.history
+    }
+
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    /// A stack overflow found by the last steps, for the frontend to warn about.
+    pub fn take_stack_overflow(&mut self) -> Option<StackOverflow> {
+        self.call_stack.take_overflow()
     }
 
-    pub fn reset(&mut self) {
-        self.registers.pc = 0x100;
+    pub fn backtrace(&self) -> Vec<String> {
+        let rom_bank = self.memory.read().current_rom_bank();
+
+        self.call_stack
+            .backtrace(self.registers.pc, rom_bank, &self.symbols)
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
     }
 
     pub fn step(&mut self, debug: bool, trace: bool) -> u8 {
-        #[cfg(not(debug_assertions))]
-        let _ = trace;
         self.last_instruction = "".to_string();
         self.pc_to_increment = -1;
         self.last_instruction_ccycles = 0;
+        self.ticked_ccycles = 0;
+
+        // A locked CPU stops fetching until reset, but the clock keeps feeding the PPU and APU
+        if self.locked {
+            self.tick();
+            return 4;
+        }
+
+        if self.stalled_cycles > 0 {
+            self.stalled_cycles = self.stalled_cycles.saturating_sub(4);
+            self.tick();
+            return 4;
+        }
 
         if !self.halted {
+            if self.check_execute_breakpoints() {
+                return 0;
+            }
+
             let instruction;
             let memory_has_bootstrap_rom;
+            let rom_bank;
+            let (pc_before, sp_before) = (self.registers.pc, self.registers.sp);
 
             {
                 let memory = self.memory.read();
 
-                instruction = memory.read_byte(self.registers.pc);
+                instruction = memory.fetch_byte(self.registers.pc);
                 memory_has_bootstrap_rom = memory.has_bootstrap_rom();
+                rom_bank = memory.current_rom_bank();
+
+                memory.log_instruction(
+                    self.registers.pc,
+                    INSTRUCTION_LENGTHS[instruction as usize] as Word,
+                );
+
+                let entry = HistoryEntry {
+                    registers: self.registers.clone(),
+                    rom_bank,
+                    bytes: [
+                        instruction,
+                        memory.peek_byte(self.registers.pc.wrapping_add(1)),
+                        memory.peek_byte(self.registers.pc.wrapping_add(2)),
+                    ],
+                    cycles: self.cycles,
+                };
+
+                // Traces are only printed in debug builds
+                if debug || (cfg!(debug_assertions) && trace) {
+                    let disassembled = Disassembler::with_symbols(&self.symbols).disassemble(
+                        &entry,
+                        self.registers.pc,
+                        rom_bank,
+                    );
+
+                    self.last_instruction =
+                        format!("{}: {}", disassembled.location(), disassembled);
+                }
+
+                self.history.push(entry);
 
                 #[cfg(debug_assertions)]
                 if trace {
-                    println!(
-                        "{:04X}: {}",
-                        self.registers.pc,
-                        disassemble::disassemble(self.registers.pc, &memory)
-                    );
+                    println!("{}", self.last_instruction);
                 }
             }
 
+            self.tick();
+
             if self.registers.pc == Address::CARTRIDGE_START && memory_has_bootstrap_rom {
                 self.memory.write().erase_bootstrap_rom();
             }
 
-            let mut output_debug = OutputDebug::new_with_reason(DebugReason::PC(self.registers.pc));
-            let debug_watchpoint = CPU_PC_WATCHPOINTS.contains(&self.registers.pc);
-
-            if debug_watchpoint {
-                output_debug.push_situation("Before", self.io_registers.read().get_debug_values());
-            }
-
             match instruction {
                 0x00 => self.nop(),
                 0x01 => self.ld_rr_nn(WordRegister::BC),
@@ -372,8 +696,9 @@ impl Cpu {
                 0xFB => self.ei(),
                 0xFE => self.cp_n(),
                 0xFF => self.rst_v_w_out(0x38),
-                _ => {
-                    panic!("Instruction not implemented: {instruction:X}");
+
+                0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+                    self.lock(instruction)
                 }
             }
 
@@ -382,26 +707,61 @@ impl Cpu {
                 "Instruction does not increment PC: {instruction:X}",
             );
 
-            if debug_watchpoint {
-                output_debug.push_situation("After", self.io_registers.read().get_debug_values());
-                output_debug.print();
+            if let Some(profiler) = &self.profiler {
+                profiler.lock().record(
+                    Location::new(rom_bank, pc_before),
+                    self.last_instruction_ccycles,
+                    self.call_stack.functions(),
+                );
+            }
+
+            match ControlTransfer::from_instruction(instruction, sp_before, self.registers.sp) {
+                Some(ControlTransfer::Call) => {
+                    // RST opcodes are a single byte, CALL carries the address
+                    let length = if instruction & 0xC7 == 0xC7 { 1 } else { 3 };
+
+                    self.call_stack.call(Frame {
+                        function: Location::new(rom_bank, self.registers.pc),
+                        return_address: pc_before.wrapping_add(length),
+                        sp: self.registers.sp,
+                        interrupt: false,
+                    });
+                }
+                Some(ControlTransfer::Return) => self.call_stack.ret(sp_before),
+                None => {}
             }
         } else {
             self.last_instruction_ccycles = 4;
             self.pc_to_increment = 0;
+
+            if let Some(profiler) = &self.profiler {
+                profiler.lock().record_halt(self.last_instruction_ccycles);
+            }
         }
 
         if debug {
-            println!("{:X}: {}", self.registers.pc, self.last_instruction);
+            println!("{}", self.last_instruction);
         }
 
+        // Internal M-cycles after the last access
+        while self.ticked_ccycles < self.last_instruction_ccycles {
+            self.tick();
+        }
+
+        debug_assert_eq!(
+            self.ticked_ccycles, self.last_instruction_ccycles,
+            "Instruction went through more M-cycles than it takes",
+        );
+
         self.registers.pc += self.pc_to_increment as Word;
 
+        self.check_watchpoints();
+
         self.last_instruction_ccycles
     }
 
     fn prefix_cb(&mut self) {
-        let op: Byte = { self.memory.read().read_byte(self.registers.pc + 1) };
+        let op = self.fetch(self.registers.pc + 1);
 
         match op {
             0x00 => self.rlc_r(ByteRegister::B),
@@ -694,13 +1054,100 @@ impl Cpu {
         }
     }
 
+    // --- BUS -------------------------------------------------------------------------------------
+
+    /**
+     * One M-cycle goes by on the bus, with or without an access. The rest of the console only
+     * catches up once it has something to do.
+     */
+    fn tick(&mut self) {
+        self.ticked_ccycles += 4;
+        self.cycles += 4;
+        self.normal_speed_cycles += if self.double_speed { 2 } else { 4 };
+        self.ccycles_ahead += 4;
+
+        if self.ccycles_ahead >= self.ccycles_to_event {
+            self.catch_up();
+        }
+    }
+
+    /**
+     * Lets the rest of the console catch up with the cycles the CPU has run ahead of it. Needed
+     * before looking at it from outside, as it may be behind otherwise.
+     */
+    pub fn catch_up(&mut self) {
+        let ccycles = std::mem::take(&mut self.ccycles_ahead);
+
+        self.ccycles_to_event = self.memory.write().tick(ccycles);
+        self.caught_up = true;
+    }
+
+    /// OAM and the I/O registers, whose accesses depend on the exact cycle they happen in.
+    fn is_timed(position: Word) -> bool {
+        (0xFE00..0xFF80).contains(&position)
+    }
+
+    /// Reads an operand in the current M-cycle.
+    fn fetch(&mut self, position: Word) -> Byte {
+        if Self::is_timed(position) {
+            self.catch_up();
+        }
+
+        let value = self.memory.read().fetch_byte(position);
+        self.tick();
+
+        value
+    }
+
+    fn fetch_signed(&mut self, position: Word) -> SignedByte {
+        self.fetch(position) as SignedByte
+    }
+
+    /// Little endian operand, in two M-cycles.
+    fn fetch_word(&mut self, position: Word) -> Word {
+        let low = self.fetch(position);
+        let high = self.fetch(position.wrapping_add(1));
+
+        two_bytes_to_word(high, low)
+    }
+
+    fn read(&mut self, position: Word) -> Byte {
+        if Self::is_timed(position) {
+            self.catch_up();
+        }
+
+        let value = self.memory.read().read_byte(position);
+        self.tick();
+
+        value
+    }
+
+    fn write(&mut self, position: Word, value: Byte) {
+        if Self::is_timed(position) {
+            self.catch_up();
+            self.memory.write().write_byte(position, value);
+            // It may have scheduled something, so the rest of the console has to see its M-cycle
+            self.ccycles_to_event = 0;
+        } else {
+            self.memory.write().write_byte(position, value);
+        }
+
+        self.tick();
+    }
+
+    /// Only OAM notices what the incrementer puts on the address bus.
+    fn inc_dec(&mut self, value: Word, reading: bool) {
+        if (0xFE00..=0xFEFF).contains(&value) {
+            self.catch_up();
+            self.memory.write().inc_dec(value, reading);
+        }
+    }
+
     // --- INSTRUCTIONS ---------------------------------------------------------------------------------------------------------------------
 
     fn nop(&mut self) {
         self.pc_to_increment = 1;
         self.last_instruction_ccycles = 4;
-
-        self.last_instruction = "NOP".to_string();
     }
 
     // --- ARITHMETIC INSTRUCTIONS ----------------------------------------------------------------------------------------------------------
@@ -717,14 +1164,9 @@ impl Cpu {
     fn dec_mhl(&mut self) {
         let pos = self.registers.read_word(&WordRegister::HL);
 
-        {
-            let mut memory = self.memory.write();
-            let value = memory.read_byte(pos);
-
-            let value = self.alu.dec_n(&mut self.registers, value);
-
-            memory.write_byte(pos, value);
-        }
+        let value = self.read(pos);
+        let value = self.alu.dec_n(&mut self.registers, value);
+        self.write(pos, value);
 
         self.pc_to_increment = 1;
         self.last_instruction_ccycles = 12;
@@ -732,6 +1174,7 @@ impl Cpu {
 
     fn dec_rr(&mut self, register: WordRegister) {
         let value = self.registers.read_word(&register);
+        self.inc_dec(value, false);
         self.registers.write_word(&register, self.alu.dec_nn(value));
 
         self.pc_to_increment = 1;
@@ -740,6 +1183,7 @@ impl Cpu {
 
     fn inc_rr(&mut self, register: WordRegister) {
         let value = self.registers.read_word(&register);
+        self.inc_dec(value, false);
         self.registers.write_word(&register, self.alu.inc_nn(value));
 
         self.pc_to_increment = 1;
@@ -758,12 +1202,9 @@ impl Cpu {
     fn inc_mhl(&mut self) {
         let position = self.registers.read_word(&WordRegister::HL);
 
-        {
-            let mut memory = self.memory.write();
-            let value = memory.read_byte(position);
-            let value = self.alu.inc_n(&mut self.registers, value);
-            memory.write_byte(position, value);
-        }
+        let value = self.read(position);
+        let value = self.alu.inc_n(&mut self.registers, value);
+        self.write(position, value);
 
         self.pc_to_increment = 1;
         self.last_instruction_ccycles = 12;
@@ -785,11 +1226,7 @@ impl Cpu {
 
     fn adc_a_mhl(&mut self) {
         let value1 = self.registers.read_byte(&ByteRegister::A);
-        let value2 = {
-            self.memory
-                .read()
-                .read_byte(self.registers.read_word(&WordRegister::HL))
-        };
+        let value2 = self.read(self.registers.read_word(&WordRegister::HL));
 
         let carry = self.registers.is_flag_c();
 
@@ -802,7 +1239,7 @@ impl Cpu {
 
     fn adc_a_n(&mut self) {
         let value1 = self.registers.a;
-        let value2 = { self.memory.read().read_byte(self.registers.pc + 1) };
+        let value2 = self.fetch(self.registers.pc + 1);
 
         let carry = self.registers.is_flag_c();
 
@@ -825,7 +1262,7 @@ impl Cpu {
     }
 
     fn add_a_n(&mut self) {
-        let value1 = { self.memory.read().read_byte(self.registers.pc + 1) };
+        let value1 = self.fetch(self.registers.pc + 1);
         let value2 = self.registers.read_byte(&ByteRegister::A);
 
         let result = self.alu.add_n(&mut self.registers, value1, value2, false);
@@ -836,11 +1273,7 @@ impl Cpu {
     }
 
     fn add_a_mhl(&mut self) {
-        let value1 = {
-            self.memory
-                .read()
-                .read_byte(self.registers.read_word(&WordRegister::HL))
-        };
+        let value1 = self.read(self.registers.read_word(&WordRegister::HL));
         let value2 = self.registers.read_byte(&ByteRegister::A);
 
         let result = self.alu.add_n(&mut self.registers, value1, value2, false);
@@ -863,10 +1296,7 @@ impl Cpu {
 
     fn add_sp_n(&mut self) {
         let value1 = self.registers.read_word(&WordRegister::SP);
-        let value2 = self
-            .memory
-            .read()
-            .read_signed_byte(self.registers.read_word(&WordRegister::PC) + 1);
+        let value2 = self.fetch_signed(self.registers.read_word(&WordRegister::PC) + 1);
 
         let result = self
             .alu
@@ -878,7 +1308,7 @@ impl Cpu {
     }
 
     fn sub_n(&mut self) {
-        let to_subtract = { self.memory.read().read_byte(self.registers.pc + 1) };
+        let to_subtract = self.fetch(self.registers.pc + 1);
 
         let value = self.registers.read_byte(&ByteRegister::A);
 
@@ -906,11 +1336,7 @@ impl Cpu {
     fn sub_mhl(&mut self) {
         let value = self.registers.read_byte(&ByteRegister::A);
 
-        let to_subtract = {
-            self.memory
-                .read()
-                .read_byte(self.registers.read_word(&WordRegister::HL))
-        };
+        let to_subtract = self.read(self.registers.read_word(&WordRegister::HL));
 
         let value = self
             .alu
@@ -940,11 +1366,7 @@ impl Cpu {
     fn sbc_mhl(&mut self) {
         let value = self.registers.read_byte(&ByteRegister::A);
 
-        let to_subtract = {
-            self.memory
-                .read()
-                .read_byte(self.registers.read_word(&WordRegister::HL))
-        };
+        let to_subtract = self.read(self.registers.read_word(&WordRegister::HL));
 
         let carry = self.registers.is_flag_c();
 
@@ -960,11 +1382,7 @@ impl Cpu {
 
     fn sbc_a_n(&mut self) {
         let value1 = self.registers.read_byte(&ByteRegister::A);
-        let value2 = {
-            self.memory
-                .read()
-                .read_byte(self.registers.read_word(&WordRegister::PC) + 1)
-        };
+        let value2 = self.fetch(self.registers.read_word(&WordRegister::PC) + 1);
 
         let carry = self.registers.is_flag_c();
 
@@ -1020,7 +1438,7 @@ impl Cpu {
     }
 
     fn xor_n(&mut self) {
-        let value = { self.memory.read().read_byte(self.registers.pc + 1) };
+        let value = self.fetch(self.registers.pc + 1);
         let result = value ^ self.registers.read_byte(&ByteRegister::A);
 
         self.registers.set_flag_z(result == 0);
@@ -1038,11 +1456,7 @@ impl Cpu {
      * XORs value in memory address HL with register A. Saves result in A. Sets flag Z if result is 0, resets N, H and C.
      */
     fn xor_mhl(&mut self) {
-        let mut value = {
-            self.memory
-                .read()
-                .read_byte(self.registers.read_word(&WordRegister::HL))
-        };
+        let mut value = self.read(self.registers.read_word(&WordRegister::HL));
         value ^= self.registers.read_byte(&ByteRegister::A);
 
         self.registers.set_flag_z(value == 0);
@@ -1069,11 +1483,7 @@ impl Cpu {
     }
 
     fn or_mhl(&mut self) {
-        let value1 = {
-            self.memory
-                .read()
-                .read_byte(self.registers.read_word(&WordRegister::HL))
-        };
+        let value1 = self.read(self.registers.read_word(&WordRegister::HL));
         let value2 = self.registers.read_byte(&ByteRegister::A);
 
         let result = self.alu.or_n(&mut self.registers, value1, value2);
@@ -1085,7 +1495,7 @@ impl Cpu {
     }
 
     fn or_n(&mut self) {
-        let value1 = { self.memory.read().read_byte(self.registers.pc + 1) };
+        let value1 = self.fetch(self.registers.pc + 1);
         let value2 = self.registers.read_byte(&ByteRegister::A);
 
         let result = self.alu.or_n(&mut self.registers, value1, value2);
@@ -1109,11 +1519,7 @@ impl Cpu {
     }
 
     fn and_mhl(&mut self) {
-        let value1 = {
-            self.memory
-                .read()
-                .read_byte(self.registers.read_word(&WordRegister::HL))
-        };
+        let value1 = self.read(self.registers.read_word(&WordRegister::HL));
         let value2 = self.registers.read_byte(&ByteRegister::A);
 
         let result = self.alu.and_n(&mut self.registers, value1, value2);
@@ -1125,7 +1531,7 @@ impl Cpu {
     }
 
     fn and_n(&mut self) {
-        let value1 = { self.memory.read().read_byte(self.registers.pc + 1) };
+        let value1 = self.fetch(self.registers.pc + 1);
         let value2 = self.registers.a;
 
         let result = self.alu.and_n(&mut self.registers, value1, value2);
@@ -1177,22 +1583,16 @@ impl Cpu {
     }
 
     fn cp_n(&mut self) {
-        let n = { self.memory.read().read_byte(self.registers.pc + 1) };
+        let n = self.fetch(self.registers.pc + 1);
 
         self.alu.cp_n(&mut self.registers, n);
 
         self.pc_to_increment = 2;
         self.last_instruction_ccycles = 8;
-
-        self.last_instruction = format!("CP {n:X}");
     }
 
     fn cp_mhl(&mut self) {
-        let n = {
-            self.memory
-                .read()
-                .read_byte(self.registers.read_word(&WordRegister::HL))
-        };
+        let n = self.read(self.registers.read_word(&WordRegister::HL));
 
         self.alu.cp_n(&mut self.registers, n);
 
@@ -1201,11 +1601,7 @@ impl Cpu {
     }
 
     fn ld_r_n(&mut self, register: ByteRegister) {
-        let value = {
-            self.memory
-                .read()
-                .read_byte(self.registers.read_word(&WordRegister::PC) + 1)
-        };
+        let value = self.fetch(self.registers.read_word(&WordRegister::PC) + 1);
         self.registers.write_byte(&register, value);
 
         self.pc_to_increment = 2;
@@ -1222,11 +1618,7 @@ impl Cpu {
 
     fn ld_hl_sp_n(&mut self) {
         let add1 = self.registers.read_word(&WordRegister::SP);
-        let add2 = {
-            self.memory
-                .read()
-                .read_signed_byte(self.registers.read_word(&WordRegister::PC) + 1)
-        };
+        let add2 = self.fetch_signed(self.registers.read_word(&WordRegister::PC) + 1);
 
         let new_value = self
             .alu
@@ -1246,11 +1638,7 @@ impl Cpu {
     }
 
     fn ld_r_mrr(&mut self, register_to: ByteRegister, register_from: WordRegister) {
-        let value = {
-            self.memory
-                .read()
-                .read_byte(self.registers.read_word(&register_from))
-        };
+        let value = self.read(self.registers.read_word(&register_from));
         self.registers.write_byte(&register_to, value);
 
         self.pc_to_increment = 1;
@@ -1261,33 +1649,22 @@ impl Cpu {
      * Writes value from register A to memory address $FF00 + n.
      */
     fn ldh_n_a(&mut self) {
-        let to_sum;
-
-        {
-            let mut memory = self.memory.write();
-            to_sum = memory.read_byte(self.registers.pc + 1) as Word;
-            let mem_addr = Address::IO_REGISTERS_START + to_sum;
+        let to_sum = self.fetch(self.registers.pc + 1) as Word;
+        let mem_addr = Address::IO_REGISTERS_START + to_sum;
 
-            memory.write_byte(mem_addr, self.registers.a);
-        }
+        self.write(mem_addr, self.registers.a);
 
         self.pc_to_increment = 2;
         self.last_instruction_ccycles = 12;
-
-        self.last_instruction = format!("LDH ($FF{to_sum:X}, A)")
     }
 
     /**
      * Writes value from register A to memory address $FF00 + C.
      */
     fn ld_mc_a(&mut self) {
-        {
-            let mut memory = self.memory.write();
-
-            let mem_addr =
-                Address::IO_REGISTERS_START + self.registers.read_byte(&ByteRegister::C) as Word;
-            memory.write_byte(mem_addr, self.registers.a);
-        }
+        let mem_addr =
+            Address::IO_REGISTERS_START + self.registers.read_byte(&ByteRegister::C) as Word;
+        self.write(mem_addr, self.registers.a);
 
         self.pc_to_increment = 1;
         self.last_instruction_ccycles = 8;
@@ -1300,7 +1677,7 @@ impl Cpu {
         let mem_addr =
             Address::IO_REGISTERS_START + self.registers.read_byte(&ByteRegister::C) as Word;
 
-        self.registers.a = self.memory.read().read_byte(mem_addr);
+        self.registers.a = self.read(mem_addr);
 
         self.pc_to_increment = 1;
         self.last_instruction_ccycles = 8;
@@ -1310,32 +1687,22 @@ impl Cpu {
      * Writes value from memory address $FF00 + n to register A.
      */
     fn ldh_a_n(&mut self) {
-        let to_sum;
-
-        {
-            let memory = self.memory.read();
-            to_sum = memory.read_byte(self.registers.pc + 1) as Word;
+        let to_sum = self.fetch(self.registers.pc + 1) as Word;
 
-            let mem_addr = Address::IO_REGISTERS_START + to_sum;
-            self.registers.a = memory.read_byte(mem_addr);
-        }
+        let mem_addr = Address::IO_REGISTERS_START + to_sum;
+        self.registers.a = self.read(mem_addr);
 
         self.pc_to_increment = 2;
         self.last_instruction_ccycles = 12;
-
-        self.last_instruction = format!("LDH (A, $FF{to_sum:X})")
     }
 
     /**
      * Writes value from register A to memory address nn.
      */
     fn ld_nn_a(&mut self) {
-        {
-            let mut memory = self.memory.write();
-            let mem_addr = memory.read_word(self.registers.pc + 1);
+        let mem_addr = self.fetch_word(self.registers.pc + 1);
 
-            memory.write_byte(mem_addr, self.registers.a);
-        }
+        self.write(mem_addr, self.registers.a);
 
         self.pc_to_increment = 3;
         self.last_instruction_ccycles = 16;
@@ -1345,12 +1712,9 @@ impl Cpu {
      * Writes value from memory address nn to register A.
      */
     fn ld_a_nn(&mut self) {
-        {
-            let memory = self.memory.read();
-            let mem_addr = memory.read_word(self.registers.pc + 1);
+        let mem_addr = self.fetch_word(self.registers.pc + 1);
 
-            self.registers.a = memory.read_byte(mem_addr);
-        }
+        self.registers.a = self.read(mem_addr);
 
         self.pc_to_increment = 3;
         self.last_instruction_ccycles = 16;
@@ -1362,9 +1726,7 @@ impl Cpu {
     fn ldd_mhl_a(&mut self) {
         let pos = self.registers.read_word(&WordRegister::HL);
 
-        {
-            self.memory.write().write_byte(pos, self.registers.a);
-        }
+        self.write(pos, self.registers.a);
 
         let value = pos;
         self.registers
@@ -1380,9 +1742,7 @@ impl Cpu {
     fn ldi_mhl_a(&mut self) {
         let pos = self.registers.read_word(&WordRegister::HL);
 
-        {
-            self.memory.write().write_byte(pos, self.registers.a);
-        }
+        self.write(pos, self.registers.a);
 
         let value = pos;
         self.registers
@@ -1393,12 +1753,10 @@ impl Cpu {
     }
 
     fn ld_mrr_r(&mut self, register_to: WordRegister, register_from: ByteRegister) {
-        {
-            self.memory.write().write_byte(
-                self.registers.read_word(&register_to),
-                self.registers.read_byte(&register_from),
-            );
-        }
+        self.write(
+            self.registers.read_word(&register_to),
+            self.registers.read_byte(&register_from),
+        );
 
         self.pc_to_increment = 1;
         self.last_instruction_ccycles = 8;
@@ -1408,13 +1766,9 @@ impl Cpu {
      * Writes 8bit value to memory address contained in HL.
      */
     fn ld_mhl_n(&mut self) {
-        {
-            let mut memory = self.memory.write();
+        let value = self.fetch(self.registers.pc + 1);
 
-            let value = memory.read_byte(self.registers.pc + 1);
-
-            memory.write_byte(self.registers.read_word(&WordRegister::HL), value);
-        }
+        self.write(self.registers.read_word(&WordRegister::HL), value);
 
         self.pc_to_increment = 2;
         self.last_instruction_ccycles = 12;
@@ -1423,7 +1777,8 @@ impl Cpu {
     fn ldi_a_mhl(&mut self) {
         let mut new_value_hl = self.registers.read_word(&WordRegister::HL);
 
-        let value = { self.memory.read().read_byte(new_value_hl) };
+        self.inc_dec(new_value_hl, true);
+        let value = self.read(new_value_hl);
         self.registers.a = value;
 
         new_value_hl = self.alu.inc_nn(new_value_hl);
@@ -1436,7 +1791,8 @@ impl Cpu {
 
     fn ldd_a_mhl(&mut self) {
         let mut new_value_hl = self.registers.read_word(&WordRegister::HL);
-        let value = { self.memory.read().read_byte(new_value_hl) };
+        self.inc_dec(new_value_hl, true);
+        let value = self.read(new_value_hl);
         self.registers.a = value;
 
         new_value_hl = self.alu.dec_nn(new_value_hl);
@@ -1448,7 +1804,7 @@ impl Cpu {
     }
 
     fn ld_rr_nn(&mut self, register: WordRegister) {
-        let value = { self.memory.read().read_word(self.registers.pc + 1) };
+        let value = self.fetch_word(self.registers.pc + 1);
         self.registers.write_word(&register, value);
 
         self.pc_to_increment = 3;
@@ -1456,13 +1812,11 @@ impl Cpu {
     }
 
     fn ld_mnn_sp(&mut self) {
-        {
-            let mut memory = self.memory.write();
-            let mem_addr = memory.read_word(self.registers.read_word(&WordRegister::PC) + 1);
+        let mem_addr = self.fetch_word(self.registers.pc + 1);
+        let value = word_to_two_bytes(self.registers.sp);
 
-            let value = self.registers.read_word(&WordRegister::SP);
-            memory.write_word(mem_addr, value);
-        }
+        self.write(mem_addr, value.1);
+        self.write(mem_addr.wrapping_add(1), value.0);
 
         self.pc_to_increment = 3;
         self.last_instruction_ccycles = 20;
@@ -1474,7 +1828,7 @@ impl Cpu {
      * Jumps to the current PC + n
      */
     fn jr_n(&mut self) {
-        let to_sum = { self.memory.read().read_signed_byte(self.registers.pc + 1) } + 2;
+        let to_sum = self.fetch_signed(self.registers.pc + 1) + 2;
 
         self.registers.pc = self.registers.pc.wrapping_add(to_sum as Word);
 
@@ -1486,7 +1840,7 @@ impl Cpu {
      * Jumps to the current PC + n only if the flag Z is not set. Otherwise, continues to the next instruction.
      */
     fn jr_nz_n(&mut self) {
-        let possible_value: i8 = { self.memory.read().read_signed_byte(self.registers.pc + 1) };
+        let possible_value: i8 = self.fetch_signed(self.registers.pc + 1);
 
         self.registers.pc += 2;
 
@@ -1504,7 +1858,7 @@ impl Cpu {
      * Jumps to the current PC + n only if the flag Z is set. Otherwise, continues to the next instruction.
      */
     fn jr_z_n(&mut self) {
-        let possible_value: i8 = { self.memory.read().read_signed_byte(self.registers.pc + 1) };
+        let possible_value: i8 = self.fetch_signed(self.registers.pc + 1);
 
         self.registers.pc += 2;
 
@@ -1522,7 +1876,7 @@ impl Cpu {
      * Jumps to the current PC + n only if the flag C is set. Otherwise, continues to the next instruction.
      */
     fn jr_c_n(&mut self) {
-        let possible_value: i8 = { self.memory.read().read_signed_byte(self.registers.pc + 1) };
+        let possible_value: i8 = self.fetch_signed(self.registers.pc + 1);
 
         self.registers.pc += 2;
 
@@ -1540,7 +1894,7 @@ impl Cpu {
      * Jumps to the current PC + n only if the flag C is not set. Otherwise, continues to the next instruction.
      */
     fn jr_nc_n(&mut self) {
-        let possible_value: i8 = { self.memory.read().read_signed_byte(self.registers.pc + 1) };
+        let possible_value: i8 = self.fetch_signed(self.registers.pc + 1);
 
         self.registers.pc += 2;
 
@@ -1558,7 +1912,7 @@ impl Cpu {
      * Jumps to the 16 bit address given.
      */
     fn jp_nn(&mut self) {
-        self.registers.pc = self.memory.read().read_word(self.registers.pc + 1);
+        self.registers.pc = self.fetch_word(self.registers.pc + 1);
 
         self.pc_to_increment = 0;
         self.last_instruction_ccycles = 16;
@@ -1575,7 +1929,7 @@ impl Cpu {
     }
 
     fn jp_c_nn(&mut self) {
-        let possible_value = { self.memory.read().read_word(self.registers.pc + 1) };
+        let possible_value = self.fetch_word(self.registers.pc + 1);
 
         self.registers.pc += 3;
 
@@ -1590,11 +1944,7 @@ impl Cpu {
     }
 
     fn jp_nc_nn(&mut self) {
-        let possible_value = {
-            self.memory
-                .read()
-                .read_word(self.registers.read_word(&WordRegister::PC) + 1)
-        };
+        let possible_value = self.fetch_word(self.registers.read_word(&WordRegister::PC) + 1);
 
         self.registers.pc += 3;
 
@@ -1612,7 +1962,7 @@ impl Cpu {
      * Jumps to the indicated address only if the flag Z is set. Otherwise, continues to the next instruction.
      */
     fn jp_z_nn(&mut self) {
-        let possible_value = { self.memory.read().read_word(self.registers.pc + 1) };
+        let possible_value = self.fetch_word(self.registers.pc + 1);
 
         self.registers.pc += 3;
 
@@ -1630,7 +1980,7 @@ impl Cpu {
      * Jumps to the indicated address only if the flag Z is NOT set. Otherwise, continues to the next instruction.
      */
     fn jp_nz_nn(&mut self) {
-        let possible_value = { self.memory.read().read_word(self.registers.pc + 1) };
+        let possible_value = self.fetch_word(self.registers.pc + 1);
 
         self.registers.pc += 3;
 
@@ -1650,10 +2000,12 @@ impl Cpu {
      * Push address of next instruction onto stack and then jump to address nn.
      */
     fn call(&mut self) {
+        let address = self.fetch_word(self.registers.pc + 1);
+
         let next_pc = self.registers.pc + 3;
         self.push_vv(next_pc);
 
-        self.registers.pc = self.memory.read().read_word(self.registers.pc + 1);
+        self.registers.pc = address;
 
         self.pc_to_increment = 0;
         self.last_instruction_ccycles = 24;
@@ -1663,6 +2015,8 @@ impl Cpu {
      * If flag Z is set, push address of next instruction onto stack and then jump to address nn.
      */
     fn call_z_nn(&mut self) {
+        let address = self.fetch_word(self.registers.pc + 1);
+
         if !self.registers.is_flag_z() {
             self.pc_to_increment = 3;
             self.last_instruction_ccycles = 12;
@@ -1673,7 +2027,7 @@ impl Cpu {
         let next_pc = self.registers.pc + 3;
         self.push_vv(next_pc);
 
-        self.registers.pc = self.memory.read().read_word(self.registers.pc + 1);
+        self.registers.pc = address;
 
         self.pc_to_increment = 0;
         self.last_instruction_ccycles = 24;
@@ -1683,6 +2037,8 @@ impl Cpu {
      * If flag Z is reset, push address of next instruction onto stack and then jump to address nn.
      */
     fn call_nz_nn(&mut self) {
+        let address = self.fetch_word(self.registers.pc + 1);
+
         if self.registers.is_flag_z() {
             self.pc_to_increment = 3;
             self.last_instruction_ccycles = 12;
@@ -1693,7 +2049,7 @@ impl Cpu {
         let next_pc = self.registers.pc + 3;
         self.push_vv(next_pc);
 
-        self.registers.pc = self.memory.read().read_word(self.registers.pc + 1);
+        self.registers.pc = address;
 
         self.pc_to_increment = 0;
         self.last_instruction_ccycles = 24;
@@ -1703,6 +2059,8 @@ impl Cpu {
      * If flag Z is set, push address of next instruction onto stack and then jump to address nn.
      */
     fn call_c_nn(&mut self) {
+        let address = self.fetch_word(self.registers.pc + 1);
+
         if !self.registers.is_flag_c() {
             self.pc_to_increment = 3;
             self.last_instruction_ccycles = 12;
@@ -1713,7 +2071,7 @@ impl Cpu {
         let next_pc = self.registers.pc + 3;
         self.push_vv(next_pc);
 
-        self.registers.pc = self.memory.read().read_word(self.registers.pc + 1);
+        self.registers.pc = address;
 
         self.pc_to_increment = 0;
         self.last_instruction_ccycles = 24;
@@ -1723,6 +2081,8 @@ impl Cpu {
      * If flag C is reset, push address of next instruction onto stack and then jump to address nn.
      */
     fn call_nc_nn(&mut self) {
+        let address = self.fetch_word(self.registers.pc + 1);
+
         if self.registers.is_flag_c() {
             self.pc_to_increment = 3;
             self.last_instruction_ccycles = 12;
@@ -1733,7 +2093,7 @@ impl Cpu {
         let next_pc = self.registers.pc + 3;
         self.push_vv(next_pc);
 
-        self.registers.pc = self.memory.read().read_word(self.registers.pc + 1);
+        self.registers.pc = address;
 
         self.pc_to_increment = 0;
         self.last_instruction_ccycles = 24;
@@ -1765,6 +2125,9 @@ impl Cpu {
      * Pop two bytes from stack & jump to that address if flag Z is not set.
      */
     fn ret_nz(&mut self) {
+        // Checking the condition
+        self.tick();
+
         if !self.registers.is_flag_z() {
             self.registers.pc = self.pop_vv();
             self.last_instruction_ccycles = 20;
@@ -1780,6 +2143,9 @@ impl Cpu {
      * Pop two bytes from stack & jump to that address if flag Z is set.
      */
     fn ret_z(&mut self) {
+        // Checking the condition
+        self.tick();
+
         if self.registers.is_flag_z() {
             self.registers.pc = self.pop_vv();
             self.last_instruction_ccycles = 20;
@@ -1795,6 +2161,9 @@ impl Cpu {
      * Pop two bytes from stack & jump to that address if flag C is not set.
      */
     fn ret_nc(&mut self) {
+        // Checking the condition
+        self.tick();
+
         if !self.registers.is_flag_c() {
             self.registers.pc = self.pop_vv();
             self.last_instruction_ccycles = 20;
@@ -1810,6 +2179,9 @@ impl Cpu {
      * Pop two bytes from stack & jump to that address if flag C is set.
      */
     fn ret_c(&mut self) {
+        // Checking the condition
+        self.tick();
+
         if self.registers.is_flag_c() {
             self.registers.pc = self.pop_vv();
             self.last_instruction_ccycles = 20;
@@ -1871,27 +2243,23 @@ impl Cpu {
     fn rr_mhl(&mut self) {
         let address = self.registers.read_word(&WordRegister::HL);
 
-        {
-            let mut memory = self.memory.write();
+        let mut value = self.read(address);
 
-            let mut value = memory.read_byte(address);
-
-            let carry: bool = value & 0b1 == 1;
-            let msf = if self.registers.is_flag_c() {
-                0b10000000
-            } else {
-                0
-            };
+        let carry: bool = value & 0b1 == 1;
+        let msf = if self.registers.is_flag_c() {
+            0b10000000
+        } else {
+            0
+        };
 
-            value = msf | ((value >> 1) & 0b01111111);
+        value = msf | ((value >> 1) & 0b01111111);
 
-            self.registers.set_flag_z(value == 0);
-            self.registers.set_flag_c(carry);
-            self.registers.set_flag_h(false);
-            self.registers.set_flag_n(false);
+        self.registers.set_flag_z(value == 0);
+        self.registers.set_flag_c(carry);
+        self.registers.set_flag_h(false);
+        self.registers.set_flag_n(false);
 
-            memory.write_byte(address, value);
-        }
+        self.write(address, value);
 
         self.pc_to_increment = 2;
         self.last_instruction_ccycles = 16;
@@ -1915,19 +2283,13 @@ impl Cpu {
     }
 
     fn rl_mhl(&mut self) {
-        let mut value;
-        let new_carry;
         let address = self.registers.read_word(&WordRegister::HL);
 
-        {
-            let mut memory = self.memory.write();
-
-            value = memory.read_byte(address);
-            new_carry = value & 0b10000000 == 0b10000000;
-            value = (value << 1) | (0x1 & (self.registers.is_flag_c() as Byte));
+        let mut value = self.read(address);
+        let new_carry = value & 0b10000000 == 0b10000000;
+        value = (value << 1) | (0x1 & (self.registers.is_flag_c() as Byte));
 
-            memory.write_byte(address, value);
-        }
+        self.write(address, value);
 
         self.registers.set_flag_z(value == 0);
         self.registers.set_flag_c(new_carry);
@@ -1976,20 +2338,13 @@ impl Cpu {
 
     fn rlc_mrr(&mut self, register: WordRegister) {
         let address = self.registers.read_word(&register);
-        let mut value;
-        let new_carry: bool;
+        let mut value = self.read(address);
+        let new_carry = value & 0b10000000 == 0b10000000;
 
-        {
-            let mut memory = self.memory.write();
-
-            value = memory.read_byte(address);
-            new_carry = value & 0b10000000 == 0b10000000;
-
-            value <<= 1;
-            value |= new_carry as Byte;
+        value <<= 1;
+        value |= new_carry as Byte;
 
-            memory.write_byte(address, value);
-        }
+        self.write(address, value);
 
         self.registers.set_flag_z(value == 0);
         self.registers.set_flag_n(false);
@@ -2039,7 +2394,7 @@ impl Cpu {
 
     fn rrc_mhl(&mut self) {
         let address = self.registers.read_word(&WordRegister::HL);
-        let mut value = { self.memory.read().read_byte(address) };
+        let mut value = self.read(address);
 
         let new_carry: bool = value & 0x1 == 0x1;
 
@@ -2051,9 +2406,7 @@ impl Cpu {
         self.registers.set_flag_h(false);
         self.registers.set_flag_n(false);
 
-        {
-            self.memory.write().write_byte(address, value)
-        }
+        self.write(address, value);
 
         self.pc_to_increment = 2;
         self.last_instruction_ccycles = 16;
@@ -2078,18 +2431,12 @@ impl Cpu {
 
     fn srl_mhl(&mut self) {
         let address = self.registers.read_word(&WordRegister::HL);
-        let mut value;
-        let carry: bool;
+        let mut value = self.read(address);
 
-        {
-            let mut memory = self.memory.write();
-            value = memory.read_byte(address);
+        let carry = value & 0x1 == 1;
+        value = (value >> 1) & 0b01111111;
 
-            carry = value & 0x1 == 1;
-            value = (value >> 1) & 0b01111111;
-
-            memory.write_byte(address, value);
-        }
+        self.write(address, value);
 
         self.registers.set_flag_z(value == 0);
         self.registers.set_flag_c(carry);
@@ -2120,18 +2467,12 @@ impl Cpu {
 
     fn sla_mhl(&mut self) {
         let address = self.registers.read_word(&WordRegister::HL);
-        let mut value;
-        let carry: bool;
+        let mut value = self.read(address);
+        let carry = value & 0b10000000 == 0b10000000;
 
-        {
-            let mut memory = self.memory.write();
-            value = memory.read_byte(address);
-            carry = value & 0b10000000 == 0b10000000;
-
-            value <<= 1;
+        value <<= 1;
 
-            memory.write_byte(address, value);
-        }
+        self.write(address, value);
 
         self.registers.set_flag_z(value == 0);
         self.registers.set_flag_n(false);
@@ -2163,21 +2504,15 @@ impl Cpu {
 
     fn sra_mhl(&mut self) {
         let address = self.registers.read_word(&WordRegister::HL);
-        let mut value;
-        let carry;
-
-        {
-            let mut memory = self.memory.write();
-            value = memory.read_byte(address);
+        let mut value = self.read(address);
 
-            let msb = value & 0b10000000;
-            carry = value & 0x1 == 0x1;
+        let msb = value & 0b10000000;
+        let carry = value & 0x1 == 0x1;
 
-            value >>= 1;
-            value |= msb;
+        value >>= 1;
+        value |= msb;
 
-            memory.write_byte(address, value);
-        }
+        self.write(address, value);
 
         self.registers.set_flag_z(value == 0);
         self.registers.set_flag_n(false);
@@ -2200,14 +2535,11 @@ impl Cpu {
     fn swap_mhl(&mut self) {
         let address = self.registers.read_word(&WordRegister::HL);
 
-        {
-            let mut memory = self.memory.write();
-            let mut value = memory.read_byte(address);
+        let mut value = self.read(address);
 
-            value = self.alu.swap_n(&mut self.registers, value);
+        value = self.alu.swap_n(&mut self.registers, value);
 
-            memory.write_byte(address, value);
-        }
+        self.write(address, value);
 
         self.pc_to_increment = 2;
         self.last_instruction_ccycles = 16;
@@ -2226,13 +2558,9 @@ impl Cpu {
     fn res_v_mhl(&mut self, bit: u8) {
         let pos = self.registers.read_word(&WordRegister::HL);
 
-        {
-            let mut memory = self.memory.write();
-
-            let mut value = memory.read_byte(pos);
-            value &= !(0x1 << bit);
-            memory.write_byte(pos, value);
-        }
+        let mut value = self.read(pos);
+        value &= !(0x1 << bit);
+        self.write(pos, value);
 
         self.pc_to_increment = 2;
         self.last_instruction_ccycles = 16;
@@ -2249,13 +2577,9 @@ impl Cpu {
     }
 
     fn set_v_mhl(&mut self, bit: u8) {
-        {
-            let mut memory = self.memory.write();
-
-            let mut value = memory.read_byte(self.registers.read_word(&WordRegister::HL));
-            value |= 0x1 << bit;
-            memory.write_byte(self.registers.read_word(&WordRegister::HL), value);
-        }
+        let mut value = self.read(self.registers.read_word(&WordRegister::HL));
+        value |= 0x1 << bit;
+        self.write(self.registers.read_word(&WordRegister::HL), value);
 
         self.pc_to_increment = 2;
         self.last_instruction_ccycles = 16;
@@ -2295,26 +2619,34 @@ impl Cpu {
 
     // --- INTERNAL --------------------------------------------------------------------------------
 
+    /**
+     * Takes three M-cycles: SP is decremented first, then the high byte is written before the
+     * low one.
+     */
     fn push_vv(&mut self, value: Word) {
-        let new_sp = self.registers.sp.wrapping_sub(2);
+        let sp = self.registers.sp;
+        let bytes = word_to_two_bytes(value);
 
-        {
-            let mut memory = self.memory.write();
-            memory.write_word(new_sp, value);
-        }
+        self.inc_dec(sp, false);
+        self.tick();
+
+        self.write(sp.wrapping_sub(1), bytes.0);
+        self.write(sp.wrapping_sub(2), bytes.1);
 
-        self.registers.sp = new_sp;
+        self.registers.sp = sp.wrapping_sub(2);
     }
 
     fn pop_vv(&mut self) -> Word {
-        let value = {
-            let memory = self.memory.read();
-            memory.read_word(self.registers.sp)
-        };
+        let sp = self.registers.sp;
 
-        self.registers.sp = self.registers.sp.wrapping_add(2);
+        self.inc_dec(sp, true);
+        let low = self.read(sp);
+        self.inc_dec(sp.wrapping_add(1), true);
+        let high = self.read(sp.wrapping_add(1));
 
-        value
+        self.registers.sp = sp.wrapping_add(2);
+
+        two_bytes_to_word(high, low)
     }
 
     fn rst_v(&mut self, value: Byte) {
@@ -2326,10 +2658,35 @@ impl Cpu {
         self.last_instruction_ccycles = 16;
     }
 
+    /**
+     * Clears the bit of the interrupt being served in IF
+     */
+    fn acknowledge_interrupt(&mut self, mask: Byte) {
+        let mut memory = self.memory.write();
+        let flags = memory.read_byte(Address::IF_INTERRUPT_FLAG);
+
+        memory.write_byte(Address::IF_INTERRUPT_FLAG, flags & !mask);
+    }
+
+    /**
+     * Dispatching takes five M-cycles: one waiting, the three of the push, one to load the handler
+     * address into PC.
+     */
     fn interrupt_vv(&mut self, new_address: Word) {
+        let return_address = self.registers.pc;
+
         self.ime = false;
-        self.push_vv(self.registers.pc);
+        self.tick();
+        self.push_vv(return_address);
         self.registers.pc = new_address;
+        self.tick();
+
+        self.call_stack.call(Frame {
+            function: Location::new(0, new_address),
+            return_address,
+            sp: self.registers.sp,
+            interrupt: true,
+        });
     }
 
     fn bit_v_r(&mut self, bit: u8, register: ByteRegister) {
@@ -2349,11 +2706,7 @@ impl Cpu {
     fn bit_v_mhl(&mut self, bit: u8) {
         let mask = 1u8 << bit;
 
-        let value = {
-            self.memory
-                .read()
-                .read_byte(self.registers.read_word(&WordRegister::HL))
-        };
+        let value = self.read(self.registers.read_word(&WordRegister::HL));
 
         let zero = value & mask != mask;
 
@@ -2368,10 +2721,12 @@ impl Cpu {
     // --- INTERRUPTS ----------------------------------------------------------------------------------
 
     pub fn vblank_interrupt(&mut self) {
+        if self.locked {
+            return;
+        }
+
         if self.ime {
-            {
-                self.io_registers.write().interrupt_flag.set_vblank(false);
-            }
+            self.acknowledge_interrupt(0b1);
 
             self.interrupt_vv(0x40)
         }
@@ -2380,32 +2735,26 @@ impl Cpu {
     }
 
     pub fn lcd_stat_interrupt(&mut self) {
-        if self.ime {
-            let lcd_enabled;
-
-            {
-                let mut io_registers = self.io_registers.write();
-                io_registers.interrupt_flag.set_lcd_stat(false);
+        if self.locked {
+            return;
+        }
 
-                lcd_enabled = io_registers.lcdc.lcd_control_operation;
-            }
+        if self.ime {
+            self.acknowledge_interrupt(0b10);
 
-            if lcd_enabled {
-                self.interrupt_vv(0x48)
-            }
+            self.interrupt_vv(0x48)
         }
 
         self.unhalt()
     }
 
     pub fn timer_overflow_interrupt(&mut self) {
+        if self.locked {
+            return;
+        }
+
         if self.ime {
-            {
-                self.io_registers
-                    .write()
-                    .interrupt_flag
-                    .set_timer_overflow(false);
-            }
+            self.acknowledge_interrupt(0b100);
 
             self.interrupt_vv(0x50)
         }
@@ -2414,13 +2763,12 @@ impl Cpu {
     }
 
     pub fn p10_p13_transition_interrupt(&mut self) {
+        if self.locked {
+            return;
+        }
+
         if self.ime {
-            {
-                self.io_registers
-                    .write()
-                    .interrupt_flag
-                    .set_p10_p13_transition(false);
-            }
+            self.acknowledge_interrupt(0b10000);
 
             self.interrupt_vv(0x60)
         }
@@ -2448,13 +2796,39 @@ impl Cpu {
         self.last_instruction_ccycles = 4;
     }
 
+    /**
+     * Switches the CPU speed when it was armed through KEY1 on CGB.
+     */
     fn stop(&mut self) {
-        // TODO
+        self.catch_up();
+
+        if self.memory.write().switch_speed() {
+            self.double_speed = !self.double_speed;
+            // Deadlines are now a different number of CPU cycles away
+            self.ccycles_to_event = 0;
+            self.stall(Self::SPEED_SWITCH_CCYCLES);
+        }
+
+        // TODO: low power mode until a button is pressed
 
         self.pc_to_increment = 2;
         self.last_instruction_ccycles = 4;
     }
 
+    // --- LOCK ------------------------------------------------------------------------------------
+
+    /**
+     * Illegal opcodes hang the CPU until the console is reset.
+     */
+    fn lock(&mut self, instruction: Byte) {
+        self.locked = true;
+
+        self.lock_reason = Some(DebugReason::IllegalOpcode(self.registers.pc, instruction));
+
+        self.pc_to_increment = 0;
+        self.last_instruction_ccycles = 4;
+    }
+
     // --- HALT ------------------------------------------------------------------------------------
 
     fn unhalt(&mut self) {
@@ -2483,9 +2857,16 @@ mod test {
 
     use parking_lot::RwLock;
 
-    use crate::Memory;
+    use crate::cartridge::Cartridge;
+    use crate::cartridge::cartridge_header::CartridgeHeader;
+    use crate::cartridge::cartridge_memory_sector::CartridgeMemorySector;
     use crate::cpu::Cpu;
-    use crate::cpu::registers::{ByteRegister, WordRegister};
+    use crate::cpu::registers::{ByteRegister, CpuRegisters, WordRegister};
+    use crate::debug::code_data_log::CodeDataLog;
+    use crate::disassembler::RomBankView;
+    use crate::io::registers::IORegisters;
+    use crate::memory::Memory;
+    use crate::model::Model;
 
     #[test_case(0x0000, 0x0001)]
     #[test_case(0xFFFF, 0x0000)]
@@ -2564,11 +2945,145 @@ mod test {
         }
     }
 
+    #[test_case(0xD3)]
+    #[test_case(0xDB)]
+    #[test_case(0xDD)]
+    #[test_case(0xE3)]
+    #[test_case(0xE4)]
+    #[test_case(0xEB)]
+    #[test_case(0xEC)]
+    #[test_case(0xED)]
+    #[test_case(0xF4)]
+    #[test_case(0xFC)]
+    #[test_case(0xFD)]
+    fn test_illegal_opcode_locks_cpu(opcode: Byte) {
+        let mut cpu = create_empty_cpu();
+        cpu.memory.write().write_byte(0xC000, opcode);
+        cpu.registers.pc = 0xC000;
+
+        assert_eq!(cpu.step(false, false), 4);
+        assert!(cpu.locked);
+        assert_eq!(cpu.registers.pc, 0xC000);
+
+        assert_eq!(cpu.step(false, false), 4);
+        assert_eq!(cpu.registers.pc, 0xC000);
+    }
+
+    #[test]
+    fn test_locked_cpu_ignores_interrupts() {
+        let mut cpu = create_empty_cpu();
+        cpu.memory.write().write_byte(0xC000, 0xD3);
+        cpu.registers.pc = 0xC000;
+        cpu.ime = true;
+
+        cpu.step(false, false);
+        cpu.vblank_interrupt();
+
+        assert_eq!(cpu.registers.pc, 0xC000);
+        assert!(cpu.locked);
+    }
+
+    #[test]
+    fn test_reset_goes_back_to_power_on_state() {
+        let mut cpu = create_empty_cpu();
+        cpu.load_symbols(SymbolTable::parse("00:0150 Main\n"));
+        cpu.memory.write().write_byte(0xC000, 0xD3);
+        cpu.registers.pc = 0xC000;
+        cpu.registers.sp = 0xC100;
+        cpu.ime = true;
+        cpu.step(false, false);
+
+        cpu.reset(CpuRegisters::default());
+
+        assert!(!cpu.locked);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.registers.pc, 0x0100);
+        assert_eq!(cpu.registers.sp, 0xFFFE);
+        assert_eq!(cpu.registers.a, 0x01);
+        assert_eq!(cpu.symbols.lookup(0, 0x0150), Some("Main"));
+    }
+
+    #[test]
+    fn test_profiler_follows_calls_and_returns() {
+        let mut cpu = create_empty_cpu();
+        let profiler = Arc::new(Mutex::new(Profiler::default()));
+        cpu.set_profiler(profiler.clone());
+
+        // CALL $C010; CALL Z,$C010 (not taken); NOP; ...; $C010: RET
+        for (offset, value) in [0xCD, 0x10, 0xC0, 0xCC, 0x10, 0xC0, 0x00]
+            .iter()
+            .enumerate()
+        {
+            cpu.memory
+                .write()
+                .write_byte(0xC000 + offset as Word, *value);
+        }
+        cpu.memory.write().write_byte(0xC010, 0xC9);
+        cpu.registers.pc = 0xC000;
+        cpu.registers.set_flag_z(false);
+
+        for _ in 0..4 {
+            cpu.step(false, false);
+        }
+
+        let mut folded = Vec::new();
+        profiler
+            .lock()
+            .write_folded(&mut folded, &SymbolTable::default())
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "[root] 40\n[root];C010 16\n"
+        );
+    }
+
+    #[test]
+    fn test_instruction_lengths_match_disassembler() {
+        for (opcode, length) in INSTRUCTION_LENGTHS.iter().enumerate() {
+            let bytes = [opcode as Byte, 0, 0];
+            let instruction = Disassembler::new().disassemble(&RomBankView::new(&bytes, 1), 0, 1);
+
+            assert_eq!(*length as Word, instruction.length(), "Opcode {opcode:02X}");
+        }
+    }
+
+    #[test]
+    fn test_logs_executed_instructions_and_data_reads() {
+        let mut data = vec![0; 0x8000];
+        // LD A,[$0200]; NOP
+        data[0x100..0x104].copy_from_slice(&[0xFA, 0x00, 0x02, 0x00]);
+
+        let header = CartridgeHeader::new_from_data(&data);
+        let mut memory = Memory::new(
+            Arc::new(RwLock::new(IORegisters::default())),
+            Cartridge::new(CartridgeMemorySector::new_from_data(data), header),
+            None,
+            Model::Dmg,
+        );
+        memory.enable_code_data_log(CodeDataLog::new(0x8000));
+
+        let memory = Arc::new(RwLock::new(memory));
+        let mut cpu = Cpu::new(memory.clone(), CpuRegisters::default());
+        cpu.step(false, false);
+        cpu.step(false, false);
+
+        let file = assert_fs::NamedTempFile::new("rom.cdl").unwrap();
+        memory.read().save_code_data_log(file.path()).unwrap();
+        let log = CodeDataLog::load_or_new(file.path(), 0x8000);
+
+        assert_eq!(log.flags(0x100), CodeDataLog::CODE | CodeDataLog::OPCODE);
+        assert_eq!(log.flags(0x101), CodeDataLog::CODE);
+        assert_eq!(log.flags(0x102), CodeDataLog::CODE);
+        assert_eq!(log.flags(0x103), CodeDataLog::CODE | CodeDataLog::OPCODE);
+        assert_eq!(log.flags(0x104), 0);
+        assert_eq!(log.flags(0x200), CodeDataLog::DATA);
+    }
+
     fn create_empty_cpu() -> Cpu {
         Cpu::new(
             Arc::new(RwLock::new(Memory::default())),
-            Arc::new(RwLock::new(IORegisters::default())),
-            false,
+            CpuRegisters::default(),
         )
     }
 }
@@ -1,8 +1,12 @@
 use crate::bus::address::Address;
+use crate::cartridge::cartridge_header::CartridgeHeader;
+use crate::debug::Debuggable;
+use crate::model::Model;
 use crate::utils::math::{two_bytes_to_word, word_to_two_bytes};
 use crate::{Byte, Word};
+use std::collections::BTreeMap;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ByteRegister {
     A,
     B,
@@ -14,7 +18,7 @@ pub enum ByteRegister {
     L,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum WordRegister {
     AF,
     BC,
@@ -24,7 +28,7 @@ pub enum WordRegister {
     SP,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CpuRegisters {
     pub a: Byte,
     f: Byte,
@@ -39,13 +43,62 @@ pub struct CpuRegisters {
 }
 
 impl CpuRegisters {
-    pub fn new(bootstrap: bool) -> Self {
-        let mut cpu_registers = Self::default();
-
+    pub fn new(bootstrap: bool, model: Model, header: &CartridgeHeader) -> Self {
         if bootstrap {
-            cpu_registers.pc = Address::BOOTSTRAP_ROM_START;
+            return Self {
+                pc: Address::BOOTSTRAP_ROM_START,
+                ..Self::default()
+            };
         }
 
+        Self::post_boot(model, header)
+    }
+
+    /**
+     * Registers as the boot ROM of each model leaves them. Some of them depend on the header:
+     * DMG and MGB set H and C unless the header checksum is 0, and the CGB boot ROM in DMG mode
+     * leaves the title checksum in B for Nintendo licensed cartridges.
+     */
+    pub fn post_boot(model: Model, header: &CartridgeHeader) -> Self {
+        let checksum_flags = if header.header_checksum == 0 {
+            0x80
+        } else {
+            0xB0
+        };
+
+        let (a, f, bc, de, hl) = match model {
+            Model::Dmg0 => (0x01, 0x00, 0xFF13, 0x00C1, 0x8403),
+            Model::Dmg => (0x01, checksum_flags, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFF, checksum_flags, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => (0x01, 0x00, 0x0014, 0x0000, 0xC060),
+            Model::Sgb2 => (0xFF, 0x00, 0x0014, 0x0000, 0xC060),
+            Model::CgbDmgMode => {
+                let b = if header.licensed_by_nintendo {
+                    header.title_checksum
+                } else {
+                    0x00
+                };
+                let hl = if b == 0x43 || b == 0x58 {
+                    0x991A
+                } else {
+                    0x007C
+                };
+
+                (0x11, 0x80, (b as Word) << 8, 0x0008, hl)
+            }
+            Model::Cgb => (0x11, 0x80, 0x0000, 0xFF56, 0x000D),
+        };
+
+        let mut cpu_registers = Self {
+            a,
+            f,
+            ..Self::default()
+        };
+
+        cpu_registers.write_word(&WordRegister::BC, bc);
+        cpu_registers.write_word(&WordRegister::DE, de);
+        cpu_registers.write_word(&WordRegister::HL, hl);
+
         cpu_registers
     }
 
@@ -161,6 +214,19 @@ impl CpuRegisters {
     }
 }
 
+impl Debuggable for CpuRegisters {
+    fn get_debug_values(&self) -> BTreeMap<&str, String> {
+        BTreeMap::from([
+            ("AF", format!("{:04X}", self.read_word(&WordRegister::AF))),
+            ("BC", format!("{:04X}", self.read_word(&WordRegister::BC))),
+            ("DE", format!("{:04X}", self.read_word(&WordRegister::DE))),
+            ("HL", format!("{:04X}", self.read_word(&WordRegister::HL))),
+            ("SP", format!("{:04X}", self.sp)),
+            ("PC", format!("{:04X}", self.pc)),
+        ])
+    }
+}
+
 impl Default for CpuRegisters {
     fn default() -> Self {
         Self {
@@ -177,3 +243,56 @@ impl Default for CpuRegisters {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn create_header(setup: impl FnOnce(&mut [Byte])) -> CartridgeHeader {
+        let mut data = vec![0; 0x150];
+        setup(&mut data);
+
+        CartridgeHeader::new_from_data(&data)
+    }
+
+    #[test_case(Model::Dmg0, 0x0100, 0xFF13, 0x00C1, 0x8403)]
+    #[test_case(Model::Dmg, 0x01B0, 0x0013, 0x00D8, 0x014D)]
+    #[test_case(Model::Mgb, 0xFFB0, 0x0013, 0x00D8, 0x014D)]
+    #[test_case(Model::Sgb, 0x0100, 0x0014, 0x0000, 0xC060)]
+    #[test_case(Model::Sgb2, 0xFF00, 0x0014, 0x0000, 0xC060)]
+    #[test_case(Model::CgbDmgMode, 0x1180, 0x0000, 0x0008, 0x007C)]
+    #[test_case(Model::Cgb, 0x1180, 0x0000, 0xFF56, 0x000D)]
+    fn test_post_boot_registers(model: Model, af: Word, bc: Word, de: Word, hl: Word) {
+        let header = create_header(|data| data[0x14D] = 0x66);
+
+        let registers = CpuRegisters::new(false, model, &header);
+
+        assert_eq!(registers.read_word(&WordRegister::AF), af);
+        assert_eq!(registers.read_word(&WordRegister::BC), bc);
+        assert_eq!(registers.read_word(&WordRegister::DE), de);
+        assert_eq!(registers.read_word(&WordRegister::HL), hl);
+        assert_eq!(registers.sp, 0xFFFE);
+        assert_eq!(registers.pc, 0x0100);
+    }
+
+    #[test]
+    fn test_dmg_flags_depend_on_header_checksum() {
+        let registers = CpuRegisters::post_boot(Model::Dmg, &create_header(|_| {}));
+
+        assert_eq!(registers.read_byte(&ByteRegister::F), 0x80);
+    }
+
+    #[test]
+    fn test_cgb_in_dmg_mode_sums_title_of_nintendo_cartridges() {
+        let header = create_header(|data| {
+            data[0x134] = 0x58;
+            data[0x14B] = 0x01;
+        });
+
+        let registers = CpuRegisters::post_boot(Model::CgbDmgMode, &header);
+
+        assert_eq!(registers.read_word(&WordRegister::BC), 0x5800);
+        assert_eq!(registers.read_word(&WordRegister::HL), 0x991A);
+    }
+}
@@ -0,0 +1,364 @@
+//! Conformance harness for the SingleStepTests `sm83` vectors. Point `SM83_TESTS_DIR` to the
+//! directory holding the per-opcode JSON files (`00.json` ... `cb ff.json`) to run all of them.
+
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+use crate::cpu::registers::{ByteRegister, CpuRegisters};
+use crate::memory::memory_sector::{ReadMemory, WriteMemory};
+use crate::{Byte, Word};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+const TESTS_DIR_VARIABLE: &str = "SM83_TESTS_DIR";
+const MAX_REPORTED_FAILURES: usize = 50;
+
+const BYTE_REGISTERS: [(&str, ByteRegister); 8] = [
+    ("A", ByteRegister::A),
+    ("F", ByteRegister::F),
+    ("B", ByteRegister::B),
+    ("C", ByteRegister::C),
+    ("D", ByteRegister::D),
+    ("E", ByteRegister::E),
+    ("H", ByteRegister::H),
+    ("L", ByteRegister::L),
+];
+
+#[derive(Deserialize)]
+struct TestVector {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    expected: State,
+    cycles: Vec<Option<Cycle>>,
+}
+
+#[derive(Deserialize)]
+struct State {
+    pc: Word,
+    sp: Word,
+    a: Byte,
+    f: Byte,
+    b: Byte,
+    c: Byte,
+    d: Byte,
+    e: Byte,
+    h: Byte,
+    l: Byte,
+    #[serde(default)]
+    ime: Byte,
+    ram: Vec<(Word, Byte)>,
+}
+
+impl State {
+    fn byte_register(&self, register: &ByteRegister) -> Byte {
+        match register {
+            ByteRegister::A => self.a,
+            ByteRegister::F => self.f,
+            ByteRegister::B => self.b,
+            ByteRegister::C => self.c,
+            ByteRegister::D => self.d,
+            ByteRegister::E => self.e,
+            ByteRegister::H => self.h,
+            ByteRegister::L => self.l,
+        }
+    }
+}
+
+/// One M-cycle: address, data and pin activity (`r-m`, `-wm`, `---`...).
+#[derive(Deserialize)]
+struct Cycle(Option<Word>, Option<Byte>, String);
+
+#[derive(Debug, PartialEq, Eq)]
+enum Access {
+    Read(Word, Byte),
+    Write(Word, Byte),
+}
+
+/// 64 KiB of plain RAM recording every access the CPU makes, with the M-cycle it happens in.
+struct FlatBus {
+    data: Vec<Byte>,
+    m_cycle: usize,
+    accesses: Mutex<Vec<(usize, Access)>>,
+}
+
+impl FlatBus {
+    fn new(ram: &[(Word, Byte)]) -> Self {
+        let mut data = vec![0; 0x10000];
+
+        for (position, value) in ram {
+            data[*position as usize] = *value;
+        }
+
+        Self {
+            data,
+            m_cycle: 0,
+            accesses: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl ReadMemory for FlatBus {
+    fn read_byte(&self, position: Word) -> Byte {
+        let value = self.data[position as usize];
+        self.accesses
+            .lock()
+            .push((self.m_cycle, Access::Read(position, value)));
+
+        value
+    }
+}
+
+impl WriteMemory for FlatBus {
+    fn write_byte(&mut self, position: Word, value: Byte) {
+        self.data[position as usize] = value;
+        self.accesses
+            .lock()
+            .push((self.m_cycle, Access::Write(position, value)));
+    }
+}
+
+impl Bus for FlatBus {
+    fn peek_byte(&self, position: Word) -> Byte {
+        self.data[position as usize]
+    }
+
+    // Called back every M-cycle, to tell which one each access happens in
+    fn tick(&mut self, cycles: u64) -> u64 {
+        self.m_cycle += cycles as usize / 4;
+
+        0
+    }
+}
+
+/**
+ * Runs a single instruction and compares registers, memory and bus activity, the latter M-cycle
+ * by M-cycle: each read or write must happen in the cycle the vector has it in. Internal cycles
+ * only put an address on the bus, so they are expected to access nothing.
+ */
+fn run_vector(vector: &TestVector) -> Result<(), String> {
+    let bus = Arc::new(RwLock::new(FlatBus::new(&vector.initial.ram)));
+
+    let mut registers = CpuRegisters::default();
+    for (_, register) in BYTE_REGISTERS.iter() {
+        registers.write_byte(register, vector.initial.byte_register(register));
+    }
+    registers.pc = vector.initial.pc;
+    registers.sp = vector.initial.sp;
+
+    let mut cpu = Cpu::new(bus.clone(), registers);
+    cpu.ime = vector.initial.ime == 1;
+
+    let cycles = std::panic::catch_unwind(AssertUnwindSafe(|| cpu.step(false, false)))
+        .map_err(|_| "panicked".to_string())?;
+
+    let mut errors = Vec::new();
+
+    for (name, register) in BYTE_REGISTERS.iter() {
+        let (actual, expected) = (
+            cpu.registers.read_byte(register),
+            vector.expected.byte_register(register),
+        );
+
+        if actual != expected {
+            errors.push(format!("{name}={actual:02X} expected {expected:02X}"));
+        }
+    }
+
+    for (name, actual, expected) in [
+        ("PC", cpu.registers.pc, vector.expected.pc),
+        ("SP", cpu.registers.sp, vector.expected.sp),
+    ] {
+        if actual != expected {
+            errors.push(format!("{name}={actual:04X} expected {expected:04X}"));
+        }
+    }
+
+    if cpu.ime != (vector.expected.ime == 1) {
+        errors.push(format!(
+            "IME={} expected {}",
+            cpu.ime as u8, vector.expected.ime
+        ));
+    }
+
+    let bus = bus.read();
+
+    for (position, expected) in vector.expected.ram.iter() {
+        let actual = bus.data[*position as usize];
+
+        if actual != *expected {
+            errors.push(format!(
+                "[{position:04X}]={actual:02X} expected {expected:02X}"
+            ));
+        }
+    }
+
+    let expected_cycles = vector.cycles.len() * 4;
+    if cycles as usize != expected_cycles {
+        errors.push(format!("{cycles} cycles, expected {expected_cycles}"));
+    }
+
+    let accesses = bus.accesses.lock();
+    let last_cycle = accesses
+        .iter()
+        .map(|(cycle, _)| cycle + 1)
+        .max()
+        .unwrap_or(0)
+        .max(vector.cycles.len());
+
+    for cycle in 0..last_cycle {
+        let actual: Vec<&Access> = accesses
+            .iter()
+            .filter(|(access_cycle, _)| *access_cycle == cycle)
+            .map(|(_, access)| access)
+            .collect();
+        let expected: Vec<Access> = vector
+            .cycles
+            .get(cycle)
+            .and_then(Option::as_ref)
+            .and_then(|Cycle(position, value, pins)| {
+                let (position, value) = ((*position)?, (*value)?);
+
+                if pins.contains('r') {
+                    Some(Access::Read(position, value))
+                } else if pins.contains('w') {
+                    Some(Access::Write(position, value))
+                } else {
+                    None
+                }
+            })
+            .into_iter()
+            .collect();
+
+        if !actual.iter().copied().eq(expected.iter()) {
+            errors.push(format!(
+                "M-cycle {cycle}: {actual:X?}, expected {expected:X?}"
+            ));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join(", "))
+    }
+}
+
+fn run_vectors(vectors: &[TestVector]) -> Vec<String> {
+    vectors
+        .iter()
+        .filter_map(|vector| {
+            run_vector(vector)
+                .err()
+                .map(|reason| format!("{}: {reason}", vector.name))
+        })
+        .collect()
+}
+
+#[test]
+fn test_harness_checks_registers_memory_and_bus() {
+    // LD [HL],A with HL=C000
+    let vectors: Vec<TestVector> = serde_json::from_str(
+        r#"[{
+            "name": "77 0000",
+            "initial": {"pc": 256, "sp": 65534, "a": 66, "f": 0, "b": 0, "c": 0, "d": 0,
+                "e": 0, "h": 192, "l": 0, "ime": 0, "ie": 0, "ram": [[256, 119]]},
+            "final": {"pc": 257, "sp": 65534, "a": 66, "f": 0, "b": 0, "c": 0, "d": 0,
+                "e": 0, "h": 192, "l": 0, "ime": 0, "ie": 0, "ram": [[256, 119], [49152, 66]]},
+            "cycles": [[256, 119, "r-m"], [49152, 66, "-wm"]]
+        }]"#,
+    )
+    .unwrap();
+
+    assert_eq!(run_vectors(&vectors), Vec::<String>::new());
+}
+
+#[test]
+fn test_harness_reports_mismatches() {
+    // NOP expected to load A, which it does not
+    let vectors: Vec<TestVector> = serde_json::from_str(
+        r#"[{
+            "name": "00 0000",
+            "initial": {"pc": 256, "sp": 65534, "a": 0, "f": 0, "b": 0, "c": 0, "d": 0,
+                "e": 0, "h": 0, "l": 0, "ram": [[256, 0]]},
+            "final": {"pc": 257, "sp": 65534, "a": 1, "f": 0, "b": 0, "c": 0, "d": 0,
+                "e": 0, "h": 0, "l": 0, "ram": [[256, 0]]},
+            "cycles": [[256, 0, "r-m"], null]
+        }]"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        run_vectors(&vectors),
+        vec!["00 0000: A=00 expected 01, 4 cycles, expected 8".to_string()]
+    );
+}
+
+#[test]
+fn test_harness_reports_accesses_in_the_wrong_m_cycle() {
+    // LD [$C000],A, expected to write before reading the high byte of the address
+    let vectors: Vec<TestVector> = serde_json::from_str(
+        r#"[{
+            "name": "ea 0000",
+            "initial": {"pc": 256, "sp": 65534, "a": 66, "f": 0, "b": 0, "c": 0, "d": 0,
+                "e": 0, "h": 0, "l": 0, "ram": [[256, 234], [257, 0], [258, 192]]},
+            "final": {"pc": 259, "sp": 65534, "a": 66, "f": 0, "b": 0, "c": 0, "d": 0,
+                "e": 0, "h": 0, "l": 0, "ram": [[49152, 66]]},
+            "cycles": [[256, 234, "r-m"], [257, 0, "r-m"], [49152, 66, "-wm"],
+                [258, 192, "r-m"]]
+        }]"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        run_vectors(&vectors),
+        vec![
+            "ea 0000: M-cycle 2: [Read(102, C0)], expected [Write(C000, 42)], \
+            M-cycle 3: [Write(C000, 42)], expected [Read(102, C0)]"
+                .to_string()
+        ]
+    );
+}
+
+#[test]
+fn test_sm83_vectors() {
+    let Ok(directory) = std::env::var(TESTS_DIR_VARIABLE) else {
+        println!("{TESTS_DIR_VARIABLE} is not set, skipping the sm83 vectors");
+        return;
+    };
+
+    let mut paths: Vec<_> = std::fs::read_dir(&directory)
+        .expect("Could not read the sm83 tests directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    paths.sort();
+
+    let mut total = 0;
+    let mut failures = Vec::new();
+
+    for path in paths {
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let vectors: Vec<TestVector> = serde_json::from_str(&contents)
+            .unwrap_or_else(|e| panic!("Could not parse {}: {e}", path.display()));
+
+        total += vectors.len();
+        failures.extend(run_vectors(&vectors));
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {total} vectors failed:\n{}",
+        failures.len(),
+        failures
+            .iter()
+            .take(MAX_REPORTED_FAILURES)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+}
@@ -0,0 +1,649 @@
+use crate::cpu::registers::{ByteRegister, CpuRegisters, WordRegister};
+use crate::{Byte, Word};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A read or write of the CPU, kept for watchpoints to check once its instruction is over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub address: Word,
+    pub value: Byte,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BreakpointKind {
+    // Any bank when none is given
+    Execute {
+        bank: Option<u16>,
+        address: Word,
+    },
+    Watch {
+        kind: AccessKind,
+        start: Word,
+        end: Word,
+    },
+}
+
+/// Value a condition compares: a register, memory pointed by another operand, a number, or the
+/// value of the access that hit a watchpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    ByteRegister(ByteRegister),
+    WordRegister(WordRegister),
+    Memory(Box<Operand>),
+    Value(Word),
+    Accessed,
+}
+
+impl Operand {
+    /// A register, or a hexadecimal address for anything else, like `HL` or `C000`.
+    pub fn parse_location(value: &str) -> Result<Self, String> {
+        match value.parse() {
+            Ok(Operand::Value(_)) | Err(_) => Ok(Operand::Value(parse_address(value)?)),
+            Ok(operand) => Ok(operand),
+        }
+    }
+
+    pub fn evaluate(
+        &self,
+        registers: &CpuRegisters,
+        peek: &dyn Fn(Word) -> Byte,
+        value: Byte,
+    ) -> Word {
+        match self {
+            Operand::ByteRegister(register) => registers.read_byte(register) as Word,
+            Operand::WordRegister(register) => registers.read_word(register),
+            Operand::Memory(address) => peek(address.evaluate(registers, peek, value)) as Word,
+            Operand::Value(value) => *value,
+            Operand::Accessed => value as Word,
+        }
+    }
+}
+
+impl FromStr for Operand {
+    type Err = String;
+
+    /**
+     * Accepts register names, `value`, numbers (`$3C`, `0x3C` or decimal) and memory at a register
+     * or hexadecimal address, like `[HL]` or `[C000]`.
+     */
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            return Ok(Operand::Memory(Box::new(Self::parse_location(
+                address.trim(),
+            )?)));
+        }
+
+        let operand = match value.to_ascii_uppercase().as_str() {
+            "A" => Operand::ByteRegister(ByteRegister::A),
+            "B" => Operand::ByteRegister(ByteRegister::B),
+            "C" => Operand::ByteRegister(ByteRegister::C),
+            "D" => Operand::ByteRegister(ByteRegister::D),
+            "E" => Operand::ByteRegister(ByteRegister::E),
+            "F" => Operand::ByteRegister(ByteRegister::F),
+            "H" => Operand::ByteRegister(ByteRegister::H),
+            "L" => Operand::ByteRegister(ByteRegister::L),
+            "AF" => Operand::WordRegister(WordRegister::AF),
+            "BC" => Operand::WordRegister(WordRegister::BC),
+            "DE" => Operand::WordRegister(WordRegister::DE),
+            "HL" => Operand::WordRegister(WordRegister::HL),
+            "PC" => Operand::WordRegister(WordRegister::PC),
+            "SP" => Operand::WordRegister(WordRegister::SP),
+            "VALUE" => Operand::Accessed,
+            _ => Operand::Value(parse_number(value)?),
+        };
+
+        Ok(operand)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    // Two character operators first, so `<=` is not taken for `<`
+    const OPERATORS: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub left: Operand,
+    pub comparison: Comparison,
+    pub right: Operand,
+}
+
+impl Condition {
+    pub fn is_met(
+        &self,
+        registers: &CpuRegisters,
+        peek: &dyn Fn(Word) -> Byte,
+        value: Byte,
+    ) -> bool {
+        let left = self.left.evaluate(registers, peek, value);
+        let right = self.right.evaluate(registers, peek, value);
+
+        match self.comparison {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    /// Accepts `OPERAND OPERATOR OPERAND`, like `A == $3C` or `[HL] != 0`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (position, operator, comparison) = Comparison::OPERATORS
+            .iter()
+            .find_map(|(operator, comparison)| {
+                value
+                    .find(operator)
+                    .map(|position| (position, operator, *comparison))
+            })
+            .ok_or_else(|| format!("Missing comparison in condition \"{value}\""))?;
+
+        Ok(Condition {
+            left: value[..position].trim().parse()?,
+            comparison,
+            right: value[position + operator.len()..].trim().parse()?,
+        })
+    }
+}
+
+/**
+ * Breakpoint on the PC or watchpoint on memory accesses. It only stops emulation when its
+ * condition holds and it has been hit `hits_required` times; one-shots are removed then.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub kind: BreakpointKind,
+    pub condition: Option<Condition>,
+    pub hits_required: u32,
+    pub hits: u32,
+    pub one_shot: bool,
+    pub enabled: bool,
+    // Text it was created from, to list it back as the user wrote it
+    spec: String,
+}
+
+impl Breakpoint {
+    /// Counts a hit when the condition holds, returning whether emulation has to stop.
+    fn hit(&mut self, registers: &CpuRegisters, peek: &dyn Fn(Word) -> Byte, value: Byte) -> bool {
+        if !self.enabled
+            || self
+                .condition
+                .as_ref()
+                .is_some_and(|condition| !condition.is_met(registers, peek, value))
+        {
+            return false;
+        }
+
+        self.hits += 1;
+
+        self.hits >= self.hits_required
+    }
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{} {}", self.id, self.spec)?;
+
+        if !self.enabled {
+            write!(f, " (disabled)")?;
+        }
+
+        write!(f, " [{} hits]", self.hits)
+    }
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    /**
+     * Accepts `exec [BANK:]ADDR`, `read ADDR[-ADDR]` or `write ADDR[-ADDR]`, followed by any of
+     * `if CONDITION`, `hits N` and `once`. Addresses and banks are hexadecimal.
+     */
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let spec = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut tokens = spec.split(' ');
+
+        let kind = tokens.next().unwrap_or_default();
+        let location = tokens
+            .next()
+            .ok_or_else(|| format!("Missing address in breakpoint \"{spec}\""))?;
+
+        let kind = match kind {
+            "exec" => {
+                let (bank, address) = match location.split_once(':') {
+                    Some((bank, address)) => (
+                        Some(
+                            u16::from_str_radix(bank, 16)
+                                .map_err(|_| format!("Invalid ROM bank \"{bank}\""))?,
+                        ),
+                        address,
+                    ),
+                    None => (None, location),
+                };
+
+                BreakpointKind::Execute {
+                    bank,
+                    address: parse_address(address)?,
+                }
+            }
+            "read" | "write" => {
+                let (start, end) = location.split_once('-').unwrap_or((location, location));
+                let (start, end) = (parse_address(start)?, parse_address(end)?);
+
+                if start > end {
+                    return Err(format!("Invalid address range \"{location}\""));
+                }
+
+                let kind = match kind {
+                    "read" => AccessKind::Read,
+                    _ => AccessKind::Write,
+                };
+
+                BreakpointKind::Watch { kind, start, end }
+            }
+            _ => return Err(format!("Unknown breakpoint kind \"{kind}\"")),
+        };
+
+        let mut breakpoint = Breakpoint {
+            id: 0,
+            kind,
+            condition: None,
+            hits_required: 1,
+            hits: 0,
+            one_shot: false,
+            enabled: true,
+            spec: spec.clone(),
+        };
+
+        let options: Vec<&str> = tokens.collect();
+        let mut index = 0;
+
+        while index < options.len() {
+            match options[index] {
+                "if" => {
+                    let end = options[index + 1..]
+                        .iter()
+                        .position(|token| *token == "hits" || *token == "once")
+                        .map_or(options.len(), |position| index + 1 + position);
+
+                    breakpoint.condition = Some(options[index + 1..end].join(" ").parse()?);
+                    index = end;
+                }
+                "hits" => {
+                    let count = options.get(index + 1).copied().unwrap_or_default();
+
+                    breakpoint.hits_required = count
+                        .parse()
+                        .map_err(|_| format!("Invalid hit count \"{count}\""))?;
+                    index += 2;
+                }
+                "once" => {
+                    breakpoint.one_shot = true;
+                    index += 1;
+                }
+                option => return Err(format!("Unknown breakpoint option \"{option}\"")),
+            }
+        }
+
+        Ok(breakpoint)
+    }
+}
+
+/// A breakpoint that stopped emulation, with the access that triggered it for watchpoints.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BreakpointHit {
+    pub breakpoint: Breakpoint,
+    pub access: Option<MemoryAccess>,
+}
+
+impl Display for BreakpointHit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Breakpoint {}", self.breakpoint)?;
+
+        match self.access {
+            Some(MemoryAccess {
+                kind: AccessKind::Read,
+                address,
+                value,
+            }) => write!(f, ", read {value:02X} from {address:04X}"),
+            Some(MemoryAccess {
+                kind: AccessKind::Write,
+                address,
+                value,
+            }) => write!(f, ", wrote {value:02X} to {address:04X}"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    /// Reads one breakpoint per line, skipping empty lines and `#` comments.
+    pub fn load(path: &Path) -> Result<Vec<Breakpoint>, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|error| format!("Could not read {}: {error}", path.display()))?;
+
+        content
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index, line.split('#').next().unwrap_or_default().trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(index, line)| {
+                line.parse()
+                    .map_err(|error| format!("{}:{}: {error}", path.display(), index + 1))
+            })
+            .collect()
+    }
+
+    /// Returns the id given to the breakpoint.
+    pub fn add(&mut self, mut breakpoint: Breakpoint) -> usize {
+        self.next_id += 1;
+        breakpoint.id = self.next_id;
+        self.breakpoints.push(breakpoint);
+
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+
+        self.breakpoints.len() != count
+    }
+
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        match self.breakpoints.iter_mut().find(|b| b.id == id) {
+            Some(breakpoint) => {
+                breakpoint.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    /// Address ranges memory has to report accesses to, for the enabled watchpoints.
+    pub fn watched_ranges(&self) -> Vec<(AccessKind, Word, Word)> {
+        self.breakpoints
+            .iter()
+            .filter(|breakpoint| breakpoint.enabled)
+            .filter_map(|breakpoint| match breakpoint.kind {
+                BreakpointKind::Watch { kind, start, end } => Some((kind, start, end)),
+                BreakpointKind::Execute { .. } => None,
+            })
+            .collect()
+    }
+
+    /// Checks the breakpoints on the instruction about to run at `pc`.
+    pub fn check_execute(
+        &mut self,
+        pc: Word,
+        rom_bank: u16,
+        registers: &CpuRegisters,
+        peek: &dyn Fn(Word) -> Byte,
+    ) -> Option<BreakpointHit> {
+        let banked = (0x4000..0x8000).contains(&pc);
+
+        self.check(None, registers, peek, |kind| match kind {
+            BreakpointKind::Execute { bank, address } => {
+                *address == pc && (bank.is_none() || !banked || *bank == Some(rom_bank))
+            }
+            BreakpointKind::Watch { .. } => false,
+        })
+    }
+
+    /// Checks the watchpoints on the accesses of the last instruction, in order.
+    pub fn check_accesses(
+        &mut self,
+        accesses: &[MemoryAccess],
+        registers: &CpuRegisters,
+        peek: &dyn Fn(Word) -> Byte,
+    ) -> Option<BreakpointHit> {
+        accesses.iter().find_map(|access| {
+            self.check(Some(*access), registers, peek, |kind| match kind {
+                BreakpointKind::Watch { kind, start, end } => {
+                    *kind == access.kind && (*start..=*end).contains(&access.address)
+                }
+                BreakpointKind::Execute { .. } => false,
+            })
+        })
+    }
+
+    fn check(
+        &mut self,
+        access: Option<MemoryAccess>,
+        registers: &CpuRegisters,
+        peek: &dyn Fn(Word) -> Byte,
+        matches: impl Fn(&BreakpointKind) -> bool,
+    ) -> Option<BreakpointHit> {
+        let value = access.map(|access| access.value).unwrap_or_default();
+        let index = self.breakpoints.iter_mut().position(|breakpoint| {
+            matches(&breakpoint.kind) && breakpoint.hit(registers, peek, value)
+        })?;
+
+        let breakpoint = if self.breakpoints[index].one_shot {
+            self.breakpoints.remove(index)
+        } else {
+            self.breakpoints[index].clone()
+        };
+
+        Some(BreakpointHit { breakpoint, access })
+    }
+}
+
+fn parse_address(value: &str) -> Result<Word, String> {
+    let digits = value
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .trim_start_matches('$');
+
+    Word::from_str_radix(digits, 16).map_err(|_| format!("Invalid address \"{value}\""))
+}
+
+/// Hexadecimal with a `$` or `0x` prefix, decimal otherwise.
+pub fn parse_number(value: &str) -> Result<Word, String> {
+    let result = match value
+        .strip_prefix('$')
+        .or_else(|| value.strip_prefix("0x"))
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(digits) => Word::from_str_radix(digits, 16),
+        None => value.parse(),
+    };
+
+    result.map_err(|_| format!("Invalid value \"{value}\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn create_registers() -> CpuRegisters {
+        let mut registers = CpuRegisters::default();
+        registers.write_byte(&ByteRegister::A, 0x3C);
+        registers.write_word(&WordRegister::HL, 0xC000);
+
+        registers
+    }
+
+    fn peek(address: Word) -> Byte {
+        match address {
+            0xC000 => 0x12,
+            _ => 0x00,
+        }
+    }
+
+    #[test_case("exec 0150", BreakpointKind::Execute { bank: None, address: 0x0150 }; "pc")]
+    #[test_case("exec 1F:$4000", BreakpointKind::Execute { bank: Some(0x1F), address: 0x4000 }; "banked pc")]
+    #[test_case("read C000-C0FF", BreakpointKind::Watch { kind: AccessKind::Read, start: 0xC000, end: 0xC0FF }; "read range")]
+    #[test_case("write  FF40", BreakpointKind::Watch { kind: AccessKind::Write, start: 0xFF40, end: 0xFF40 }; "write")]
+    fn test_parse_kind(value: &str, expected: BreakpointKind) {
+        assert_eq!(value.parse::<Breakpoint>().unwrap().kind, expected);
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let breakpoint: Breakpoint = "write FF40 if value<$80 hits 3 once".parse().unwrap();
+
+        assert_eq!(
+            breakpoint.condition,
+            Some(Condition {
+                left: Operand::Accessed,
+                comparison: Comparison::Less,
+                right: Operand::Value(0x80),
+            })
+        );
+        assert_eq!(breakpoint.hits_required, 3);
+        assert!(breakpoint.one_shot);
+    }
+
+    #[test_case("jump 0150"; "unknown kind")]
+    #[test_case("exec"; "missing address")]
+    #[test_case("read C0FF-C000"; "empty range")]
+    #[test_case("exec 0150 if A"; "missing comparison")]
+    #[test_case("exec 0150 if Q == 1"; "unknown operand")]
+    #[test_case("exec 0150 hits x"; "invalid hit count")]
+    #[test_case("exec 0150 forever"; "unknown option")]
+    fn test_parse_errors(value: &str) {
+        assert!(value.parse::<Breakpoint>().is_err());
+    }
+
+    #[test_case("A == $3C", true)]
+    #[test_case("A == 60", true)]
+    #[test_case("A != 0x3C", false)]
+    #[test_case("[HL] != 0", true)]
+    #[test_case("[C001] > 0", false)]
+    #[test_case("HL >= $C000", true)]
+    #[test_case("SP <= 0", false)]
+    fn test_conditions(condition: &str, expected: bool) {
+        let condition: Condition = condition.parse().unwrap();
+
+        assert_eq!(condition.is_met(&create_registers(), &peek, 0), expected);
+    }
+
+    #[test]
+    fn test_execute_breakpoints_match_bank_of_switchable_rom_only() {
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.add("exec 02:4000".parse().unwrap());
+        breakpoints.add("exec 01:0150".parse().unwrap());
+        let registers = create_registers();
+
+        assert!(
+            breakpoints
+                .check_execute(0x4000, 3, &registers, &peek)
+                .is_none()
+        );
+        assert!(
+            breakpoints
+                .check_execute(0x4000, 2, &registers, &peek)
+                .is_some()
+        );
+        assert!(
+            breakpoints
+                .check_execute(0x0150, 5, &registers, &peek)
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_hit_counts_and_one_shots() {
+        let mut breakpoints = Breakpoints::default();
+        let id = breakpoints.add("exec 0150 if [HL] == $12 hits 2 once".parse().unwrap());
+        let registers = create_registers();
+
+        assert!(
+            breakpoints
+                .check_execute(0x0150, 1, &registers, &peek)
+                .is_none()
+        );
+
+        let hit = breakpoints.check_execute(0x0150, 1, &registers, &peek);
+
+        assert_eq!(hit.map(|hit| hit.breakpoint.id), Some(id));
+        assert!(breakpoints.is_empty());
+    }
+
+    #[test]
+    fn test_watchpoints_check_accesses() {
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.add("write C000-C0FF if value == 7".parse().unwrap());
+        let disabled = breakpoints.add("read C000".parse().unwrap());
+        breakpoints.set_enabled(disabled, false);
+
+        assert_eq!(
+            breakpoints.watched_ranges(),
+            vec![(AccessKind::Write, 0xC000, 0xC0FF)]
+        );
+
+        let access = |kind, value| MemoryAccess {
+            kind,
+            address: 0xC010,
+            value,
+        };
+        let registers = create_registers();
+
+        assert!(
+            breakpoints
+                .check_accesses(
+                    &[access(AccessKind::Read, 7), access(AccessKind::Write, 6)],
+                    &registers,
+                    &peek
+                )
+                .is_none()
+        );
+
+        let hit = breakpoints
+            .check_accesses(&[access(AccessKind::Write, 7)], &registers, &peek)
+            .unwrap();
+
+        assert_eq!(
+            hit.to_string(),
+            "Breakpoint #1 write C000-C0FF if value == 7 [1 hits], wrote 07 to C010"
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use crate::Word;
+use crate::debug::profiler::Location;
+use crate::disassembler::symbols::SymbolTable;
+use std::fmt::{Display, Formatter};
+
+/// Deeper stacks drop their outermost frame, as code that never returns would otherwise grow
+/// them forever.
+const MAX_DEPTH: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub function: Location,
+    pub return_address: Word,
+    // Where the return address has been pushed
+    pub sp: Word,
+    pub interrupt: bool,
+}
+
+/// SP left the region the outermost frame was pushed in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackOverflow {
+    sp: Word,
+    region: StackRegion,
+    depth: usize,
+}
+
+impl Display for StackOverflow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Stack overflow: SP {:04X} grew out of {:?} at call depth {}",
+            self.sp, self.region, self.depth
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StackRegion {
+    Wram,
+    Hram,
+    Other,
+}
+
+impl StackRegion {
+    fn of(address: Word) -> Self {
+        match address {
+            0xC000..=0xDFFF => StackRegion::Wram,
+            0xFF80..=0xFFFE => StackRegion::Hram,
+            _ => StackRegion::Other,
+        }
+    }
+}
+
+/**
+ * Shadow of the calls the CPU is in, kept apart from the emulated stack. Frames remember where
+ * their return address was pushed, so code that drops return addresses by moving SP around
+ * loses those frames on the next call or return instead of leaving them behind forever.
+ */
+#[derive(Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    // Same functions as frames, as a slice the profiler can key call stacks with
+    functions: Vec<Location>,
+    overflow_reported: bool,
+    overflow: Option<StackOverflow>,
+}
+
+impl CallStack {
+    pub fn call(&mut self, frame: Frame) {
+        // The new return address overwrites any other pushed at the same place
+        self.pop_while(|top| top.sp <= frame.sp);
+
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+            self.functions.remove(0);
+        }
+
+        self.check_overflow(frame.sp);
+
+        self.frames.push(frame);
+        self.functions.push(frame.function);
+    }
+
+    /**
+     * A return popping its address from `sp`. Returns whose address was not pushed by a tracked
+     * call, like jumps through a pushed address, leave the known frames alone.
+     */
+    pub fn ret(&mut self, sp: Word) {
+        self.pop_while(|top| top.sp < sp);
+
+        if self.frames.last().is_some_and(|frame| frame.sp == sp) {
+            self.frames.pop();
+            self.functions.pop();
+        }
+
+        if self.frames.is_empty() {
+            self.overflow_reported = false;
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn innermost(&self) -> Option<&Frame> {
+        self.frames.last()
+    }
+
+    /// The stack overflow found since the last call, reported once until the stack empties.
+    pub fn take_overflow(&mut self) -> Option<StackOverflow> {
+        self.overflow.take()
+    }
+
+    pub fn functions(&self) -> &[Location] {
+        &self.functions
+    }
+
+    /**
+     * One line per frame, innermost first, starting with the current position.
+     */
+    pub fn backtrace(&self, pc: Word, rom_bank: u16, symbols: &SymbolTable) -> Vec<String> {
+        let mut lines = vec![format!("#0 {}", Location::new(rom_bank, pc).name(symbols))];
+
+        for (index, frame) in self.frames.iter().rev().enumerate() {
+            lines.push(format!(
+                "#{} {} {}, returns to {:04X}",
+                index + 1,
+                if frame.interrupt { "interrupt" } else { "in" },
+                frame.function.name(symbols),
+                frame.return_address
+            ));
+        }
+
+        lines
+    }
+
+    /// Drops frames whose return address has been popped or skipped by the program.
+    fn pop_while(&mut self, condition: impl Fn(&Frame) -> bool) {
+        while self.frames.last().is_some_and(&condition) {
+            self.frames.pop();
+            self.functions.pop();
+        }
+    }
+
+    /**
+     * A stack set up in WRAM or HRAM that grows out of it starts overwriting whatever lies below,
+     * VRAM, cartridge RAM or I/O registers.
+     */
+    fn check_overflow(&mut self, sp: Word) {
+        let Some(base) = self.frames.first() else {
+            return;
+        };
+
+        let base_region = StackRegion::of(base.sp);
+
+        if self.overflow_reported
+            || base_region == StackRegion::Other
+            || StackRegion::of(sp) == base_region
+        {
+            return;
+        }
+
+        self.overflow = Some(StackOverflow {
+            sp,
+            region: base_region,
+            depth: self.frames.len(),
+        });
+        self.overflow_reported = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(function: Word, sp: Word) -> Frame {
+        Frame {
+            function: Location::new(1, function),
+            return_address: 0x0150,
+            sp,
+            interrupt: false,
+        }
+    }
+
+    #[test]
+    fn test_matching_returns_pop_frames() {
+        let mut call_stack = CallStack::default();
+
+        call_stack.call(frame(0x2000, 0xDFFC));
+        call_stack.call(frame(0x3000, 0xDFFA));
+        assert_eq!(call_stack.depth(), 2);
+
+        call_stack.ret(0xDFFA);
+        assert_eq!(call_stack.functions(), &[Location::new(1, 0x2000)]);
+
+        call_stack.ret(0xDFFC);
+        assert_eq!(call_stack.depth(), 0);
+    }
+
+    #[test]
+    fn test_returns_not_matching_a_call_keep_frames() {
+        let mut call_stack = CallStack::default();
+
+        call_stack.call(frame(0x2000, 0xDFFC));
+        // PUSH HL; RET inside the function
+        call_stack.ret(0xDFFA);
+
+        assert_eq!(call_stack.depth(), 1);
+    }
+
+    #[test]
+    fn test_frames_below_sp_are_dropped() {
+        let mut call_stack = CallStack::default();
+
+        call_stack.call(frame(0x2000, 0xDFFC));
+        call_stack.call(frame(0x3000, 0xDFFA));
+        // Both return addresses discarded by resetting the stack
+        call_stack.call(frame(0x4000, 0xDFFE));
+
+        assert_eq!(call_stack.functions(), &[Location::new(1, 0x4000)]);
+
+        call_stack.call(frame(0x5000, 0xDFFC));
+        // POP of the return address, then a return from the outer function
+        call_stack.ret(0xDFFE);
+
+        assert_eq!(call_stack.depth(), 0);
+    }
+
+    #[test]
+    fn test_reports_stack_growing_out_of_hram() {
+        let mut call_stack = CallStack::default();
+
+        call_stack.call(frame(0x2000, 0xFF82));
+        call_stack.call(frame(0x2000, 0xFF80));
+        assert_eq!(call_stack.take_overflow(), None);
+
+        call_stack.call(frame(0x2000, 0xFF7E));
+        assert_eq!(
+            call_stack.take_overflow().unwrap().to_string(),
+            "Stack overflow: SP FF7E grew out of Hram at call depth 2"
+        );
+
+        call_stack.call(frame(0x2000, 0xFF7C));
+        assert_eq!(call_stack.take_overflow(), None);
+    }
+
+    #[test]
+    fn test_backtrace_lists_innermost_first() {
+        let mut symbols = SymbolTable::default();
+        symbols.insert(1, 0x4000, "Update");
+
+        let mut call_stack = CallStack::default();
+        call_stack.call(frame(0x4000, 0xDFFC));
+        call_stack.call(Frame {
+            function: Location::new(1, 0x0040),
+            return_address: 0x4010,
+            sp: 0xDFFA,
+            interrupt: true,
+        });
+
+        assert_eq!(
+            call_stack.backtrace(0x0045, 1, &symbols),
+            vec![
+                "#0 00:0045".to_string(),
+                "#1 interrupt 00:0040, returns to 4010".to_string(),
+                "#2 in Update, returns to 0150".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use crate::Byte;
+use std::path::Path;
+
+/**
+ * Code/data log: one flag byte per ROM byte, the raw layout FCEUX made common. Bit 0 marks bytes
+ * executed as code, bit 1 bytes read as data, and bit 2 the first byte of each instruction so
+ * opcodes can be told from their operands.
+ */
+pub struct CodeDataLog {
+    flags: Vec<Byte>,
+}
+
+impl CodeDataLog {
+    pub const CODE: Byte = 0b001;
+    pub const DATA: Byte = 0b010;
+    pub const OPCODE: Byte = 0b100;
+
+    pub fn new(rom_size: usize) -> Self {
+        Self {
+            flags: vec![0; rom_size],
+        }
+    }
+
+    /**
+     * Log of a previous session, or an empty one when there is none. A log of a different size
+     * belongs to another ROM and is ignored.
+     */
+    pub fn load_or_new(path: &Path, rom_size: usize) -> Self {
+        match std::fs::read(path) {
+            Ok(flags) if flags.len() == rom_size => Self { flags },
+            Ok(flags) => {
+                println!(
+                    "Ignoring {}: {} bytes, the ROM has {rom_size}",
+                    path.display(),
+                    flags.len()
+                );
+
+                Self::new(rom_size)
+            }
+            Err(_) => Self::new(rom_size),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, &self.flags)
+    }
+
+    pub fn flags(&self, offset: usize) -> Byte {
+        self.flags.get(offset).copied().unwrap_or_default()
+    }
+
+    pub fn mark_data(&mut self, offset: usize) {
+        self.mark(offset, Self::DATA);
+    }
+
+    pub fn mark_instruction(&mut self, offset: usize, length: usize) {
+        self.mark(offset, Self::CODE | Self::OPCODE);
+
+        for operand in offset + 1..offset + length {
+            self.mark(operand, Self::CODE);
+        }
+    }
+
+    fn mark(&mut self, offset: usize, flags: Byte) {
+        if let Some(value) = self.flags.get_mut(offset) {
+            *value |= flags;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::NamedTempFile;
+
+    #[test]
+    fn test_marks_opcodes_operands_and_data() {
+        let mut log = CodeDataLog::new(8);
+
+        log.mark_instruction(1, 3);
+        log.mark_data(3);
+        log.mark_data(7);
+        log.mark_instruction(7, 2);
+
+        assert_eq!(log.flags(0), 0);
+        assert_eq!(log.flags(1), CodeDataLog::CODE | CodeDataLog::OPCODE);
+        assert_eq!(log.flags(2), CodeDataLog::CODE);
+        assert_eq!(log.flags(3), CodeDataLog::CODE | CodeDataLog::DATA);
+        assert_eq!(
+            log.flags(7),
+            CodeDataLog::CODE | CodeDataLog::OPCODE | CodeDataLog::DATA
+        );
+    }
+
+    #[test]
+    fn test_saves_and_loads_matching_size_only() {
+        let file = NamedTempFile::new("rom.cdl").unwrap();
+        let mut log = CodeDataLog::new(4);
+        log.mark_data(2);
+        log.save(file.path()).unwrap();
+
+        assert_eq!(
+            CodeDataLog::load_or_new(file.path(), 4).flags(2),
+            CodeDataLog::DATA
+        );
+        assert_eq!(CodeDataLog::load_or_new(file.path(), 8).flags(2), 0);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::Word;
+use crate::cpu::registers::CpuRegisters;
+use crate::debug::Debuggable;
+use crate::debug::history::ExecutionHistory;
+use crate::disassembler::Disassembler;
+use crate::disassembler::symbols::SymbolTable;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Everything known about the emulated console at the moment something went wrong.
+pub struct CrashReport<'a> {
+    pub reason: &'a str,
+    pub registers: &'a CpuRegisters,
+    pub rom_bank: u16,
+    pub cycles: u64,
+    pub history: &'a ExecutionHistory,
+    // Innermost first
+    pub backtrace: Vec<String>,
+    pub symbols: &'a SymbolTable,
+    pub io_values: BTreeMap<&'a str, String>,
+    // Words from SP upwards
+    pub stack: Vec<(Word, Word)>,
+}
+
+impl CrashReport<'_> {
+    pub fn write<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        writeln!(output, "#### {} ####\n", self.reason)?;
+
+        writeln!(output, "--- CPU ---")?;
+        for (name, value) in self.registers.get_debug_values() {
+            writeln!(output, "{name:<4} {value}")?;
+        }
+        writeln!(output, "BANK {:02X}", self.rom_bank)?;
+        writeln!(output, "CYCLES {}\n", self.cycles)?;
+
+        writeln!(output, "--- Backtrace ---")?;
+        for line in &self.backtrace {
+            writeln!(output, "{line}")?;
+        }
+
+        writeln!(
+            output,
+            "\n--- Last {} instructions, oldest first ---",
+            self.history.len()
+        )?;
+
+        let disassembler = Disassembler::with_symbols(self.symbols);
+
+        for entry in self.history.iter() {
+            let instruction = disassembler.disassemble(entry, entry.registers.pc, entry.rom_bank);
+            let registers = entry.registers.get_debug_values();
+
+            writeln!(
+                output,
+                "{:>12}  {:<7}  {:<24}  AF:{} BC:{} DE:{} HL:{} SP:{}",
+                entry.cycles,
+                instruction.location(),
+                instruction.to_string(),
+                registers["AF"],
+                registers["BC"],
+                registers["DE"],
+                registers["HL"],
+                registers["SP"],
+            )?;
+        }
+
+        writeln!(output, "\n--- I/O registers ---")?;
+        for (name, value) in &self.io_values {
+            writeln!(output, "{name:<5} {value}")?;
+        }
+
+        writeln!(output, "\n--- Stack ---")?;
+        for (address, value) in &self.stack {
+            writeln!(output, "{address:04X}  {value:04X}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Text a panic was raised with, when it has one.
+pub fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<String>()
+        .cloned()
+        .or_else(|| panic.downcast_ref::<&str>().map(|text| text.to_string()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug::history::HistoryEntry;
+
+    #[test]
+    fn test_writes_history_registers_and_stack() {
+        let mut registers = CpuRegisters::default();
+        registers.pc = 0x4123;
+
+        let mut history = ExecutionHistory::new(2);
+        history.push(HistoryEntry {
+            registers: registers.clone(),
+            rom_bank: 3,
+            bytes: [0xCD, 0x00, 0x50],
+            cycles: 1000,
+        });
+
+        let report = CrashReport {
+            reason: "CPU locked up",
+            registers: &registers,
+            rom_bank: 3,
+            cycles: 1024,
+            history: &history,
+            backtrace: vec!["#0 03:4123".to_string()],
+            symbols: &SymbolTable::default(),
+            io_values: BTreeMap::from([("LY", "90".to_string())]),
+            stack: vec![(0xFFFE, 0x0150)],
+        };
+
+        let mut output = Vec::new();
+        report.write(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.starts_with("#### CPU locked up ####"));
+        assert!(output.contains("PC   4123"));
+        assert!(output.contains("BANK 03"));
+        assert!(output.contains("--- Backtrace ---\n#0 03:4123\n"));
+        assert!(output.contains("        1000  03:4123  CALL $5000"));
+        assert!(output.contains("LY    90"));
+        assert!(output.contains("FFFE  0150"));
+    }
+}
@@ -0,0 +1,624 @@
+use crate::cpu::Cpu;
+use crate::debug::Debuggable;
+use crate::debug::breakpoints::{Breakpoint, Operand, parse_number};
+use crate::disassembler::Disassembler;
+use crate::emulator::Emulator;
+use crate::memory::Memory;
+use crate::memory::memory_sector::ReadMemory;
+use crate::{Byte, Word};
+use std::io::Write;
+use std::str::FromStr;
+
+/// Longest a single command keeps the emulation running: ten emulated seconds.
+const RUN_CYCLE_LIMIT: u64 = Cpu::CLOCK_FREQUENCY as u64 * 10;
+const DISASSEMBLY_HISTORY: usize = 3;
+const DISASSEMBLY_LENGTH: usize = 8;
+
+const HELP: &str = "\
+step [N], s         Runs N instructions (default 1)
+next, n             Runs the next instruction, calls included
+finish              Runs until the current function returns
+continue, c         Resumes emulation
+until frame         Runs until the next frame starts
+until scanline [N]  Runs until LY changes, or becomes N
+until interrupt     Runs until an interrupt is served
+break SPEC, b       Adds a breakpoint: [exec|read|write] ADDR [if CONDITION] [hits N] [once]
+watch SPEC          Adds a watchpoint: [read|write] ADDR[-ADDR] [if CONDITION] [hits N] [once]
+info breakpoints    Lists breakpoints
+delete ID           Removes a breakpoint
+enable ID           Enables a breakpoint
+disable ID          Disables a breakpoint
+info registers      Shows the CPU registers
+x/NFU ADDR          Shows N units of memory: F is x (hex) or d (decimal), U is b (byte) or w (word)
+disassemble [ADDR] [N]
+                    Disassembles N instructions from ADDR, or around PC
+backtrace, bt       Shows the call stack
+set TARGET = VALUE  Sets a register or [ADDR] to a value
+restrict on|off     Blocks the program from VRAM and OAM while the PPU uses them (default on)";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunTarget {
+    Frame,
+    // Any change of LY when none is given
+    Scanline(Option<Byte>),
+    Interrupt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Hexadecimal,
+    Decimal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unit {
+    Byte,
+    Word,
+}
+
+/// A command of the interactive debugger, gdb style.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DebuggerCommand {
+    Step(u32),
+    Next,
+    Finish,
+    Continue,
+    Until(RunTarget),
+    Break(Breakpoint),
+    ListBreakpoints,
+    Delete(usize),
+    Enable(usize, bool),
+    Registers,
+    Examine {
+        address: Operand,
+        count: u16,
+        format: Format,
+        unit: Unit,
+    },
+    Disassemble {
+        address: Option<Operand>,
+        count: usize,
+    },
+    Backtrace,
+    Set {
+        target: Operand,
+        value: Operand,
+    },
+    Restrict(bool),
+    Help,
+}
+
+/// Debugger reads of memory, which must not count as accesses of the program.
+struct Peek<'a>(&'a Memory);
+
+impl ReadMemory for Peek<'_> {
+    fn read_byte(&self, position: Word) -> Byte {
+        self.0.peek_byte(position)
+    }
+}
+
+impl DebuggerCommand {
+    /**
+     * Runs the command on the paused emulator, writing what it shows to `output`. Returns whether
+     * emulation has to resume.
+     */
+    pub fn execute(
+        &self,
+        emulator: &mut Emulator,
+        output: &mut dyn Write,
+    ) -> std::io::Result<bool> {
+        match self {
+            DebuggerCommand::Step(count) => {
+                for _ in 0..*count {
+                    emulator.step(false, false);
+
+                    if emulator.cpu.breakpoint_hit().is_some() {
+                        break;
+                    }
+                }
+
+                write_stop(emulator, output)?;
+            }
+            DebuggerCommand::Next => {
+                emulator.step_over(RUN_CYCLE_LIMIT);
+                write_stop(emulator, output)?;
+            }
+            DebuggerCommand::Finish => {
+                emulator.step_out(RUN_CYCLE_LIMIT);
+                write_stop(emulator, output)?;
+            }
+            DebuggerCommand::Continue => return Ok(true),
+            DebuggerCommand::Until(target) => {
+                run_until(emulator, *target);
+                write_stop(emulator, output)?;
+            }
+            DebuggerCommand::Break(breakpoint) => {
+                let id = emulator.cpu.add_breakpoint(breakpoint.clone());
+                writeln!(output, "Breakpoint #{id} set")?;
+            }
+            DebuggerCommand::ListBreakpoints => {
+                if emulator.cpu.breakpoints().is_empty() {
+                    writeln!(output, "No breakpoints")?;
+                }
+
+                for breakpoint in emulator.cpu.breakpoints().iter() {
+                    writeln!(output, "{breakpoint}")?;
+                }
+            }
+            DebuggerCommand::Delete(id) => {
+                if !emulator.cpu.remove_breakpoint(*id) {
+                    writeln!(output, "No breakpoint #{id}")?;
+                }
+            }
+            DebuggerCommand::Enable(id, enabled) => {
+                if !emulator.cpu.set_breakpoint_enabled(*id, *enabled) {
+                    writeln!(output, "No breakpoint #{id}")?;
+                }
+            }
+            DebuggerCommand::Registers => write_registers(emulator, output)?,
+            DebuggerCommand::Examine {
+                address,
+                count,
+                format,
+                unit,
+            } => {
+                let address = evaluate(emulator, address);
+                write_memory(
+                    &emulator.memory.read(),
+                    address,
+                    *count,
+                    *format,
+                    *unit,
+                    output,
+                )?;
+            }
+            DebuggerCommand::Disassemble { address, count } => {
+                let address = address.as_ref().map(|address| evaluate(emulator, address));
+                write_disassembly(emulator, address, *count, output)?;
+            }
+            DebuggerCommand::Backtrace => {
+                for line in emulator.cpu.backtrace() {
+                    writeln!(output, "{line}")?;
+                }
+            }
+            DebuggerCommand::Set { target, value } => {
+                let value = evaluate(emulator, value);
+
+                match target {
+                    Operand::ByteRegister(register) => {
+                        emulator.cpu.registers.write_byte(register, value as Byte)
+                    }
+                    Operand::WordRegister(register) => {
+                        emulator.cpu.registers.write_word(register, value)
+                    }
+                    Operand::Memory(address) => {
+                        let address = evaluate(emulator, address);
+
+                        emulator.memory.write().poke_byte(address, value as Byte);
+                    }
+                    Operand::Value(_) | Operand::Accessed => {
+                        writeln!(output, "Only registers and memory can be set")?
+                    }
+                }
+            }
+            DebuggerCommand::Restrict(restricted) => emulator
+                .memory
+                .write()
+                .set_ignore_access_restrictions(!restricted),
+            DebuggerCommand::Help => writeln!(output, "{HELP}")?,
+        }
+
+        Ok(false)
+    }
+}
+
+impl FromStr for DebuggerCommand {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let (command, arguments) = value.split_once(' ').unwrap_or((value, ""));
+        let arguments = arguments.trim();
+
+        if let Some(modifiers) = command.strip_prefix('x') {
+            return parse_examine(modifiers, arguments);
+        }
+
+        let command = match (command, arguments) {
+            ("step" | "s", "") => DebuggerCommand::Step(1),
+            ("step" | "s", count) => DebuggerCommand::Step(
+                count
+                    .parse()
+                    .map_err(|_| format!("Invalid step count \"{count}\""))?,
+            ),
+            ("next" | "n", "") => DebuggerCommand::Next,
+            ("finish", "") => DebuggerCommand::Finish,
+            ("continue" | "c", "") => DebuggerCommand::Continue,
+            ("until", "frame") => DebuggerCommand::Until(RunTarget::Frame),
+            ("until", "interrupt") => DebuggerCommand::Until(RunTarget::Interrupt),
+            ("until", "scanline") => DebuggerCommand::Until(RunTarget::Scanline(None)),
+            ("until", target) if target.starts_with("scanline ") => {
+                let line = target["scanline ".len()..].trim();
+
+                DebuggerCommand::Until(RunTarget::Scanline(Some(
+                    line.parse()
+                        .map_err(|_| format!("Invalid scanline \"{line}\""))?,
+                )))
+            }
+            ("break" | "b", spec) => DebuggerCommand::Break(parse_breakpoint(spec, "exec")?),
+            ("watch", spec) => DebuggerCommand::Break(parse_breakpoint(spec, "write")?),
+            ("info", "breakpoints" | "b") => DebuggerCommand::ListBreakpoints,
+            ("info", "registers" | "r") => DebuggerCommand::Registers,
+            ("delete", id) => DebuggerCommand::Delete(parse_id(id)?),
+            ("enable", id) => DebuggerCommand::Enable(parse_id(id)?, true),
+            ("disable", id) => DebuggerCommand::Enable(parse_id(id)?, false),
+            ("disassemble" | "disas", arguments) => {
+                let mut arguments = arguments.split_whitespace();
+
+                DebuggerCommand::Disassemble {
+                    address: arguments.next().map(Operand::parse_location).transpose()?,
+                    count: match arguments.next() {
+                        Some(count) => count
+                            .parse()
+                            .map_err(|_| format!("Invalid instruction count \"{count}\""))?,
+                        None => DISASSEMBLY_LENGTH,
+                    },
+                }
+            }
+            ("backtrace" | "bt", "") => DebuggerCommand::Backtrace,
+            ("set", assignment) => {
+                let (target, value) = assignment
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected TARGET = VALUE, got \"{assignment}\""))?;
+
+                DebuggerCommand::Set {
+                    target: target.trim().parse()?,
+                    value: value.trim().parse()?,
+                }
+            }
+            ("restrict", "on") => DebuggerCommand::Restrict(true),
+            ("restrict", "off") => DebuggerCommand::Restrict(false),
+            ("help" | "h", "") => DebuggerCommand::Help,
+            _ => return Err(format!("Unknown command \"{value}\", try \"help\"")),
+        };
+
+        Ok(command)
+    }
+}
+
+/// `x/NFU`: count, then format and unit in any order, all optional.
+fn parse_examine(modifiers: &str, address: &str) -> Result<DebuggerCommand, String> {
+    let modifiers = match modifiers.strip_prefix('/') {
+        Some(modifiers) => modifiers,
+        None if modifiers.is_empty() => "",
+        None => return Err(format!("Unknown command \"x{modifiers}\", try \"help\"")),
+    };
+
+    let digits = modifiers
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(modifiers.len());
+    let count = match &modifiers[..digits] {
+        "" => 1,
+        count => count
+            .parse()
+            .map_err(|_| format!("Invalid count \"{count}\""))?,
+    };
+
+    let mut format = Format::Hexadecimal;
+    let mut unit = Unit::Byte;
+
+    for modifier in modifiers[digits..].chars() {
+        match modifier {
+            'x' => format = Format::Hexadecimal,
+            'd' => format = Format::Decimal,
+            'b' => unit = Unit::Byte,
+            'w' => unit = Unit::Word,
+            _ => return Err(format!("Unknown format \"{modifier}\"")),
+        }
+    }
+
+    if address.is_empty() {
+        return Err("Missing address".to_string());
+    }
+
+    Ok(DebuggerCommand::Examine {
+        address: Operand::parse_location(address)?,
+        count,
+        format,
+        unit,
+    })
+}
+
+/// The breakpoint kind can be left out, `default_kind` is taken then.
+fn parse_breakpoint(spec: &str, default_kind: &str) -> Result<Breakpoint, String> {
+    match spec.split_whitespace().next() {
+        Some("exec" | "read" | "write") => spec.parse(),
+        Some(_) => format!("{default_kind} {spec}").parse(),
+        None => Err("Missing address".to_string()),
+    }
+}
+
+fn parse_id(value: &str) -> Result<usize, String> {
+    parse_number(value)
+        .map(|id| id as usize)
+        .map_err(|_| format!("Invalid breakpoint id \"{value}\""))
+}
+
+fn evaluate(emulator: &Emulator, operand: &Operand) -> Word {
+    let memory = emulator.memory.read();
+
+    operand.evaluate(
+        &emulator.cpu.registers,
+        &|address| memory.peek_byte(address),
+        0,
+    )
+}
+
+fn run_until(emulator: &mut Emulator, target: RunTarget) {
+    match target {
+        RunTarget::Frame => {
+            let frame = emulator.frame();
+
+            emulator.run_until(RUN_CYCLE_LIMIT, |emulator| emulator.frame() != frame);
+        }
+        RunTarget::Scanline(line) => {
+            let start = emulator.io_registers.read().ly.value;
+
+            emulator.run_until(RUN_CYCLE_LIMIT, |emulator| {
+                let ly = emulator.io_registers.read().ly.value;
+
+                ly != start && line.is_none_or(|line| ly == line)
+            });
+        }
+        RunTarget::Interrupt => {
+            emulator.run_until(RUN_CYCLE_LIMIT, Emulator::is_entering_interrupt);
+        }
+    }
+}
+
+/// Where emulation stopped, and why when a breakpoint did it.
+fn write_stop(emulator: &mut Emulator, output: &mut dyn Write) -> std::io::Result<()> {
+    // What gets looked at from now on must see the rest of the console where the CPU is
+    emulator.cpu.catch_up();
+
+    if let Some(overflow) = emulator.cpu.take_stack_overflow() {
+        writeln!(output, "{overflow}")?;
+    }
+
+    if let Some(hit) = emulator.cpu.take_breakpoint_hit() {
+        writeln!(output, "{hit}")?;
+    }
+
+    write_disassembly(emulator, Some(emulator.cpu.registers.pc), 1, output)
+}
+
+fn write_registers(emulator: &Emulator, output: &mut dyn Write) -> std::io::Result<()> {
+    let registers: Vec<String> = emulator
+        .cpu
+        .registers
+        .get_debug_values()
+        .iter()
+        .map(|(name, value)| format!("{name} {value}"))
+        .collect();
+
+    writeln!(output, "{}", registers.join("  "))?;
+    writeln!(
+        output,
+        "BANK {:02X}  LY {:02X}  CYCLES {}",
+        emulator.memory.read().current_rom_bank(),
+        emulator.io_registers.read().ly.value,
+        emulator.cpu.cycles()
+    )
+}
+
+fn write_memory(
+    memory: &Memory,
+    address: Word,
+    count: u16,
+    format: Format,
+    unit: Unit,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    let (size, per_line) = match unit {
+        Unit::Byte => (1, 16),
+        Unit::Word => (2, 8),
+    };
+
+    for line_start in (0..count).step_by(per_line) {
+        let line_address = address.wrapping_add(line_start * size);
+        write!(output, "{line_address:04X}:")?;
+
+        for index in line_start..count.min(line_start + per_line as u16) {
+            let position = address.wrapping_add(index * size);
+            let value = match unit {
+                Unit::Byte => memory.peek_byte(position) as Word,
+                Unit::Word => Word::from_le_bytes([
+                    memory.peek_byte(position),
+                    memory.peek_byte(position.wrapping_add(1)),
+                ]),
+            };
+
+            match (format, unit) {
+                (Format::Hexadecimal, Unit::Byte) => write!(output, " {value:02X}")?,
+                (Format::Hexadecimal, Unit::Word) => write!(output, " {value:04X}")?,
+                (Format::Decimal, _) => write!(output, " {value}")?,
+            }
+        }
+
+        writeln!(output)?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Disassembles `count` instructions from `address`. Without one, the last executed instructions
+ * come first, as code before PC cannot be decoded backwards reliably.
+ */
+fn write_disassembly(
+    emulator: &Emulator,
+    address: Option<Word>,
+    count: usize,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    let memory = emulator.memory.read();
+    let rom_bank = memory.current_rom_bank();
+    let pc = emulator.cpu.registers.pc;
+    let disassembler = Disassembler::with_symbols(emulator.cpu.symbols());
+
+    if address.is_none() {
+        let history = emulator.cpu.history();
+
+        for entry in history
+            .iter()
+            .skip(history.len().saturating_sub(DISASSEMBLY_HISTORY))
+        {
+            let instruction = disassembler.disassemble(entry, entry.registers.pc, entry.rom_bank);
+            writeln!(output, "   {:<7}  {instruction}", instruction.location())?;
+        }
+    }
+
+    let mut address = address.unwrap_or(pc);
+
+    for _ in 0..count {
+        let instruction = disassembler.disassemble(&Peek(&memory), address, rom_bank);
+        let marker = if address == pc { "=>" } else { "  " };
+
+        writeln!(
+            output,
+            "{marker} {:<7}  {instruction}",
+            instruction.location()
+        )?;
+        address = address.wrapping_add(instruction.length());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::cartridge::cartridge_header::CartridgeHeader;
+    use crate::cartridge::cartridge_memory_sector::CartridgeMemorySector;
+    use crate::cpu::registers::{ByteRegister, WordRegister};
+    use crate::model::Model;
+    use test_case::test_case;
+
+    fn create_emulator(program: &[u8]) -> Emulator {
+        let mut data = vec![0; 0x8000];
+        data[0x100..0x100 + program.len()].copy_from_slice(program);
+
+        let header = CartridgeHeader::new_from_data(&data);
+        let cartridge = Cartridge::new(CartridgeMemorySector::new_from_data(data), header);
+
+        Emulator::new(cartridge, None, Model::Dmg)
+    }
+
+    fn execute(emulator: &mut Emulator, command: &str) -> String {
+        let mut output = Vec::new();
+        command
+            .parse::<DebuggerCommand>()
+            .unwrap()
+            .execute(emulator, &mut output)
+            .unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test_case("s", DebuggerCommand::Step(1))]
+    #[test_case("step 10", DebuggerCommand::Step(10))]
+    #[test_case(
+        "until scanline 144",
+        DebuggerCommand::Until(RunTarget::Scanline(Some(144)))
+    )]
+    #[test_case("info b", DebuggerCommand::ListBreakpoints)]
+    #[test_case("disable 2", DebuggerCommand::Enable(2, false))]
+    #[test_case("x/16xb $C000", DebuggerCommand::Examine { address: Operand::Value(0xC000), count: 16, format: Format::Hexadecimal, unit: Unit::Byte })]
+    #[test_case("x/dw HL", DebuggerCommand::Examine { address: Operand::WordRegister(WordRegister::HL), count: 1, format: Format::Decimal, unit: Unit::Word })]
+    #[test_case("disas 0150", DebuggerCommand::Disassemble { address: Some(Operand::Value(0x0150)), count: DISASSEMBLY_LENGTH })]
+    #[test_case("set A = $3C", DebuggerCommand::Set { target: Operand::ByteRegister(ByteRegister::A), value: Operand::Value(0x3C) })]
+    #[test_case("restrict off", DebuggerCommand::Restrict(false))]
+    fn test_parse(value: &str, expected: DebuggerCommand) {
+        assert_eq!(value.parse(), Ok(expected));
+    }
+
+    #[test]
+    fn test_break_defaults_to_exec_and_watch_to_write() {
+        assert_eq!(
+            "b 02:4000 if A == 1".parse(),
+            Ok(DebuggerCommand::Break(
+                "exec 02:4000 if A == 1".parse().unwrap()
+            ))
+        );
+        assert_eq!(
+            "watch C000-C0FF".parse(),
+            Ok(DebuggerCommand::Break("write C000-C0FF".parse().unwrap()))
+        );
+    }
+
+    #[test_case("jump"; "unknown command")]
+    #[test_case("step many"; "invalid count")]
+    #[test_case("x/16q C000"; "unknown format")]
+    #[test_case("x/4"; "missing address")]
+    #[test_case("set A"; "missing value")]
+    #[test_case("restrict"; "missing restrict state")]
+    fn test_parse_errors(value: &str) {
+        assert!(value.parse::<DebuggerCommand>().is_err());
+    }
+
+    #[test]
+    fn test_step_and_next_show_the_next_instruction() {
+        // NOP; CALL $0110; NOP; ...; $0110: RET
+        let mut program = vec![0x00; 0x11];
+        program[1..4].copy_from_slice(&[0xCD, 0x10, 0x01]);
+        program[0x10] = 0xC9;
+        let mut emulator = create_emulator(&program);
+
+        assert_eq!(
+            execute(&mut emulator, "step"),
+            "=> 00:0101  CALL $0110 ; 00:0110\n"
+        );
+        assert_eq!(execute(&mut emulator, "next"), "=> 00:0104  NOP\n");
+    }
+
+    #[test]
+    fn test_stops_on_breakpoints() {
+        let mut emulator = create_emulator(&[0x00; 8]);
+
+        assert_eq!(execute(&mut emulator, "break 0103"), "Breakpoint #1 set\n");
+        assert_eq!(
+            execute(&mut emulator, "until frame"),
+            "Breakpoint #1 exec 0103 [1 hits]\n=> 00:0103  NOP\n"
+        );
+        assert_eq!(
+            execute(&mut emulator, "info breakpoints"),
+            "#1 exec 0103 [1 hits]\n"
+        );
+    }
+
+    #[test]
+    fn test_sets_and_examines_memory_and_registers() {
+        let mut emulator = create_emulator(&[]);
+
+        execute(&mut emulator, "set HL = $C000");
+        execute(&mut emulator, "set [HL] = 0x12");
+        execute(&mut emulator, "set [C001] = 52");
+
+        assert_eq!(execute(&mut emulator, "x/3xb HL"), "C000: 12 34 00\n");
+        assert_eq!(execute(&mut emulator, "x/1dw C000"), "C000: 13330\n");
+        assert!(execute(&mut emulator, "info registers").contains("HL C000"));
+    }
+
+    #[test]
+    fn test_continue_resumes_emulation() {
+        let mut emulator = create_emulator(&[]);
+        let mut output = Vec::new();
+
+        assert!(
+            DebuggerCommand::Continue
+                .execute(&mut emulator, &mut output)
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use crate::cpu::registers::CpuRegisters;
+use crate::memory::memory_sector::ReadMemory;
+use crate::{Byte, Word};
+use std::collections::VecDeque;
+
+/// An executed instruction with the state the CPU had right before running it.
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub registers: CpuRegisters,
+    pub rom_bank: u16,
+    pub bytes: [Byte; 3],
+    pub cycles: u64,
+}
+
+/// Lets the disassembler decode the instruction from the bytes kept in the entry.
+impl ReadMemory for HistoryEntry {
+    fn read_byte(&self, position: Word) -> Byte {
+        let offset = position.wrapping_sub(self.registers.pc) as usize;
+
+        self.bytes.get(offset).copied().unwrap_or(0xFF)
+    }
+}
+
+/**
+ * Ring buffer of the last executed instructions. Once full, each new instruction drops the oldest
+ * one.
+ */
+pub struct ExecutionHistory {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+}
+
+impl ExecutionHistory {
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(entry);
+    }
+
+    /// Oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl Default for ExecutionHistory {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_entry(pc: Word) -> HistoryEntry {
+        let mut registers = CpuRegisters::default();
+        registers.pc = pc;
+
+        HistoryEntry {
+            registers,
+            rom_bank: 1,
+            bytes: [0x3E, 0x12, 0x00],
+            cycles: pc as u64,
+        }
+    }
+
+    #[test]
+    fn test_keeps_only_the_last_entries() {
+        let mut history = ExecutionHistory::new(3);
+
+        for pc in 0..5 {
+            history.push(create_entry(pc));
+        }
+
+        let pcs: Vec<Word> = history.iter().map(|entry| entry.registers.pc).collect();
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(pcs, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_entry_reads_its_instruction_bytes() {
+        let entry = create_entry(0x4000);
+
+        assert_eq!(entry.read_byte(0x4000), 0x3E);
+        assert_eq!(entry.read_byte(0x4001), 0x12);
+        assert_eq!(entry.read_byte(0x4003), 0xFF);
+        assert_eq!(entry.read_byte(0x3FFF), 0xFF);
+    }
+}
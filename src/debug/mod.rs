@@ -1,25 +1,18 @@
+use crate::debug::breakpoints::BreakpointHit;
+use crate::debug::call_stack::StackOverflow;
 use crate::{Byte, Word};
 use prettytable::{Table, cell, row};
 use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 
-// CPU
-pub const CPU_PC_WATCHPOINTS: [Word; 1] = [
-    //0xC162, // Power down for a moment, wreg NR52,$00
-    //0xC1AD, // Test 2,"Powering off should clear NR12" - wreg NR14,$80
-    0xC1E2, // Test 3,"Powering off should clear NR13" - wreg NR10,$11
-];
-
-// I/O
-pub const IO_READ_WATCHPOINTS: [Word; 0] = [];
-pub const IO_WRITE_WATCHPOINTS: [Word; 0] = [
-    // Address::NR52_SOUND,
-    // Address::NR14_SOUND_1_FR_HI,
-    // Address::NR10_SOUND_1_SWEEP,
-    //Address::NR24_SOUND_2_FR_HI,
-    //Address::NR34_SOUND_3_FR_HI,
-    //Address::NR44_SOUND_4_CONTROL,
-];
+pub mod breakpoints;
+pub mod call_stack;
+pub mod code_data_log;
+pub mod crash_report;
+pub mod debugger;
+pub mod history;
+pub mod profiler;
+pub mod trace;
 
 pub trait Debuggable {
     fn get_debug_values(&self) -> BTreeMap<&str, String>;
@@ -87,17 +80,25 @@ impl OutputDebug {
 }
 
 pub enum DebugReason {
-    IORead(Word),
-    IOWrite(Word, Byte),
-    PC(Word),
+    Breakpoint(BreakpointHit),
+    IllegalOpcode(Word, Byte),
+    LcdOffOutsideVBlank(Byte),
+    StackOverflow(StackOverflow),
 }
 
 impl Display for DebugReason {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let text = match self {
-            DebugReason::IORead(addr) => format!("I/O Read {addr:X}",),
-            DebugReason::IOWrite(addr, value) => format!("I/O Write {addr:X} value {value:X}"),
-            DebugReason::PC(addr) => format!("PC {addr:X}"),
+            DebugReason::Breakpoint(hit) => hit.to_string(),
+            DebugReason::IllegalOpcode(addr, opcode) => {
+                format!("Illegal opcode {opcode:X} at {addr:X}, CPU locked")
+            }
+            DebugReason::LcdOffOutsideVBlank(line) => {
+                format!(
+                    "LCD turned off at line {line} outside of V-Blank, which can damage a real LCD"
+                )
+            }
+            DebugReason::StackOverflow(overflow) => overflow.to_string(),
         };
 
         write!(f, "{text}")
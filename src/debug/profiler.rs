@@ -0,0 +1,287 @@
+use crate::Word;
+use crate::disassembler::format_location;
+use crate::disassembler::symbols::SymbolTable;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+const REPORT_TOP_ADDRESSES: usize = 50;
+const ROOT_FRAME: &str = "[root]";
+const HALT_FRAME: &str = "[halt]";
+
+/// Address of executed code, with the ROM bank it was mapped from when it is switchable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Location {
+    pub bank: u16,
+    pub address: Word,
+}
+
+impl Location {
+    pub fn new(rom_bank: u16, address: Word) -> Self {
+        let bank = match address {
+            0x4000..=0x7FFF => rom_bank,
+            _ => 0,
+        };
+
+        Self { bank, address }
+    }
+
+    pub fn name(&self, symbols: &SymbolTable) -> String {
+        symbols
+            .lookup(self.bank, self.address)
+            .map_or_else(|| format_location(self.bank, self.address), str::to_string)
+    }
+}
+
+/// Where the cycles of executed code go, grouped by the place the code lives in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Region {
+    RomBank(u16),
+    Ram,
+}
+
+/**
+ * Counts cycles per executed address, per ROM bank and per call stack, the functions of the CPU
+ * shadow call stack.
+ */
+#[derive(Default)]
+pub struct Profiler {
+    addresses: HashMap<Location, u64>,
+    regions: BTreeMap<Region, u64>,
+    stacks: HashMap<Vec<Location>, u64>,
+
+    running_cycles: u64,
+    halted_cycles: u64,
+}
+
+impl Profiler {
+    pub fn record(&mut self, location: Location, cycles: u8, call_stack: &[Location]) {
+        let cycles = cycles as u64;
+
+        *self.addresses.entry(location).or_default() += cycles;
+
+        let region = match location.address {
+            0x0000..=0x7FFF => Region::RomBank(location.bank),
+            _ => Region::Ram,
+        };
+        *self.regions.entry(region).or_default() += cycles;
+
+        match self.stacks.get_mut(call_stack) {
+            Some(stack_cycles) => *stack_cycles += cycles,
+            None => {
+                self.stacks.insert(call_stack.to_vec(), cycles);
+            }
+        }
+
+        self.running_cycles += cycles;
+    }
+
+    pub fn record_halt(&mut self, cycles: u8) {
+        self.halted_cycles += cycles as u64;
+    }
+
+    pub fn write_report<W: Write>(
+        &self,
+        output: &mut W,
+        symbols: &SymbolTable,
+    ) -> std::io::Result<()> {
+        let total = self.running_cycles + self.halted_cycles;
+        let percentage = |cycles: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                cycles as f64 * 100.0 / total as f64
+            }
+        };
+
+        writeln!(output, "Total cycles: {total}")?;
+        writeln!(
+            output,
+            "Running:      {} ({:.2}%)",
+            self.running_cycles,
+            percentage(self.running_cycles)
+        )?;
+        writeln!(
+            output,
+            "Halted:       {} ({:.2}%)",
+            self.halted_cycles,
+            percentage(self.halted_cycles)
+        )?;
+
+        writeln!(output, "\n--- ROM banks ---")?;
+        let mut regions: Vec<_> = self.regions.iter().collect();
+        regions.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        for (region, cycles) in regions {
+            let name = match region {
+                Region::RomBank(bank) => format!("{bank:02X}"),
+                Region::Ram => "RAM".to_string(),
+            };
+
+            writeln!(
+                output,
+                "{name:<8} {cycles:>12} {:>7.2}%",
+                percentage(*cycles)
+            )?;
+        }
+
+        writeln!(output, "\n--- Functions (inclusive / self) ---")?;
+        for (function, inclusive, exclusive) in self.functions() {
+            writeln!(
+                output,
+                "{:<32} {inclusive:>12} {:>7.2}% {exclusive:>12} {:>7.2}%",
+                function.name(symbols),
+                percentage(inclusive),
+                percentage(exclusive)
+            )?;
+        }
+
+        writeln!(output, "\n--- Hottest addresses ---")?;
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        for (location, cycles) in addresses.into_iter().take(REPORT_TOP_ADDRESSES) {
+            writeln!(
+                output,
+                "{:<32} {cycles:>12} {:>7.2}%",
+                location.name(symbols),
+                percentage(*cycles)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /**
+     * One line per call stack with the cycles spent in its innermost function, the folded format
+     * `flamegraph.pl` and `inferno` read.
+     */
+    pub fn write_folded<W: Write>(
+        &self,
+        output: &mut W,
+        symbols: &SymbolTable,
+    ) -> std::io::Result<()> {
+        let mut lines: Vec<(String, u64)> = self
+            .stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let frames: Vec<String> = std::iter::once(ROOT_FRAME.to_string())
+                    .chain(stack.iter().map(|location| location.name(symbols)))
+                    .collect();
+
+                (frames.join(";"), *cycles)
+            })
+            .collect();
+
+        if self.halted_cycles > 0 {
+            lines.push((format!("{ROOT_FRAME};{HALT_FRAME}"), self.halted_cycles));
+        }
+
+        lines.sort();
+
+        for (stack, cycles) in lines {
+            writeln!(output, "{stack} {cycles}")?;
+        }
+
+        Ok(())
+    }
+
+    /// Every function seen on a call stack with its inclusive and self cycles, hottest first.
+    fn functions(&self) -> Vec<(Location, u64, u64)> {
+        let mut functions: HashMap<Location, (u64, u64)> = HashMap::new();
+
+        for (stack, cycles) in &self.stacks {
+            let mut seen = Vec::with_capacity(stack.len());
+
+            for function in stack {
+                // Recursive calls count once towards the inclusive time
+                if !seen.contains(function) {
+                    seen.push(*function);
+                    functions.entry(*function).or_default().0 += cycles;
+                }
+            }
+
+            if let Some(function) = stack.last() {
+                functions.entry(*function).or_default().1 += cycles;
+            }
+        }
+
+        let mut functions: Vec<_> = functions
+            .into_iter()
+            .map(|(function, (inclusive, exclusive))| (function, inclusive, exclusive))
+            .collect();
+        functions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        functions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> Profiler {
+        let mut profiler = Profiler::default();
+        let function = Location::new(2, 0x4000);
+        let interrupt = Location::new(2, 0x0038);
+
+        profiler.record(Location::new(1, 0x0150), 4, &[]);
+        profiler.record(function, 8, &[function]);
+        profiler.record(interrupt, 16, &[function, interrupt]);
+        profiler.record(Location::new(1, 0x0150), 4, &[]);
+        profiler.record_halt(4);
+
+        profiler
+    }
+
+    #[test]
+    fn test_location_only_keeps_bank_for_switchable_rom() {
+        assert_eq!(Location::new(3, 0x0150).bank, 0);
+        assert_eq!(Location::new(3, 0x4150).bank, 3);
+        assert_eq!(Location::new(3, 0xC000).bank, 0);
+    }
+
+    #[test]
+    fn test_functions_count_inclusive_and_self_cycles() {
+        let profiler = profile();
+
+        assert_eq!(
+            profiler.functions(),
+            vec![
+                (Location::new(2, 0x4000), 24, 8),
+                (Location::new(0, 0x0038), 16, 16),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_writes_folded_stacks() {
+        let mut symbols = SymbolTable::default();
+        symbols.insert(2, 0x4000, "UpdateActors");
+
+        let mut output = Vec::new();
+        profile().write_folded(&mut output, &symbols).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[root] 8\n\
+             [root];UpdateActors 8\n\
+             [root];UpdateActors;00:0038 16\n\
+             [root];[halt] 4\n"
+        );
+    }
+
+    #[test]
+    fn test_report_splits_running_and_halted_cycles() {
+        let mut output = Vec::new();
+        profile()
+            .write_report(&mut output, &SymbolTable::default())
+            .unwrap();
+        let report = String::from_utf8(output).unwrap();
+
+        assert!(report.contains("Total cycles: 36\n"));
+        assert!(report.contains("Running:      32 (88.89%)"));
+        assert!(report.contains("Halted:       4 (11.11%)"));
+        assert!(report.contains("00                 24   66.67%"));
+        assert!(report.contains(&format!("{:<32} {:>12}   22.22%\n", "02:4000", 8)));
+    }
+}
@@ -0,0 +1,225 @@
+use crate::cpu::registers::{ByteRegister, CpuRegisters};
+use crate::memory::Memory;
+use crate::{Byte, Word};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+
+/// Point in the execution where a trace starts or stops.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceCondition {
+    PcRange(Word, Word),
+    RomBank(u16),
+    Frame(u64),
+}
+
+impl TraceCondition {
+    fn is_met(&self, pc: Word, rom_bank: u16, frame: u64) -> bool {
+        match self {
+            TraceCondition::PcRange(start, end) => (*start..=*end).contains(&pc),
+            TraceCondition::RomBank(bank) => (0x4000..0x8000).contains(&pc) && rom_bank == *bank,
+            TraceCondition::Frame(number) => frame >= *number,
+        }
+    }
+}
+
+impl FromStr for TraceCondition {
+    type Err = String;
+
+    /// Accepts `pc:0150`, `pc:0150-01FF`, `bank:3` and `frame:120`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (kind, argument) = value
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid trace condition \"{value}\""))?;
+
+        match kind {
+            "pc" => {
+                let (start, end) = argument.split_once('-').unwrap_or((argument, argument));
+                let start = parse_address(start)?;
+                let end = parse_address(end)?;
+
+                if start > end {
+                    return Err(format!("Invalid PC range \"{argument}\""));
+                }
+
+                Ok(TraceCondition::PcRange(start, end))
+            }
+            "bank" => argument
+                .parse()
+                .map(TraceCondition::RomBank)
+                .map_err(|_| format!("Invalid ROM bank \"{argument}\"")),
+            "frame" => argument
+                .parse()
+                .map(TraceCondition::Frame)
+                .map_err(|_| format!("Invalid frame number \"{argument}\"")),
+            _ => Err(format!("Unknown trace condition \"{kind}\"")),
+        }
+    }
+}
+
+fn parse_address(value: &str) -> Result<Word, String> {
+    let digits = value
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .trim_start_matches('$');
+
+    Word::from_str_radix(digits, 16).map_err(|_| format!("Invalid address \"{value}\""))
+}
+
+/// Writes one line per executed instruction using the Gameboy Doctor log format.
+pub struct TraceLogger {
+    writer: BufWriter<File>,
+    start: Option<TraceCondition>,
+    stop: Option<TraceCondition>,
+    started: bool,
+    stopped: bool,
+}
+
+impl TraceLogger {
+    pub fn new(
+        path: &str,
+        start: Option<TraceCondition>,
+        stop: Option<TraceCondition>,
+    ) -> std::io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            started: start.is_none(),
+            start,
+            stop,
+            stopped: false,
+        })
+    }
+
+    pub fn log(&mut self, registers: &CpuRegisters, memory: &Memory, frame: u64) {
+        if self.stopped {
+            return;
+        }
+
+        let pc = registers.pc;
+        let rom_bank = memory.current_rom_bank();
+
+        if !self.started {
+            self.started = self
+                .start
+                .as_ref()
+                .is_some_and(|condition| condition.is_met(pc, rom_bank, frame));
+
+            if !self.started {
+                return;
+            }
+        }
+
+        if let Some(stop) = &self.stop
+            && stop.is_met(pc, rom_bank, frame)
+        {
+            self.stopped = true;
+            self.flush();
+
+            return;
+        }
+
+        let pc_memory: [Byte; 4] = [
+            memory.peek_byte(pc),
+            memory.peek_byte(pc.wrapping_add(1)),
+            memory.peek_byte(pc.wrapping_add(2)),
+            memory.peek_byte(pc.wrapping_add(3)),
+        ];
+
+        writeln!(
+            self.writer,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            registers.read_byte(&ByteRegister::A),
+            registers.read_byte(&ByteRegister::F),
+            registers.read_byte(&ByteRegister::B),
+            registers.read_byte(&ByteRegister::C),
+            registers.read_byte(&ByteRegister::D),
+            registers.read_byte(&ByteRegister::E),
+            registers.read_byte(&ByteRegister::H),
+            registers.read_byte(&ByteRegister::L),
+            registers.sp,
+            pc,
+            pc_memory[0],
+            pc_memory[1],
+            pc_memory[2],
+            pc_memory[3],
+        )
+        .expect("Could not write to trace file");
+    }
+
+    pub fn flush(&mut self) {
+        self.writer.flush().expect("Could not flush trace file");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::NamedTempFile;
+    use test_case::test_case;
+
+    #[test_case("pc:0150", TraceCondition::PcRange(0x150, 0x150))]
+    #[test_case("pc:$0150-0x01FF", TraceCondition::PcRange(0x150, 0x1FF))]
+    #[test_case("bank:3", TraceCondition::RomBank(3))]
+    #[test_case("frame:120", TraceCondition::Frame(120))]
+    fn test_parses_trace_condition(value: &str, expected: TraceCondition) {
+        assert_eq!(value.parse::<TraceCondition>(), Ok(expected));
+    }
+
+    #[test_case("pc"; "missing separator")]
+    #[test_case("pc:01FF-0150"; "reversed range")]
+    #[test_case("bank:one"; "non numeric bank")]
+    #[test_case("line:3"; "unknown kind")]
+    fn test_rejects_invalid_trace_condition(value: &str) {
+        assert!(value.parse::<TraceCondition>().is_err());
+    }
+
+    #[test]
+    fn test_logs_in_gameboy_doctor_format() {
+        let tmp_file = NamedTempFile::new("trace.log").unwrap();
+        let mut memory = Memory::default();
+        let mut registers = CpuRegisters::default();
+
+        for (offset, value) in [0x3E, 0x12, 0x00, 0xC9].iter().enumerate() {
+            memory.write_byte(0xC000 + offset as Word, *value);
+        }
+
+        registers.pc = 0xC000;
+
+        let mut logger = TraceLogger::new(tmp_file.to_str().unwrap(), None, None).unwrap();
+        logger.log(&registers, &memory, 0);
+        logger.flush();
+
+        assert_eq!(
+            std::fs::read_to_string(tmp_file.path()).unwrap(),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:C000 PCMEM:3E,12,00,C9\n"
+        );
+    }
+
+    #[test]
+    fn test_logs_only_between_start_and_stop() {
+        let tmp_file = NamedTempFile::new("trace.log").unwrap();
+        let memory = Memory::default();
+        let mut registers = CpuRegisters::default();
+        registers.pc = 0xC000;
+
+        let mut logger = TraceLogger::new(
+            tmp_file.to_str().unwrap(),
+            Some(TraceCondition::Frame(2)),
+            Some(TraceCondition::PcRange(0xC003, 0xC003)),
+        )
+        .unwrap();
+
+        for frame in 0..4 {
+            registers.pc = 0xC000 + frame as Word;
+            logger.log(&registers, &memory, frame);
+        }
+
+        logger.flush();
+
+        let contents = std::fs::read_to_string(tmp_file.path()).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("PC:C002"));
+    }
+}
@@ -0,0 +1,473 @@
+use crate::debug::code_data_log::CodeDataLog;
+use crate::disassembler::symbols::SymbolTable;
+use crate::disassembler::{Disassembler, Flow, ROM_BANK_SIZE, RomBankView, bank_for_address};
+use crate::utils::math::two_bytes_to_word;
+use crate::{Byte, Word};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::io::Write;
+
+/// Addresses where the CPU starts executing: RST vectors, interrupt vectors and the entry point.
+const ENTRY_POINTS: [Word; 14] = [
+    0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38, 0x40, 0x48, 0x50, 0x58, 0x60, 0x100,
+];
+
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteUsage {
+    Unknown,
+    Opcode,
+    Operand,
+    Data,
+}
+
+/// Classification of every ROM byte as code or data, per bank.
+pub struct CodeMap {
+    usage: Vec<ByteUsage>,
+    labels: Vec<bool>,
+}
+
+impl CodeMap {
+    pub fn new(rom_size: usize) -> Self {
+        Self {
+            usage: vec![ByteUsage::Unknown; rom_size],
+            labels: vec![false; rom_size],
+        }
+    }
+
+    pub fn usage(&self, offset: usize) -> ByteUsage {
+        self.usage[offset]
+    }
+
+    pub fn is_label(&self, offset: usize) -> bool {
+        self.labels[offset]
+    }
+
+    /// Follows every reachable path from the entry points, marking the bytes it decodes as code.
+    pub fn trace(&mut self, data: &[Byte]) {
+        self.trace_from(data, ENTRY_POINTS.iter().map(|a| (0, *a, true)).collect());
+    }
+
+    /**
+     * Takes what a code/data log saw at runtime: bytes only read as data are never decoded, and
+     * every executed instruction is traced from, reaching code static analysis cannot find,
+     * like jump tables and banked calls.
+     */
+    pub fn apply_code_data_log(&mut self, data: &[Byte], code_data_log: &CodeDataLog) {
+        let mut executed = VecDeque::new();
+
+        for offset in 0..data.len() {
+            let flags = code_data_log.flags(offset);
+
+            if flags & CodeDataLog::OPCODE != 0 {
+                let (bank, address) = location_of(offset);
+                executed.push_back((bank, address, false));
+            } else if flags & CodeDataLog::DATA != 0 && flags & CodeDataLog::CODE == 0 {
+                self.mark(offset, ByteUsage::Data);
+            }
+        }
+
+        self.trace_from(data, executed);
+    }
+
+    fn mark(&mut self, offset: usize, usage: ByteUsage) {
+        self.usage[offset] = usage;
+    }
+
+    fn trace_from(&mut self, data: &[Byte], mut pending: VecDeque<(u16, Word, bool)>) {
+        let bank_count = data.len().div_ceil(ROM_BANK_SIZE).max(1);
+
+        while let Some((bank, start, is_label)) = pending.pop_front() {
+            let view = RomBankView::new(data, bank.max(1));
+            let mut address = start;
+
+            if is_label && let Some(offset) = view.offset(address) {
+                self.labels[offset] = true;
+            }
+
+            while let Some(offset) = view.offset(address) {
+                if (address < 0x4000) != (start < 0x4000) || self.usage[offset] == ByteUsage::Opcode
+                {
+                    break;
+                }
+
+                let instruction = Disassembler::new().disassemble(&view, address, bank.max(1));
+                let length = instruction.length() as usize;
+
+                let end_in_same_region = (address as usize & !(ROM_BANK_SIZE - 1))
+                    == ((address as usize + length - 1) & !(ROM_BANK_SIZE - 1));
+
+                if instruction.flow == Flow::Illegal
+                    || !end_in_same_region
+                    || offset + length > data.len()
+                    || (0..length).any(|i| self.usage[offset + i] != ByteUsage::Unknown)
+                {
+                    break;
+                }
+
+                self.mark(offset, ByteUsage::Opcode);
+
+                for i in 1..length {
+                    self.mark(offset + i, ByteUsage::Operand);
+                }
+
+                let next = address.wrapping_add(length as Word);
+
+                match instruction.flow {
+                    Flow::Next | Flow::ConditionalReturn => address = next,
+                    Flow::ConditionalJump(target)
+                    | Flow::Call(target)
+                    | Flow::ConditionalCall(target) => {
+                        if let Some((bank, target)) = Self::resolve_target(bank, target, bank_count)
+                        {
+                            pending.push_back((bank, target, true));
+                        }
+
+                        address = next;
+                    }
+                    Flow::Jump(target) => {
+                        if let Some((bank, target)) = Self::resolve_target(bank, target, bank_count)
+                        {
+                            pending.push_back((bank, target, true));
+                        }
+
+                        break;
+                    }
+                    Flow::Return | Flow::IndirectJump | Flow::Illegal => break,
+                }
+            }
+        }
+    }
+
+    /// Works out the bank a jump lands in. Jumps from bank 0 into switchable ROM can only be
+    /// followed when the cartridge has no banking.
+    fn resolve_target(bank: u16, target: Word, bank_count: usize) -> Option<(u16, Word)> {
+        match target {
+            0x0000..=0x3FFF => Some((0, target)),
+            0x4000..=0x7FFF if bank != 0 => Some((bank, target)),
+            0x4000..=0x7FFF if bank_count == 2 => Some((1, target)),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Writes an RGBDS compatible listing of the given banks, optionally restricted to a range.
+ * Instructions with a label inside, which other code jumps into, are written as data so the
+ * label can be defined. Labels referenced from outside the listed sections are defined as
+ * constants first.
+ */
+pub fn write_listing<W: Write>(
+    output: &mut W,
+    data: &[Byte],
+    code_map: &CodeMap,
+    symbols: &SymbolTable,
+    banks: &[u16],
+    range: Option<(Word, Word)>,
+) -> std::io::Result<()> {
+    let mut labels = SymbolTable::default();
+
+    for offset in (0..data.len()).filter(|offset| code_map.is_label(*offset)) {
+        let (bank, address) = location_of(offset);
+
+        match symbols.lookup(bank, address) {
+            Some(label) => labels.insert(bank, address, label),
+            None => labels.insert(bank, address, &format!("Label_{bank:03X}_{address:04X}")),
+        }
+    }
+
+    let disassembler = Disassembler::with_symbols(&labels);
+    let bank_count = data.len().div_ceil(ROM_BANK_SIZE);
+
+    let mut sections = Vec::new();
+    let mut defined = HashSet::new();
+    let mut referenced = BTreeMap::new();
+
+    for bank in banks {
+        // Code in bank 0 can only be sure about the switchable bank when there is no banking
+        let switchable_bank = match bank {
+            0 if bank_count == 2 => 1,
+            _ => *bank,
+        };
+
+        let view = RomBankView::new(data, (*bank).max(1));
+        let bank_start: Word = if *bank == 0 { 0x0000 } else { 0x4000 };
+        let bank_end = bank_start as usize + ROM_BANK_SIZE;
+
+        let (start, end) = match range {
+            Some((start, end)) => (start.max(bank_start) as usize, (end as usize).min(bank_end)),
+            None => (bank_start as usize, bank_end),
+        };
+
+        if start >= end || view.offset(start as Word).is_none() {
+            continue;
+        }
+
+        if *bank == 0 {
+            writeln!(sections, "SECTION \"ROM Bank $000\", ROM0[${start:04X}]\n")?;
+        } else {
+            writeln!(
+                sections,
+                "SECTION \"ROM Bank ${bank:03X}\", ROMX[${start:04X}], BANK[${bank:X}]\n"
+            )?;
+        }
+
+        let mut address = start;
+        let mut pending_data: Vec<Byte> = Vec::with_capacity(DATA_BYTES_PER_LINE);
+
+        while address < end {
+            let Some(offset) = view.offset(address as Word) else {
+                break;
+            };
+
+            let label = code_map
+                .is_label(offset)
+                .then(|| labels.lookup(*bank, address as Word))
+                .flatten();
+
+            let instruction = (code_map.usage(offset) == ByteUsage::Opcode)
+                .then(|| disassembler.disassemble(&view, address as Word, switchable_bank))
+                .filter(|instruction| {
+                    (1..instruction.length() as usize).all(|i| !code_map.is_label(offset + i))
+                });
+            let is_code = instruction.is_some();
+
+            if (label.is_some() || is_code || pending_data.len() == DATA_BYTES_PER_LINE)
+                && !pending_data.is_empty()
+            {
+                write_data(&mut sections, &pending_data)?;
+                pending_data.clear();
+            }
+
+            if let Some(label) = label {
+                writeln!(sections, "{label}:")?;
+                defined.insert(label);
+            }
+
+            if let Some(instruction) = instruction {
+                if let Some(target) = referenced_address(&instruction.bytes, instruction.flow)
+                    && let Some(label) =
+                        labels.lookup(bank_for_address(switchable_bank, target), target)
+                {
+                    referenced.insert(label, target);
+                }
+
+                writeln!(
+                    sections,
+                    "    {:<32} ; {}{}",
+                    instruction.text,
+                    instruction.location(),
+                    instruction
+                        .comment
+                        .as_ref()
+                        .map_or(String::new(), |comment| format!(" {comment}"))
+                )?;
+
+                address += instruction.length() as usize;
+            } else {
+                pending_data.push(data[offset]);
+                address += 1;
+            }
+        }
+
+        if !pending_data.is_empty() {
+            write_data(&mut sections, &pending_data)?;
+        }
+
+        writeln!(sections)?;
+    }
+
+    let outside: Vec<_> = referenced
+        .into_iter()
+        .filter(|(label, _)| !defined.contains(label))
+        .collect();
+
+    for (label, address) in &outside {
+        writeln!(output, "DEF {label} EQU ${address:04X}")?;
+    }
+
+    if !outside.is_empty() {
+        writeln!(output)?;
+    }
+
+    output.write_all(&sections)?;
+
+    Ok(())
+}
+
+/// Address an instruction refers to, which may have a label: the target of a jump or a call,
+/// or the address loaded from or stored to.
+fn referenced_address(bytes: &[Byte], flow: Flow) -> Option<Word> {
+    match (flow, bytes) {
+        (
+            Flow::Jump(target)
+            | Flow::ConditionalJump(target)
+            | Flow::Call(target)
+            | Flow::ConditionalCall(target),
+            _,
+        ) => Some(target),
+        (_, [0x08 | 0xEA | 0xFA, low, high]) => Some(two_bytes_to_word(*high, *low)),
+        _ => None,
+    }
+}
+
+fn write_data<W: Write>(output: &mut W, bytes: &[Byte]) -> std::io::Result<()> {
+    let values: Vec<String> = bytes.iter().map(|byte| format!("${byte:02X}")).collect();
+
+    writeln!(output, "    DB {}", values.join(","))
+}
+
+fn location_of(offset: usize) -> (u16, Word) {
+    let bank = (offset / ROM_BANK_SIZE) as u16;
+    let address = if bank == 0 {
+        offset
+    } else {
+        ROM_BANK_SIZE + offset % ROM_BANK_SIZE
+    };
+
+    (bank, address as Word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn create_rom() -> Vec<Byte> {
+        let mut data = vec![0xFF; ROM_BANK_SIZE * 2];
+
+        // Entry point: NOP, JP $0150
+        data[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        // LD A,$01; CALL $0160; JR -7
+        data[0x150..0x157].copy_from_slice(&[0x3E, 0x01, 0xCD, 0x60, 0x01, 0x18, 0xF9]);
+        // JP $4000
+        data[0x160..0x163].copy_from_slice(&[0xC3, 0x00, 0x40]);
+        // RET
+        data[0x4000] = 0xC9;
+
+        data
+    }
+
+    #[test]
+    fn test_traces_code_from_entry_point() {
+        let data = create_rom();
+        let mut code_map = CodeMap::new(data.len());
+
+        code_map.trace(&data);
+
+        assert_eq!(code_map.usage(0x100), ByteUsage::Opcode);
+        assert_eq!(code_map.usage(0x102), ByteUsage::Operand);
+        assert_eq!(code_map.usage(0x155), ByteUsage::Opcode);
+        assert_eq!(code_map.usage(0x157), ByteUsage::Unknown);
+        assert_eq!(code_map.usage(0x4000), ByteUsage::Opcode);
+        assert!(code_map.is_label(0x150));
+        assert!(code_map.is_label(0x160));
+        assert!(code_map.is_label(0x4000));
+    }
+
+    #[test]
+    fn test_code_data_log_adds_executed_code_and_data() {
+        let mut data = create_rom();
+        // Reached through JP HL only: LD B,$02; RET
+        data[0x200..0x203].copy_from_slice(&[0x06, 0x02, 0xC9]);
+        // Read as data, decodes as LD BC,$0000 otherwise
+        data[0x104..0x107].copy_from_slice(&[0x01, 0x00, 0x00]);
+
+        let mut code_data_log = CodeDataLog::new(data.len());
+        code_data_log.mark_instruction(0x200, 2);
+        code_data_log.mark_data(0x104);
+        code_data_log.mark_instruction(0x100, 3);
+        code_data_log.mark_data(0x102);
+
+        let mut code_map = CodeMap::new(data.len());
+        code_map.apply_code_data_log(&data, &code_data_log);
+        code_map.trace(&data);
+
+        assert_eq!(code_map.usage(0x200), ByteUsage::Opcode);
+        assert_eq!(code_map.usage(0x202), ByteUsage::Opcode);
+        assert!(!code_map.is_label(0x200));
+        assert_eq!(code_map.usage(0x104), ByteUsage::Data);
+        assert_eq!(code_map.usage(0x105), ByteUsage::Unknown);
+        // Operand of the entry point JP also read as data
+        assert_eq!(code_map.usage(0x102), ByteUsage::Operand);
+    }
+
+    #[test]
+    fn test_writes_rgbds_listing() {
+        let data = create_rom();
+        let mut code_map = CodeMap::new(data.len());
+        code_map.trace(&data);
+
+        let mut output = Vec::new();
+        let symbols = SymbolTable::parse("00:0160 Routine\n");
+
+        write_listing(
+            &mut output,
+            &data,
+            &code_map,
+            &symbols,
+            &[0],
+            Some((0x150, 0x165)),
+        )
+        .unwrap();
+
+        let listing = String::from_utf8(output).unwrap();
+
+        assert!(
+            listing.starts_with(
+                "DEF Label_001_4000 EQU $4000\n\nSECTION \"ROM Bank $000\", ROM0[$0150]"
+            )
+        );
+        assert!(listing.contains("Label_000_0150:\n    LD A,$01"));
+        assert!(listing.contains("CALL Routine"));
+        assert!(listing.contains("JR Label_000_0150"));
+        assert!(listing.contains("Routine:\n    JP Label_001_4000"));
+        assert!(listing.contains("    DB $FF,$FF,$FF,$FF,$FF,$FF,$FF,$FF\n"));
+    }
+
+    /// Names used as operands that are neither labels nor constants defined in the listing.
+    fn undefined_labels(listing: &str) -> Vec<String> {
+        let mut defined = HashSet::new();
+        let mut referenced = Vec::new();
+
+        for line in listing.lines() {
+            let code = line.split(';').next().unwrap();
+
+            if let Some(label) = code.strip_suffix(':') {
+                defined.insert(label.to_string());
+            } else if let Some(constant) = code.strip_prefix("DEF ") {
+                defined.insert(constant.split(' ').next().unwrap().to_string());
+            } else if !code.trim_start().starts_with("SECTION") {
+                referenced.extend(
+                    code.split(|c: char| !c.is_alphanumeric() && c != '_')
+                        .filter(|word| word.starts_with("Label_") || word == &"Routine")
+                        .map(str::to_string),
+                );
+            }
+        }
+
+        referenced.retain(|label| !defined.contains(label));
+        referenced
+    }
+
+    #[test_case(None ; "whole bank")]
+    #[test_case(Some((0x150, 0x158)) ; "range")]
+    fn test_every_referenced_label_is_defined(range: Option<(Word, Word)>) {
+        let mut data = create_rom();
+        // LD A,$18; JR NZ,-3 into the operand of LD, which decodes as JR $0173 from there; JP $0160
+        data[0x150..0x157].copy_from_slice(&[0x3E, 0x18, 0x20, 0xFD, 0xC3, 0x60, 0x01]);
+        let mut code_map = CodeMap::new(data.len());
+        code_map.trace(&data);
+
+        let mut output = Vec::new();
+        let symbols = SymbolTable::parse("00:0160 Routine\n");
+        write_listing(&mut output, &data, &code_map, &symbols, &[0], range).unwrap();
+
+        let listing = String::from_utf8(output).unwrap();
+
+        assert!(
+            listing.contains("    DB $3E\nLabel_000_0151:\n    DB $18\n    JR NZ,Label_000_0151")
+        );
+        assert_eq!(undefined_labels(&listing), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,482 @@
+use crate::bus::address::Address;
+use crate::disassembler::symbols::SymbolTable;
+use crate::memory::memory_sector::ReadMemory;
+use crate::{Byte, SignedByte, Word};
+use std::fmt::{Display, Formatter};
+
+pub mod listing;
+pub mod symbols;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+/// How an instruction hands over control once it has been executed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Next,
+    Jump(Word),
+    ConditionalJump(Word),
+    Call(Word),
+    ConditionalCall(Word),
+    Return,
+    ConditionalReturn,
+    IndirectJump,
+    Illegal,
+}
+
+#[derive(Debug)]
+pub struct Instruction {
+    pub address: Word,
+    pub bank: u16,
+    pub bytes: Vec<Byte>,
+    pub text: String,
+    pub comment: Option<String>,
+    pub flow: Flow,
+}
+
+impl Instruction {
+    pub fn length(&self) -> Word {
+        self.bytes.len() as Word
+    }
+
+    pub fn location(&self) -> String {
+        format_location(self.bank, self.address)
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.comment {
+            Some(comment) => write!(f, "{} ; {}", self.text, comment),
+            None => write!(f, "{}", self.text),
+        }
+    }
+}
+
+/// Formats an address as `bank:addr` when it points to ROM, so banked code can be told apart.
+pub fn format_location(bank: u16, address: Word) -> String {
+    if address < 0x8000 {
+        format!("{:02X}:{:04X}", bank_for_address(bank, address), address)
+    } else {
+        format!("{address:04X}")
+    }
+}
+
+fn bank_for_address(bank: u16, address: Word) -> u16 {
+    if address < 0x4000 { 0 } else { bank }
+}
+
+/// Read-only view over raw ROM data with a given bank mapped at 0x4000-0x7FFF.
+pub struct RomBankView<'a> {
+    data: &'a [Byte],
+    bank: u16,
+}
+
+impl<'a> RomBankView<'a> {
+    pub fn new(data: &'a [Byte], bank: u16) -> Self {
+        Self { data, bank }
+    }
+
+    pub fn offset(&self, address: Word) -> Option<usize> {
+        match address {
+            0x0000..=0x3FFF => Some(address as usize),
+            0x4000..=0x7FFF => {
+                Some(self.bank as usize * ROM_BANK_SIZE + address as usize - ROM_BANK_SIZE)
+            }
+            _ => None,
+        }
+        .filter(|offset| *offset < self.data.len())
+    }
+}
+
+impl ReadMemory for RomBankView<'_> {
+    fn read_byte(&self, position: Word) -> Byte {
+        self.offset(position)
+            .map_or(0xFF, |offset| self.data[offset])
+    }
+}
+
+#[derive(Default)]
+pub struct Disassembler<'a> {
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new() -> Self {
+        Self { symbols: None }
+    }
+
+    pub fn with_symbols(symbols: &'a SymbolTable) -> Self {
+        Self {
+            symbols: Some(symbols),
+        }
+    }
+
+    pub fn disassemble<M: ReadMemory + ?Sized>(
+        &self,
+        memory: &M,
+        address: Word,
+        bank: u16,
+    ) -> Instruction {
+        let op = memory.read_byte(address);
+        let b1 = memory.read_byte(address.wrapping_add(1));
+        let b2 = memory.read_byte(address.wrapping_add(2));
+        let nn: Word = (b2 as Word) << 8 | b1 as Word;
+        let relative = address
+            .wrapping_add(2)
+            .wrapping_add(b1 as SignedByte as Word);
+
+        let mut comment = None;
+
+        let (text, length, flow) = match op {
+            0x00 => ("NOP".to_string(), 1, Flow::Next),
+            0x01 => (format!("LD BC,${nn:04X}"), 3, Flow::Next),
+            0x02 => ("LD [BC],A".to_string(), 1, Flow::Next),
+            0x03 => ("INC BC".to_string(), 1, Flow::Next),
+            0x04 => ("INC B".to_string(), 1, Flow::Next),
+            0x05 => ("DEC B".to_string(), 1, Flow::Next),
+            0x06 => (format!("LD B,${b1:02X}"), 2, Flow::Next),
+            0x07 => ("RLCA".to_string(), 1, Flow::Next),
+            0x08 => (
+                format!("LD [{}],SP", self.operand(nn, bank, &mut comment)),
+                3,
+                Flow::Next,
+            ),
+            0x09 => ("ADD HL,BC".to_string(), 1, Flow::Next),
+            0x0A => ("LD A,[BC]".to_string(), 1, Flow::Next),
+            0x0B => ("DEC BC".to_string(), 1, Flow::Next),
+            0x0C => ("INC C".to_string(), 1, Flow::Next),
+            0x0D => ("DEC C".to_string(), 1, Flow::Next),
+            0x0E => (format!("LD C,${b1:02X}"), 2, Flow::Next),
+            0x0F => ("RRCA".to_string(), 1, Flow::Next),
+
+            0x10 => ("STOP".to_string(), 2, Flow::Next),
+            0x11 => (format!("LD DE,${nn:04X}"), 3, Flow::Next),
+            0x12 => ("LD [DE],A".to_string(), 1, Flow::Next),
+            0x13 => ("INC DE".to_string(), 1, Flow::Next),
+            0x14 => ("INC D".to_string(), 1, Flow::Next),
+            0x15 => ("DEC D".to_string(), 1, Flow::Next),
+            0x16 => (format!("LD D,${b1:02X}"), 2, Flow::Next),
+            0x17 => ("RLA".to_string(), 1, Flow::Next),
+            0x18 => (
+                format!("JR {}", self.target(relative, bank)),
+                2,
+                Flow::Jump(relative),
+            ),
+            0x19 => ("ADD HL,DE".to_string(), 1, Flow::Next),
+            0x1A => ("LD A,[DE]".to_string(), 1, Flow::Next),
+            0x1B => ("DEC DE".to_string(), 1, Flow::Next),
+            0x1C => ("INC E".to_string(), 1, Flow::Next),
+            0x1D => ("DEC E".to_string(), 1, Flow::Next),
+            0x1E => (format!("LD E,${b1:02X}"), 2, Flow::Next),
+            0x1F => ("RRA".to_string(), 1, Flow::Next),
+
+            0x20 | 0x28 | 0x30 | 0x38 => (
+                format!("JR {},{}", condition_name(op), self.target(relative, bank)),
+                2,
+                Flow::ConditionalJump(relative),
+            ),
+            0x21 => (format!("LD HL,${nn:04X}"), 3, Flow::Next),
+            0x22 => ("LD [HL+],A".to_string(), 1, Flow::Next),
+            0x23 => ("INC HL".to_string(), 1, Flow::Next),
+            0x24 => ("INC H".to_string(), 1, Flow::Next),
+            0x25 => ("DEC H".to_string(), 1, Flow::Next),
+            0x26 => (format!("LD H,${b1:02X}"), 2, Flow::Next),
+            0x27 => ("DAA".to_string(), 1, Flow::Next),
+            0x29 => ("ADD HL,HL".to_string(), 1, Flow::Next),
+            0x2A => ("LD A,[HL+]".to_string(), 1, Flow::Next),
+            0x2B => ("DEC HL".to_string(), 1, Flow::Next),
+            0x2C => ("INC L".to_string(), 1, Flow::Next),
+            0x2D => ("DEC L".to_string(), 1, Flow::Next),
+            0x2E => (format!("LD L,${b1:02X}"), 2, Flow::Next),
+            0x2F => ("CPL".to_string(), 1, Flow::Next),
+
+            0x31 => (format!("LD SP,${nn:04X}"), 3, Flow::Next),
+            0x32 => ("LD [HL-],A".to_string(), 1, Flow::Next),
+            0x33 => ("INC SP".to_string(), 1, Flow::Next),
+            0x34 => ("INC [HL]".to_string(), 1, Flow::Next),
+            0x35 => ("DEC [HL]".to_string(), 1, Flow::Next),
+            0x36 => (format!("LD [HL],${b1:02X}"), 2, Flow::Next),
+            0x37 => ("SCF".to_string(), 1, Flow::Next),
+            0x39 => ("ADD HL,SP".to_string(), 1, Flow::Next),
+            0x3A => ("LD A,[HL-]".to_string(), 1, Flow::Next),
+            0x3B => ("DEC SP".to_string(), 1, Flow::Next),
+            0x3C => ("INC A".to_string(), 1, Flow::Next),
+            0x3D => ("DEC A".to_string(), 1, Flow::Next),
+            0x3E => (format!("LD A,${b1:02X}"), 2, Flow::Next),
+            0x3F => ("CCF".to_string(), 1, Flow::Next),
+
+            0x76 => ("HALT".to_string(), 1, Flow::Next),
+            0x40..=0x7F => (
+                format!("LD {},{}", reg_name((op >> 3) & 0x7), reg_name(op & 0x7)),
+                1,
+                Flow::Next,
+            ),
+
+            0x80..=0x87 => (format!("ADD A,{}", reg_name(op & 0x7)), 1, Flow::Next),
+            0x88..=0x8F => (format!("ADC A,{}", reg_name(op & 0x7)), 1, Flow::Next),
+            0x90..=0x97 => (format!("SUB A,{}", reg_name(op & 0x7)), 1, Flow::Next),
+            0x98..=0x9F => (format!("SBC A,{}", reg_name(op & 0x7)), 1, Flow::Next),
+            0xA0..=0xA7 => (format!("AND A,{}", reg_name(op & 0x7)), 1, Flow::Next),
+            0xA8..=0xAF => (format!("XOR A,{}", reg_name(op & 0x7)), 1, Flow::Next),
+            0xB0..=0xB7 => (format!("OR A,{}", reg_name(op & 0x7)), 1, Flow::Next),
+            0xB8..=0xBF => (format!("CP A,{}", reg_name(op & 0x7)), 1, Flow::Next),
+
+            0xC0 | 0xC8 | 0xD0 | 0xD8 => (
+                format!("RET {}", condition_name(op)),
+                1,
+                Flow::ConditionalReturn,
+            ),
+            0xC1 => ("POP BC".to_string(), 1, Flow::Next),
+            0xC2 | 0xCA | 0xD2 | 0xDA => (
+                format!("JP {},{}", condition_name(op), self.target(nn, bank)),
+                3,
+                Flow::ConditionalJump(nn),
+            ),
+            0xC3 => (format!("JP {}", self.target(nn, bank)), 3, Flow::Jump(nn)),
+            0xC4 | 0xCC | 0xD4 | 0xDC => (
+                format!("CALL {},{}", condition_name(op), self.target(nn, bank)),
+                3,
+                Flow::ConditionalCall(nn),
+            ),
+            0xC5 => ("PUSH BC".to_string(), 1, Flow::Next),
+            0xC6 => (format!("ADD A,${b1:02X}"), 2, Flow::Next),
+            0xC9 => ("RET".to_string(), 1, Flow::Return),
+            0xCB => (disassemble_cb(b1), 2, Flow::Next),
+            0xCD => (format!("CALL {}", self.target(nn, bank)), 3, Flow::Call(nn)),
+            0xCE => (format!("ADC A,${b1:02X}"), 2, Flow::Next),
+
+            0xD1 => ("POP DE".to_string(), 1, Flow::Next),
+            0xD5 => ("PUSH DE".to_string(), 1, Flow::Next),
+            0xD6 => (format!("SUB A,${b1:02X}"), 2, Flow::Next),
+            0xD9 => ("RETI".to_string(), 1, Flow::Return),
+            0xDE => (format!("SBC A,${b1:02X}"), 2, Flow::Next),
+
+            0xE0 => (
+                format!(
+                    "LDH [{}],A",
+                    self.operand(0xFF00 | b1 as Word, bank, &mut comment)
+                ),
+                2,
+                Flow::Next,
+            ),
+            0xE1 => ("POP HL".to_string(), 1, Flow::Next),
+            0xE2 => ("LDH [C],A".to_string(), 1, Flow::Next),
+            0xE5 => ("PUSH HL".to_string(), 1, Flow::Next),
+            0xE6 => (format!("AND A,${b1:02X}"), 2, Flow::Next),
+            0xE8 => (format!("ADD SP,{}", b1 as SignedByte), 2, Flow::Next),
+            0xE9 => ("JP HL".to_string(), 1, Flow::IndirectJump),
+            0xEA => (
+                format!("LD [{}],A", self.operand(nn, bank, &mut comment)),
+                3,
+                Flow::Next,
+            ),
+            0xEE => (format!("XOR A,${b1:02X}"), 2, Flow::Next),
+
+            0xF0 => (
+                format!(
+                    "LDH A,[{}]",
+                    self.operand(0xFF00 | b1 as Word, bank, &mut comment)
+                ),
+                2,
+                Flow::Next,
+            ),
+            0xF1 => ("POP AF".to_string(), 1, Flow::Next),
+            0xF2 => ("LDH A,[C]".to_string(), 1, Flow::Next),
+            0xF3 => ("DI".to_string(), 1, Flow::Next),
+            0xF5 => ("PUSH AF".to_string(), 1, Flow::Next),
+            0xF6 => (format!("OR A,${b1:02X}"), 2, Flow::Next),
+            0xF8 => {
+                let offset = b1 as SignedByte;
+                let sign = if offset < 0 { '-' } else { '+' };
+
+                (
+                    format!("LD HL,SP{sign}{}", offset.unsigned_abs()),
+                    2,
+                    Flow::Next,
+                )
+            }
+            0xF9 => ("LD SP,HL".to_string(), 1, Flow::Next),
+            0xFA => (
+                format!("LD A,[{}]", self.operand(nn, bank, &mut comment)),
+                3,
+                Flow::Next,
+            ),
+            0xFB => ("EI".to_string(), 1, Flow::Next),
+            0xFE => (format!("CP A,${b1:02X}"), 2, Flow::Next),
+
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+                let vector = (op & 0x38) as Word;
+
+                (format!("RST ${vector:02X}"), 1, Flow::Call(vector))
+            }
+
+            0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+                comment = Some("illegal opcode".to_string());
+
+                (format!("DB ${op:02X}"), 1, Flow::Illegal)
+            }
+        };
+
+        if comment.is_none()
+            && let Flow::Jump(target)
+            | Flow::ConditionalJump(target)
+            | Flow::Call(target)
+            | Flow::ConditionalCall(target) = flow
+            && self.symbol(target, bank).is_none()
+        {
+            comment = Some(format_location(bank, target));
+        }
+
+        Instruction {
+            address,
+            bank: bank_for_address(bank, address),
+            bytes: (0..length)
+                .map(|offset| memory.read_byte(address.wrapping_add(offset)))
+                .collect(),
+            text,
+            comment,
+            flow,
+        }
+    }
+
+    fn symbol(&self, address: Word, bank: u16) -> Option<&str> {
+        self.symbols?
+            .lookup(bank_for_address(bank, address), address)
+    }
+
+    fn target(&self, address: Word, bank: u16) -> String {
+        self.symbol(address, bank)
+            .map_or_else(|| format!("${address:04X}"), |label| label.to_string())
+    }
+
+    fn operand(&self, address: Word, bank: u16, comment: &mut Option<String>) -> String {
+        if let Some(name) = Address::io_register_name(address) {
+            *comment = Some(name.to_string());
+        }
+
+        self.target(address, bank)
+    }
+}
+
+fn condition_name(op: Byte) -> &'static str {
+    match (op >> 3) & 0x3 {
+        0 => "NZ",
+        1 => "Z",
+        2 => "NC",
+        _ => "C",
+    }
+}
+
+fn reg_name(r: u8) -> &'static str {
+    match r {
+        0 => "B",
+        1 => "C",
+        2 => "D",
+        3 => "E",
+        4 => "H",
+        5 => "L",
+        6 => "[HL]",
+        7 => "A",
+        _ => "?",
+    }
+}
+
+fn disassemble_cb(op: u8) -> String {
+    let reg = reg_name(op & 0x7);
+    match op {
+        0x00..=0x07 => format!("RLC {reg}"),
+        0x08..=0x0F => format!("RRC {reg}"),
+        0x10..=0x17 => format!("RL {reg}"),
+        0x18..=0x1F => format!("RR {reg}"),
+        0x20..=0x27 => format!("SLA {reg}"),
+        0x28..=0x2F => format!("SRA {reg}"),
+        0x30..=0x37 => format!("SWAP {reg}"),
+        0x38..=0x3F => format!("SRL {reg}"),
+        0x40..=0x7F => format!("BIT {},{}", (op - 0x40) >> 3, reg),
+        0x80..=0xBF => format!("RES {},{}", (op - 0x80) >> 3, reg),
+        0xC0..=0xFF => format!("SET {},{}", (op - 0xC0) >> 3, reg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn rom_with(code: &[Byte]) -> Vec<Byte> {
+        let mut data = vec![0; ROM_BANK_SIZE * 4];
+        data[0x150..0x150 + code.len()].copy_from_slice(code);
+
+        data
+    }
+
+    #[test_case(&[0x18, 0xFE], "JR $0150", Flow::Jump(0x150) ; "jr to itself")]
+    #[test_case(&[0x20, 0x05], "JR NZ,$0157", Flow::ConditionalJump(0x157) ; "jr nz forward")]
+    #[test_case(&[0x38, 0xF0], "JR C,$0142", Flow::ConditionalJump(0x142) ; "jr c backwards")]
+    #[test_case(&[0xCD, 0x00, 0x40], "CALL $4000", Flow::Call(0x4000) ; "call")]
+    #[test_case(&[0xFF], "RST $38", Flow::Call(0x38) ; "rst")]
+    #[test_case(&[0xD9], "RETI", Flow::Return ; "reti")]
+    #[test_case(&[0xE9], "JP HL", Flow::IndirectJump ; "jp hl")]
+    #[test_case(&[0xD3], "DB $D3", Flow::Illegal ; "illegal")]
+    fn test_resolves_flow(code: &[Byte], text: &str, flow: Flow) {
+        let data = rom_with(code);
+        let instruction = Disassembler::new().disassemble(&RomBankView::new(&data, 1), 0x150, 1);
+
+        assert_eq!(instruction.text, text);
+        assert_eq!(instruction.flow, flow);
+        assert_eq!(instruction.bytes, code);
+    }
+
+    #[test_case(&[0xCB, 0x7C], "BIT 7,H")]
+    #[test_case(&[0xCB, 0x36], "SWAP [HL]")]
+    #[test_case(&[0xCB, 0xFF], "SET 7,A")]
+    #[test_case(&[0xCB, 0x86], "RES 0,[HL]")]
+    fn test_decodes_cb_prefixed_opcodes(code: &[Byte], text: &str) {
+        let data = rom_with(code);
+        let instruction = Disassembler::new().disassemble(&RomBankView::new(&data, 1), 0x150, 1);
+
+        assert_eq!(instruction.text, text);
+        assert_eq!(instruction.length(), 2);
+    }
+
+    #[test]
+    fn test_annotates_io_registers() {
+        let data = rom_with(&[0xE0, 0x40]);
+        let instruction = Disassembler::new().disassemble(&RomBankView::new(&data, 1), 0x150, 1);
+
+        assert_eq!(instruction.to_string(), "LDH [$FF40],A ; LCDC");
+    }
+
+    #[test]
+    fn test_labels_targets_with_symbols() {
+        let mut data = rom_with(&[]);
+        let symbols = SymbolTable::parse("00:0150 Main\n02:4000 BankedRoutine\n");
+
+        data[2 * ROM_BANK_SIZE..2 * ROM_BANK_SIZE + 3].copy_from_slice(&[0xC3, 0x00, 0x40]);
+        data[0x150..0x153].copy_from_slice(&[0xC3, 0x00, 0x40]);
+
+        let disassembler = Disassembler::with_symbols(&symbols);
+
+        let instruction = disassembler.disassemble(&RomBankView::new(&data, 2), 0x4000, 2);
+        assert_eq!(instruction.to_string(), "JP BankedRoutine");
+        assert_eq!(instruction.location(), "02:4000");
+
+        let instruction = disassembler.disassemble(&RomBankView::new(&data, 1), 0x150, 1);
+        assert_eq!(instruction.to_string(), "JP $4000 ; 01:4000");
+        assert_eq!(instruction.location(), "00:0150");
+    }
+
+    #[test]
+    fn test_rom_bank_view_maps_selected_bank() {
+        let mut data = vec![0; ROM_BANK_SIZE * 4];
+        data[0] = 0x11;
+        data[3 * ROM_BANK_SIZE] = 0x33;
+
+        let view = RomBankView::new(&data, 3);
+
+        assert_eq!(view.read_byte(0x0000), 0x11);
+        assert_eq!(view.read_byte(0x4000), 0x33);
+        assert_eq!(view.read_byte(0xC000), 0xFF);
+        assert_eq!(RomBankView::new(&data, 9).read_byte(0x4000), 0xFF);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::Word;
+use std::collections::HashMap;
+
+/// Labels loaded from an RGBDS or WLA-DX `.sym` file.
+#[derive(Default, Clone)]
+pub struct SymbolTable {
+    labels: HashMap<(u16, Word), String>,
+}
+
+impl SymbolTable {
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    /// Parses `BB:AAAA Label` lines, skipping comments, section headers and malformed entries.
+    pub fn parse(contents: &str) -> Self {
+        let mut symbols = Self::default();
+
+        for line in contents.lines() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+
+            if line.is_empty() || line.starts_with('[') {
+                continue;
+            }
+
+            let Some((location, label)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+
+            let Some((bank, address)) = location.split_once(':') else {
+                continue;
+            };
+
+            if let (Ok(bank), Ok(address)) = (
+                u16::from_str_radix(bank, 16),
+                Word::from_str_radix(address, 16),
+            ) {
+                symbols.insert(bank, address, label.trim());
+            }
+        }
+
+        symbols
+    }
+
+    pub fn insert(&mut self, bank: u16, address: Word, label: &str) {
+        self.labels
+            .entry((bank, address))
+            .or_insert_with(|| label.to_string());
+    }
+
+    pub fn lookup(&self, bank: u16, address: Word) -> Option<&str> {
+        if let Some(label) = self.labels.get(&(bank, address)) {
+            return Some(label);
+        }
+
+        // RAM banks are not tracked while disassembling, so take any bank for non ROM addresses
+        if address >= 0x8000 {
+            return self
+                .labels
+                .iter()
+                .filter(|((_, label_address), _)| *label_address == address)
+                .min_by_key(|((label_bank, _), _)| *label_bank)
+                .map(|(_, label)| label.as_str());
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_rgbds_and_wla_files() {
+        let symbols = SymbolTable::parse(
+            "; File generated by rgblink\n\
+             [labels]\n\
+             00:0150 Main\n\
+             01:4000 Bank1Start ; trailing comment\n\
+             00:C000 wBuffer\n\
+             invalid line\n",
+        );
+
+        assert_eq!(symbols.lookup(0, 0x0150), Some("Main"));
+        assert_eq!(symbols.lookup(1, 0x4000), Some("Bank1Start"));
+        assert_eq!(symbols.lookup(2, 0x4000), None);
+        assert_eq!(symbols.lookup(1, 0xC000), Some("wBuffer"));
+    }
+
+    #[test]
+    fn test_keeps_first_label_for_an_address() {
+        let symbols = SymbolTable::parse("00:0150 Main\n00:0150 Main.loop\n");
+
+        assert_eq!(symbols.lookup(0, 0x0150), Some("Main"));
+    }
+}
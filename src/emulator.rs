@@ -0,0 +1,715 @@
+use crate::Word;
+use crate::cartridge::Cartridge;
+use crate::cpu::Cpu;
+use crate::cpu::registers::CpuRegisters;
+use crate::debug::Debuggable;
+use crate::debug::crash_report::CrashReport;
+use crate::io::registers::IORegisters;
+use crate::memory::Memory;
+use crate::memory::bootstrap_rom::BootstrapRom;
+use crate::model::Model;
+use crate::motherboard::Motherboard;
+use image::RgbaImage;
+use parking_lot::RwLock;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const CRASH_REPORT_STACK_WORDS: Word = 16;
+
+/**
+ * The console without its frontend: the CPU and every component it drives through the events
+ * they schedule. Audio output stays outside, so it can run without a sound device.
+ */
+pub struct Emulator {
+    pub io_registers: Arc<RwLock<IORegisters>>,
+    pub memory: Arc<RwLock<Memory>>,
+    pub cpu: Cpu,
+    pub canvas: Arc<RwLock<RgbaImage>>,
+
+    motherboard: Arc<RwLock<Motherboard>>,
+    bootstrap_path: Option<String>,
+    model: Model,
+    audio_update_possible: bool,
+}
+
+impl Emulator {
+    pub fn new(cartridge: Cartridge, bootstrap_path: Option<&str>, model: Model) -> Self {
+        let bootstrap_rom = BootstrapRom::new_from_optional_path(bootstrap_path);
+        let bootstrap = bootstrap_rom.is_some();
+        let model = model.for_cartridge(&cartridge.header);
+
+        let io_registers = Arc::new(RwLock::new(IORegisters::power_on(model, bootstrap)));
+        let memory = Arc::new(RwLock::new(Memory::new(
+            io_registers.clone(),
+            cartridge,
+            bootstrap_rom,
+            model,
+        )));
+        let motherboard = Arc::new(RwLock::new(Motherboard::new(
+            memory.clone(),
+            io_registers.clone(),
+        )));
+
+        let registers = CpuRegisters::new(bootstrap, model, memory.read().cartridge_header());
+        let canvas = motherboard.read().canvas();
+
+        Self {
+            cpu: Cpu::new(motherboard.clone(), registers),
+            canvas,
+            io_registers,
+            memory,
+            motherboard,
+            bootstrap_path: bootstrap_path.map(|path| path.to_string()),
+            model,
+            audio_update_possible: false,
+        }
+    }
+
+    /**
+     * Power cycle: every component goes back to its power-on state, only the cartridge survives.
+     */
+    pub fn reset(&mut self) {
+        let bootstrap_rom = BootstrapRom::new_from_optional_path(self.bootstrap_path.as_deref());
+        let bootstrap = bootstrap_rom.is_some();
+
+        *self.io_registers.write() = IORegisters::power_on(self.model, bootstrap);
+        self.memory.write().power_cycle(bootstrap_rom, self.model);
+        self.cpu.reset(CpuRegisters::new(
+            bootstrap,
+            self.model,
+            self.memory.read().cartridge_header(),
+        ));
+        self.motherboard.write().reset();
+        self.audio_update_possible = false;
+    }
+
+    /// Frames the PPU has finished since power on.
+    pub fn frame(&self) -> u64 {
+        self.motherboard.read().frame()
+    }
+
+    /**
+     * Runs one instruction, the rest of the console keeping up with each of its M-cycles, and
+     * serves the first pending interrupt. Returns the cycles taken, dispatching the interrupt
+     * included, at normal speed, so half of them in double speed mode.
+     */
+    pub fn step(&mut self, debug: bool, trace: bool) -> u8 {
+        let start = self.cpu.normal_speed_cycles();
+        let cycles_since_start =
+            |emulator: &Emulator| (emulator.cpu.normal_speed_cycles() - start) as u8;
+
+        // Stopped at a breakpoint before running anything
+        if self.cpu.step(debug, trace) == 0 {
+            return 0;
+        }
+
+        let check_vblank;
+        let check_lcd_stat;
+        let check_timer_overflow;
+        let check_joystick;
+
+        // Only the rest of the console catching up can have started a VRAM DMA or updated audio
+        if self.cpu.take_caught_up() {
+            self.audio_update_possible = true;
+
+            let hdma_stall = self.memory.write().take_hdma_stall();
+            if hdma_stall > 0 {
+                let stall = self.io_registers.read().scheduler.to_cpu_cycles(hdma_stall);
+                self.cpu.stall(stall);
+            }
+        }
+
+        // Interrupts wait until the CPU is back
+        if self.cpu.is_stalled() {
+            return cycles_since_start(self);
+        }
+
+        {
+            let io_registers = self.io_registers.read();
+
+            check_vblank =
+                io_registers.interrupt_enable.vblank && io_registers.interrupt_flag.vblank;
+
+            check_lcd_stat =
+                io_registers.interrupt_enable.lcd_stat && io_registers.interrupt_flag.lcd_stat;
+
+            check_timer_overflow = io_registers.interrupt_enable.timer_overflow
+                && io_registers.interrupt_flag.timer_overflow;
+
+            check_joystick = io_registers.interrupt_enable.p10_13_transition
+                && io_registers.interrupt_flag.p10_13_transition;
+        }
+
+        // TODO: Serial transfer
+        if check_vblank {
+            self.cpu.vblank_interrupt();
+        } else if check_lcd_stat {
+            self.cpu.lcd_stat_interrupt();
+        } else if check_timer_overflow {
+            self.cpu.timer_overflow_interrupt();
+        } else if check_joystick {
+            self.cpu.p10_p13_transition_interrupt();
+        }
+
+        cycles_since_start(self)
+    }
+
+    /**
+     * Frame sequencer ticks since the last call, as long as the audio unit has something to catch
+     * up with: ticks or written registers.
+     */
+    pub fn take_audio_update(&mut self) -> Option<u8> {
+        if !std::mem::take(&mut self.audio_update_possible) {
+            return None;
+        }
+
+        self.motherboard.write().take_audio_update()
+    }
+
+    /**
+     * Runs instructions until `is_done` holds after one of them, a breakpoint is hit or
+     * `cycle_limit` cycles have gone by. Returns the cycles run.
+     */
+    pub fn run_until(&mut self, cycle_limit: u64, is_done: impl Fn(&Emulator) -> bool) -> u64 {
+        let mut cycles = 0;
+
+        loop {
+            cycles += self.step(false, false) as u64;
+
+            if is_done(self) || cycles >= cycle_limit || self.cpu.breakpoint_hit().is_some() {
+                return cycles;
+            }
+        }
+    }
+
+    /**
+     * Runs the next instruction and, when it calls a function or an interrupt gets served, the
+     * rest of it until it returns.
+     */
+    pub fn step_over(&mut self, cycle_limit: u64) -> u64 {
+        let depth = self.cpu.call_stack().depth();
+
+        self.run_until(cycle_limit, |emulator| {
+            emulator.cpu.call_stack().depth() <= depth
+        })
+    }
+
+    /**
+     * Runs until the current function returns to its caller. Outside of any known call it runs a
+     * single instruction.
+     */
+    pub fn step_out(&mut self, cycle_limit: u64) -> u64 {
+        let depth = self.cpu.call_stack().depth();
+
+        self.run_until(cycle_limit, |emulator| {
+            depth == 0 || emulator.cpu.call_stack().depth() < depth
+        })
+    }
+
+    /// Whether the CPU is at the first instruction of an interrupt handler it has just jumped to.
+    pub fn is_entering_interrupt(&self) -> bool {
+        self.cpu
+            .call_stack()
+            .innermost()
+            .is_some_and(|frame| frame.interrupt && frame.function.address == self.cpu.registers.pc)
+    }
+
+    /**
+     * The CPU will not run another instruction until reset: it hit an illegal opcode, or it halted
+     * with no interrupt enabled to wake it up.
+     */
+    pub fn is_locked_up(&self) -> bool {
+        self.cpu.is_locked()
+            || (self.cpu.is_halted() && self.io_registers.read().interrupt_enable.value == 0)
+    }
+
+    pub fn write_crash_report(&self, path: &Path, reason: &str) -> std::io::Result<()> {
+        let memory = self.memory.read();
+        let io_registers = self.io_registers.read();
+
+        let stack = (0..CRASH_REPORT_STACK_WORDS)
+            .map(|index| self.cpu.registers.sp.wrapping_add(index * 2))
+            .take_while(|address| *address < 0xFFFF && *address >= self.cpu.registers.sp)
+            .map(|address| {
+                let value = memory.peek_word(address);
+
+                (address, value)
+            })
+            .collect();
+
+        let report = CrashReport {
+            reason,
+            registers: &self.cpu.registers,
+            rom_bank: memory.current_rom_bank(),
+            cycles: self.cpu.cycles(),
+            history: self.cpu.history(),
+            backtrace: self.cpu.call_stack().backtrace(
+                self.cpu.registers.pc,
+                memory.current_rom_bank(),
+                self.cpu.symbols(),
+            ),
+            symbols: self.cpu.symbols(),
+            io_values: io_registers.get_debug_values(),
+            stack,
+        };
+
+        let mut output = BufWriter::new(File::create(path)?);
+        report.write(&mut output)?;
+
+        output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Byte;
+    use crate::bus::address::Address;
+    use crate::cartridge::cartridge_header::CartridgeHeader;
+    use crate::cartridge::cartridge_memory_sector::CartridgeMemorySector;
+    use crate::cpu::registers::WordRegister;
+    use crate::debug::DebugReason;
+    use crate::gpu::color::Color;
+    use crate::io::hdma::Hdma;
+    use crate::io::stat::STATMode;
+    use crate::scheduler::Event;
+    use assert_fs::NamedTempFile;
+    use image::Rgba;
+    use test_case::test_case;
+
+    fn create_emulator(program: &[u8]) -> Emulator {
+        create_emulator_on(Model::Dmg, program)
+    }
+
+    /// The cartridge supports CGB features, so it runs in CGB mode on CGB.
+    fn create_emulator_on(model: Model, program: &[u8]) -> Emulator {
+        let mut data = vec![0; 0x8000];
+        data[0x100..0x100 + program.len()].copy_from_slice(program);
+        data[0x143] = 0x80;
+
+        let header = CartridgeHeader::new_from_data(&data);
+        let cartridge = Cartridge::new(CartridgeMemorySector::new_from_data(data), header);
+
+        Emulator::new(cartridge, None, model)
+    }
+
+    #[test]
+    fn test_illegal_opcode_locks_up_and_is_reported() {
+        // PUSH BC, then an illegal opcode
+        let mut emulator = create_emulator(&[0xC5, 0xD3]);
+        let report_file = NamedTempFile::new("rom.crash.log").unwrap();
+
+        emulator.step(false, false);
+        assert!(!emulator.is_locked_up());
+
+        emulator.step(false, false);
+        assert!(emulator.is_locked_up());
+        assert!(matches!(
+            emulator.cpu.take_lock_reason(),
+            Some(DebugReason::IllegalOpcode(0x0101, 0xD3))
+        ));
+        assert!(emulator.cpu.take_lock_reason().is_none());
+
+        emulator
+            .write_crash_report(report_file.path(), "CPU locked up")
+            .unwrap();
+        let report = std::fs::read_to_string(report_file.path()).unwrap();
+
+        assert!(report.contains("00:0100  PUSH BC"));
+        assert!(report.contains("00:0101  DB $D3"));
+        assert!(report.contains("FFFC  0013"));
+        assert!(report.contains("LCDC"));
+    }
+
+    #[test]
+    fn test_step_over_runs_called_function() {
+        // CALL $0110; NOP; ...; $0110: NOP; RET
+        let mut program = vec![0x00; 0x12];
+        program[0..3].copy_from_slice(&[0xCD, 0x10, 0x01]);
+        program[0x11] = 0xC9;
+        let mut emulator = create_emulator(&program);
+
+        assert_eq!(emulator.step_over(1000), 24 + 4 + 16);
+        assert_eq!(emulator.cpu.registers.pc, 0x0103);
+        assert_eq!(emulator.cpu.call_stack().depth(), 0);
+    }
+
+    #[test]
+    fn test_step_out_returns_to_caller() {
+        let mut program = vec![0x00; 0x12];
+        program[0..3].copy_from_slice(&[0xCD, 0x10, 0x01]);
+        program[0x11] = 0xC9;
+        let mut emulator = create_emulator(&program);
+
+        emulator.step(false, false);
+        assert_eq!(emulator.cpu.backtrace().len(), 2);
+
+        emulator.step_out(1000);
+        assert_eq!(emulator.cpu.registers.pc, 0x0103);
+    }
+
+    #[test]
+    fn test_step_over_gives_up_after_cycle_limit() {
+        // CALL $0110; ...; $0110: JR $0110
+        let mut program = vec![0x00; 0x12];
+        program[0..3].copy_from_slice(&[0xCD, 0x10, 0x01]);
+        program[0x10..0x12].copy_from_slice(&[0x18, 0xFE]);
+        let mut emulator = create_emulator(&program);
+
+        assert!(emulator.step_over(100) >= 100);
+        assert_eq!(emulator.cpu.registers.pc, 0x0110);
+    }
+
+    #[test]
+    fn test_halt_without_enabled_interrupts_locks_up() {
+        let mut emulator = create_emulator(&[0x76]);
+
+        emulator.step(false, false);
+
+        assert!(emulator.is_locked_up());
+    }
+
+    // NOPs, or LD A,$00; LDH [LCDC],A
+    #[test_case(&[0x00, 0x00] ; "lcd on")]
+    #[test_case(&[0x3E, 0x00, 0xE0, 0x40] ; "lcd off")]
+    fn test_lcd_stat_interrupt_dispatch_takes_5_m_cycles(program: &[u8]) {
+        // Then EI
+        let mut program = program.to_vec();
+        program.push(0xFB);
+        let mut emulator = create_emulator(&program);
+        {
+            let mut io_registers = emulator.io_registers.write();
+            io_registers.interrupt_flag.update(0);
+            io_registers.interrupt_enable.update(0b10);
+        }
+
+        emulator.step(false, false);
+        emulator.step(false, false);
+        emulator
+            .io_registers
+            .write()
+            .interrupt_flag
+            .set_lcd_stat(true);
+
+        assert_eq!(emulator.step(false, false), 4 + 20);
+        assert_eq!(emulator.cpu.registers.pc, 0x0048);
+        assert!(!emulator.io_registers.read().interrupt_flag.lcd_stat);
+    }
+
+    #[test]
+    fn test_oam_dma_lasts_160_m_cycles_after_a_delay() {
+        // LD A,$C0; LDH [DMA],A, then NOPs read from WRAM through the DMA
+        let mut emulator = create_emulator(&[0x3E, 0xC0, 0xE0, 0x46]);
+        emulator.step(false, false);
+        emulator.step(false, false);
+        assert!(emulator.io_registers.read().dma.is_active());
+
+        // Written at cycle 16, the last byte is copied at 16 + 4 + 159 * 4, from cycle 20
+        let cycles = emulator.run_until(1000, |emulator| {
+            !emulator.io_registers.read().dma.is_active()
+        });
+
+        assert_eq!(cycles, 636);
+    }
+
+    #[test]
+    fn test_oam_dma_bus_conflicts_change_every_m_cycle() {
+        // LD A,$C0; LDH [DMA],A, then the program runs what the DMA reads: LD A,$42
+        let mut emulator = create_emulator(&[0x3E, 0xC0, 0xE0, 0x46]);
+        emulator.memory.write().write_byte(0xC000, 0x3E);
+        emulator.memory.write().write_byte(0xC001, 0x42);
+
+        for _ in 0..3 {
+            emulator.step(false, false);
+        }
+
+        assert_eq!(emulator.cpu.registers.a, 0x42);
+        assert_eq!(emulator.cpu.registers.pc, 0x0106);
+    }
+
+    #[test]
+    fn test_timer_overflow_is_raised_at_its_cycle() {
+        // LD A,$FF; LDH [TIMA],A; LD A,$05; LDH [TAC],A; NOP
+        let mut emulator = create_emulator(&[0x3E, 0xFF, 0xE0, 0x05, 0x3E, 0x05, 0xE0, 0x07, 0x00]);
+        let timer_overflow =
+            |emulator: &Emulator| emulator.io_registers.read().interrupt_flag.timer_overflow;
+        emulator.io_registers.write().interrupt_flag.update(0);
+
+        // The timer starts when TAC is written, at cycle 36, and counts every 16 cycles. It
+        // overflows at cycle 52, then TMA is loaded an M-cycle later
+        for _ in 0..7 {
+            emulator.step(false, false);
+        }
+        emulator.cpu.catch_up();
+        assert_eq!(emulator.io_registers.read().scheduler.now(), 52);
+        assert_eq!(
+            emulator
+                .memory
+                .read()
+                .read_byte(Address::TIMA_TIMER_COUNTER),
+            0x00
+        );
+        assert!(!timer_overflow(&emulator));
+
+        emulator.step(false, false);
+        assert!(timer_overflow(&emulator));
+    }
+
+    // The timer overflows at cycle 72 and TMA ($40) is loaded at 76
+    #[test_case(&[0x00], 0x05, false ; "while reading 0 cancels the reload")]
+    #[test_case(&[0x00, 0x00], 0x40, true ; "on the reload cycle is ignored")]
+    fn test_writing_tima_after_an_overflow(delay: &[u8], tima: Byte, timer_overflow: bool) {
+        // LD A,$40; LDH [TMA],A; LD A,$FF; LDH [TIMA],A; LD A,$05; LDH [TAC],A; NOPs;
+        // LDH [TIMA],A
+        let mut program = vec![
+            0x3E, 0x40, 0xE0, 0x06, 0x3E, 0xFF, 0xE0, 0x05, 0x3E, 0x05, 0xE0, 0x07,
+        ];
+        program.extend_from_slice(delay);
+        program.extend_from_slice(&[0xE0, 0x05]);
+        let mut emulator = create_emulator(&program);
+        emulator.io_registers.write().interrupt_flag.update(0);
+
+        for _ in 0..7 + delay.len() {
+            emulator.step(false, false);
+        }
+
+        assert_eq!(
+            emulator
+                .memory
+                .read()
+                .read_byte(Address::TIMA_TIMER_COUNTER),
+            tima
+        );
+        assert_eq!(
+            emulator.io_registers.read().interrupt_flag.timer_overflow,
+            timer_overflow
+        );
+    }
+
+    #[test]
+    fn test_frames_last_the_same_cycles_as_the_hardware() {
+        // JR -2
+        let mut emulator = create_emulator(&[0x18, 0xFE]);
+
+        emulator.run_until(u64::MAX, |emulator| emulator.frame() == 1);
+
+        assert_eq!(
+            emulator.run_until(u64::MAX, |emulator| emulator.frame() == 2),
+            70224
+        );
+    }
+
+    // LCD off; BGP = $FF; LCD on; JR -2
+    const RESTART_LCD_WITH_BLACK_SCREEN: [u8; 14] = [
+        0x3E, 0x00, 0xE0, 0x40, 0x3E, 0xFF, 0xE0, 0x47, 0x3E, 0x91, 0xE0, 0x40, 0x18, 0xFE,
+    ];
+
+    #[test]
+    fn test_line_0_after_turning_lcd_on_has_no_oam_scan() {
+        let mut emulator = create_emulator(&RESTART_LCD_WITH_BLACK_SCREEN);
+        let mode = |emulator: &Emulator| emulator.io_registers.read().stat.mode();
+
+        for _ in 0..6 {
+            emulator.step(false, false);
+        }
+
+        // Turned on at cycle 56, mode 3 starts at 56 + 76 and line 1 at 56 + 452
+        let deadline = |emulator: &Emulator| {
+            emulator
+                .io_registers
+                .read()
+                .scheduler
+                .deadline(Event::PpuMode)
+        };
+        assert_eq!(mode(&emulator), STATMode::HBlank);
+        assert_eq!(emulator.io_registers.read().ly.value, 0);
+        assert_eq!(deadline(&emulator), Some(56 + 76));
+
+        emulator.run_until(u64::MAX, |emulator| mode(emulator) == STATMode::LCDTransfer);
+        emulator.run_until(u64::MAX, |emulator| mode(emulator) == STATMode::HBlank);
+        assert_eq!(emulator.io_registers.read().ly.value, 0);
+        assert_eq!(deadline(&emulator), Some(56 + 452));
+    }
+
+    // Turned on at cycle 36, line 1 scans OAM from 36 + 452, a row per M-cycle
+    #[test_case(116, 5 ; "row 5")]
+    #[test_case(121, 10 ; "row 10")]
+    fn test_oam_bug_corrupts_the_row_scanned_in_the_m_cycle_of_the_access(nops: usize, row: usize) {
+        // LCD off; LCD on; NOPs; INC HL, which puts HL on the bus in its second M-cycle
+        let mut program = vec![0x3E, 0x00, 0xE0, 0x40, 0x3E, 0x91, 0xE0, 0x40];
+        program.extend(std::iter::repeat_n(0x00, nops));
+        program.push(0x23);
+        let mut emulator = create_emulator(&program);
+        emulator.cpu.registers.write_word(&WordRegister::HL, 0xFE00);
+
+        emulator.step(false, false);
+        emulator.step(false, false);
+        for position in 0..0xA0 {
+            emulator
+                .memory
+                .write()
+                .write_byte(0xFE00 + position, position as Byte);
+        }
+
+        for _ in 0..2 + nops + 1 {
+            emulator.step(false, false);
+        }
+
+        let memory = emulator.memory.read();
+        let mut corrupted_rows: Vec<_> = (0..0xA0)
+            .filter(|position| memory.peek_byte(0xFE00 + position) != *position as Byte)
+            .map(|position| position / 8)
+            .collect();
+        corrupted_rows.dedup();
+        assert_eq!(corrupted_rows, vec![row as Word]);
+    }
+
+    #[test]
+    fn test_first_frame_after_turning_lcd_on_is_blank() {
+        let mut emulator = create_emulator(&RESTART_LCD_WITH_BLACK_SCREEN);
+        let black = Rgba(Color::black().to_rgba());
+
+        let frame = emulator.frame();
+        emulator.run_until(u64::MAX, |emulator| emulator.frame() == frame + 1);
+        assert!(emulator.canvas.read().pixels().all(|pixel| *pixel != black));
+
+        emulator.run_until(u64::MAX, |emulator| emulator.frame() == frame + 2);
+        assert!(emulator.canvas.read().pixels().all(|pixel| *pixel == black));
+    }
+
+    #[test]
+    fn test_ly_reads_0_for_most_of_line_153() {
+        // JR -2
+        let mut emulator = create_emulator(&[0x18, 0xFE]);
+
+        emulator.run_until(u64::MAX, |emulator| {
+            emulator.io_registers.read().ly.value == 153
+        });
+        emulator.step(false, false);
+
+        let io_registers = emulator.io_registers.read();
+        assert_eq!(io_registers.ly.value, 0);
+        assert_eq!(io_registers.stat.mode(), STATMode::VBlank);
+        assert!(io_registers.stat.coincidence_flag);
+    }
+
+    #[test]
+    fn test_breakpoint_stops_before_instruction_and_resumes() {
+        // LD A,$3C; LD [$C000],A; NOP
+        let mut emulator = create_emulator(&[0x3E, 0x3C, 0xEA, 0x00, 0xC0, 0x00]);
+        emulator.cpu.add_breakpoint("exec 0102".parse().unwrap());
+
+        assert_eq!(emulator.step(false, false), 8);
+        assert!(emulator.cpu.take_breakpoint_hit().is_none());
+
+        assert!(emulator.cpu.is_about_to_run());
+        assert_eq!(emulator.step(false, false), 0);
+        assert_eq!(emulator.cpu.registers.pc, 0x0102);
+        assert!(emulator.cpu.take_breakpoint_hit().is_some());
+        assert!(!emulator.cpu.is_about_to_run());
+
+        assert_eq!(emulator.step(false, false), 16);
+        assert_eq!(emulator.cpu.registers.pc, 0x0105);
+    }
+
+    #[test]
+    fn test_watchpoint_stops_after_access() {
+        // LD A,$3C; LD [$C000],A; NOP
+        let mut emulator = create_emulator(&[0x3E, 0x3C, 0xEA, 0x00, 0xC0, 0x00]);
+        emulator
+            .cpu
+            .add_breakpoint("write C000 if [C000] == $3C".parse().unwrap());
+
+        emulator.step(false, false);
+        assert!(emulator.cpu.take_breakpoint_hit().is_none());
+
+        emulator.step(false, false);
+        let hit = emulator.cpu.take_breakpoint_hit().unwrap();
+
+        assert_eq!(emulator.cpu.registers.pc, 0x0105);
+        assert_eq!(
+            hit.access.map(|access| (access.address, access.value)),
+            Some((0xC000, 0x3C))
+        );
+    }
+
+    #[test_case(Model::Cgb, 0xFE, 2050, 2 ; "cgb")]
+    #[test_case(Model::Dmg, 0xFF, 0, 4 ; "dmg")]
+    fn test_stop_switches_to_double_speed_when_armed(
+        model: Model,
+        key1: Byte,
+        stalled_steps: u32,
+        nop_cycles: u8,
+    ) {
+        // LD A,$01; LDH [$4D],A; STOP; NOP
+        let mut emulator = create_emulator_on(model, &[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x00]);
+
+        for _ in 0..3 {
+            emulator.step(false, false);
+        }
+        assert_eq!(
+            emulator.memory.read().read_byte(Address::KEY1_SPEED_SWITCH),
+            key1
+        );
+
+        let mut steps = 0;
+        while emulator.cpu.is_stalled() {
+            emulator.step(false, false);
+            steps += 1;
+        }
+
+        assert_eq!(steps, stalled_steps);
+        assert_eq!(emulator.step(false, false), nop_cycles);
+        assert_eq!(emulator.cpu.registers.pc, 0x0107);
+    }
+
+    #[test]
+    fn test_general_purpose_hdma_halts_the_cpu() {
+        // LD A,$00; LDH [$55],A; NOP
+        let mut emulator = create_emulator_on(Model::Cgb, &[0x3E, 0x00, 0xE0, 0x55, 0x00]);
+
+        emulator.step(false, false);
+        emulator.step(false, false);
+
+        let mut steps = 0;
+        while emulator.cpu.is_stalled() {
+            assert!(!emulator.cpu.is_about_to_run());
+            emulator.step(false, false);
+            steps += 1;
+        }
+
+        assert_eq!(steps, Hdma::BLOCK_CYCLES / 4);
+        assert_eq!(emulator.cpu.registers.pc, 0x0104);
+        assert!(emulator.cpu.is_about_to_run());
+    }
+
+    #[test]
+    fn test_rest_of_the_console_only_catches_up_when_an_event_is_due() {
+        // LD A,$00; LDH [LCDC],A, then NOPs
+        let mut emulator = create_emulator(&[0x3E, 0x00, 0xE0, 0x40]);
+        emulator.step(false, false);
+        emulator.step(false, false);
+
+        emulator.cpu.catch_up();
+        let (now, cycles_to_next) = {
+            let io_registers = emulator.io_registers.read();
+
+            (
+                io_registers.scheduler.now(),
+                io_registers.scheduler.cpu_cycles_to_next(),
+            )
+        };
+        assert!(cycles_to_next > 4 && cycles_to_next < u64::MAX);
+
+        let mut cycles = 0;
+        while cycles + 4 < cycles_to_next {
+            cycles += emulator.step(false, false) as u64;
+            assert_eq!(emulator.io_registers.read().scheduler.now(), now);
+        }
+
+        cycles += emulator.step(false, false) as u64;
+        assert_eq!(emulator.io_registers.read().scheduler.now(), now + cycles);
+    }
+}
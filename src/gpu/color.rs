@@ -1,4 +1,4 @@
-use crate::Byte;
+use crate::{Byte, Word};
 
 pub struct Color {
     r: Byte,
@@ -23,6 +23,17 @@ impl Color {
         Self::from(pixel_color)
     }
 
+    /// A CGB palette colour: 5 bits each of red, green and blue from the lowest ones.
+    pub fn from_rgb555(value: Word) -> Self {
+        let channel = |shift: Word| {
+            let value = (value >> shift & 0x1F) as Byte;
+
+            value << 3 | value >> 2
+        };
+
+        Self::new(channel(0), channel(5), channel(10))
+    }
+
     pub fn black() -> Self {
         Self::new(0, 0, 0)
     }
@@ -1,26 +1,30 @@
-use std::cmp::{max, min};
 use std::sync::Arc;
 
 use image::{ImageBuffer, Rgba, RgbaImage};
 use parking_lot::RwLock;
 
+use crate::Byte;
 use crate::gpu::color::Color;
+use crate::gpu::pixel_fifo::{PixelFifo, Window};
 use crate::io::registers::IORegisters;
 use crate::io::stat::STATMode;
 use crate::memory::Memory;
 use crate::memory::oam_entry::OamEntry;
-use crate::utils::math::word_to_two_bytes;
-use crate::{Byte, Word};
 
 pub mod color;
-
-type DisplayPixel = [Byte; 4];
+mod pixel_fifo;
 
 pub struct Gpu {
-    cycles_accumulated: u16,
+    frame: u64,
+
+    // Line being drawn in mode 3, and the cycle it started at
+    pixel_fifo: Option<PixelFifo>,
+    transfer_start: u64,
+    window: Window,
 
-    sprites_to_be_drawn_with_priority: Vec<OamEntry>,
-    sprites_to_be_drawn_without_priority: Vec<OamEntry>,
+    // The first frame after turning the LCD on is drawn, but never shown
+    blank_frame: bool,
+    hidden_canvas: RgbaImage,
 
     memory: Arc<RwLock<Memory>>,
     io_registers: Arc<RwLock<IORegisters>>,
@@ -32,395 +36,272 @@ impl Gpu {
 
     const TILE_SIZE_BYTES: u8 = 16;
     const BACKGROUND_MAP_TILE_SIZE_X: u16 = 32;
-    const BACKGROUND_MAP_TILE_SIZE_Y: u16 = 32;
-    const PIXELS_PER_TILE: u16 = 8;
+    // Mode 3 grows into the H-Blank, together they always last the same
+    const TRANSFER_AND_HBLANK_DOTS: u64 = 376;
+    const LAST_LINE: Byte = 153;
+    const SPRITES_PER_LINE: usize = 10;
+    // Dots LY reads 153 for before it wraps
+    const LAST_LINE_DOTS: u64 = 4;
 
     pub fn new(memory: Arc<RwLock<Memory>>, io_registers: Arc<RwLock<IORegisters>>) -> Gpu {
         Gpu {
-            cycles_accumulated: 0,
-            sprites_to_be_drawn_with_priority: Vec::with_capacity(10),
-            sprites_to_be_drawn_without_priority: Vec::with_capacity(10),
+            frame: 0,
+            pixel_fifo: None,
+            transfer_start: 0,
+            window: Window::default(),
+            blank_frame: false,
+            hidden_canvas: ImageBuffer::new(Self::PIXEL_WIDTH as u32, Self::PIXEL_HEIGHT as u32),
             memory,
             io_registers,
         }
     }
 
-    pub fn step(&mut self, last_instruction_cycles: u8, canvas: &mut RgbaImage) {
-        let mode;
-        let lcdc;
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
 
-        {
-            let io_registers = self.io_registers.read();
-            mode = io_registers.stat.mode();
-            lcdc = io_registers.lcdc;
-        }
+    /**
+     * Called at the cycle the PPU asked for, `cycle`, once the CPU has reached `now`. Ends the
+     * current mode, or draws the line further in mode 3. Returns the cycle to be called at next.
+     */
+    pub fn update(&mut self, cycle: u64, now: u64, canvas: &mut RgbaImage) -> u64 {
+        let (mode, lcd_turned_on) = {
+            let mut io_registers = self.io_registers.write();
 
-        if !lcdc.lcd_control_operation {
-            {
-                self.io_registers.write().ly_reset_wo_interrupt();
-            }
-            self.cycles_accumulated = 0;
+            (io_registers.stat.mode(), io_registers.take_lcd_turned_on())
+        };
 
-            return;
+        if lcd_turned_on {
+            self.start_lcd(canvas);
         }
 
-        self.cycles_accumulated += last_instruction_cycles as u16;
-
         match mode {
             // H-blank mode
-            STATMode::HBlank => self.hblank(),
+            STATMode::HBlank if !lcd_turned_on => cycle + self.hblank(),
 
             // V-blank mode
-            STATMode::VBlank => self.vblank(),
+            STATMode::VBlank => cycle + self.vblank(),
 
-            // Searching OAM-RAM mode
-            STATMode::SearchOamRam => self.search_oam_ram(),
+            // Searching OAM-RAM mode, or the start of line 0 after turning the LCD on
+            STATMode::SearchOamRam | STATMode::HBlank => {
+                self.search_oam_ram();
+                self.transfer_start = cycle;
+
+                self.lcd_transfer(now, canvas)
+            }
 
             // Transferring data to LCD Driver mode
-            STATMode::LCDTransfer => self.lcd_transfer(canvas),
+            STATMode::LCDTransfer => self.lcd_transfer(now, canvas),
         }
     }
 
-    fn hblank(&mut self) {
-        if self.cycles_accumulated >= 204 {
-            self.cycles_accumulated = 0;
+    /// A new frame starts, with the screen left blank until it is over.
+    fn start_lcd(&mut self, canvas: &mut RgbaImage) {
+        let white = Rgba(Color::white().to_rgba());
+        canvas.pixels_mut().for_each(|pixel| *pixel = white);
 
-            {
-                let mut io_registers = self.io_registers.write();
-                io_registers.ly_increment();
+        self.blank_frame = true;
+        self.window = Window::default();
+    }
 
-                if io_registers.ly.has_reached_end_of_screen() {
-                    io_registers.set_stat_mode(STATMode::VBlank);
-                } else {
-                    io_registers.set_stat_mode(STATMode::SearchOamRam);
-                }
-            }
+    /// Returns the cycles until the next mode ends.
+    fn hblank(&mut self) -> u64 {
+        let mut io_registers = self.io_registers.write();
+        io_registers.ly_increment();
+
+        if io_registers.ly.has_reached_end_of_screen() {
+            io_registers.set_stat_mode(STATMode::VBlank);
+            self.frame += 1;
+            self.blank_frame = false;
+        } else {
+            io_registers.set_stat_mode(STATMode::SearchOamRam);
         }
+
+        io_registers.stat.mode().cycles()
     }
 
-    fn vblank(&mut self) {
-        if self.cycles_accumulated >= 456 {
-            self.cycles_accumulated = 0;
+    /**
+     * Ends a V-Blank line, or the first dots of line 153: LY already reads 0, and is compared
+     * with LYC as such, for the rest of it. Returns the cycles until the next event.
+     */
+    fn vblank(&mut self) -> u64 {
+        let mut io_registers = self.io_registers.write();
 
-            {
-                let mut io_registers = self.io_registers.write();
+        match io_registers.ly.value {
+            Self::LAST_LINE => {
+                io_registers.ly_reset();
+
+                STATMode::VBlank.cycles() - Self::LAST_LINE_DOTS
+            }
+            0 => {
+                io_registers.set_stat_mode(STATMode::SearchOamRam);
+                self.window = Window::default();
+
+                STATMode::SearchOamRam.cycles()
+            }
+            _ => {
                 io_registers.ly_increment();
 
-                if io_registers.ly.has_reached_end_of_vblank() {
-                    // Enter Searching OAM-RAM mode
-                    io_registers.set_stat_mode(STATMode::SearchOamRam);
-                    io_registers.ly_reset();
+                if io_registers.ly.value == Self::LAST_LINE {
+                    Self::LAST_LINE_DOTS
+                } else {
+                    STATMode::VBlank.cycles()
                 }
             }
         }
     }
 
+    /// Picks the sprites on the line, then starts drawing it.
     fn search_oam_ram(&mut self) {
-        if self.cycles_accumulated < 80 {
-            return;
-        }
-
-        // Enter transferring data to LCD Driver mode
-        self.cycles_accumulated = 0;
-
-        self.sprites_to_be_drawn_with_priority.clear();
-        self.sprites_to_be_drawn_without_priority.clear();
-
-        let obj_sprite_display;
         let obj_sprite_size;
         let ly;
+        let scx;
 
         {
             let mut io_registers = self.io_registers.write();
             io_registers.set_stat_mode(STATMode::LCDTransfer);
 
-            obj_sprite_display = io_registers.lcdc.obj_sprite_display;
             obj_sprite_size = io_registers.lcdc.obj_sprite_size;
             ly = io_registers.ly.value;
-        }
-
-        if !obj_sprite_display {
-            return;
-        }
-
-        let sprite_size = if obj_sprite_size { 16 } else { 8 };
-
-        let oam_ram = { self.memory.read().oam_ram.clone() };
+            scx = io_registers.scx;
 
-        for oam_entry in oam_ram {
-            if oam_entry.x != 0 && ly + 16 >= oam_entry.y && ly + 16 < oam_entry.y + sprite_size {
-                if oam_entry.priority() {
-                    self.sprites_to_be_drawn_with_priority.push(oam_entry);
-                } else {
-                    self.sprites_to_be_drawn_without_priority.push(oam_entry);
-                }
+            // Latched for the rest of the frame, even if WY changes afterwards
+            if ly == io_registers.wy {
+                self.window.y_triggered = true;
             }
         }
 
-        self.sprites_to_be_drawn_with_priority.sort_by_key(|a| a.x);
+        let sprite_height = if obj_sprite_size { 16 } else { 8 };
+        let oam_ram = { self.memory.read().oam_ram.lock().clone() };
 
-        self.sprites_to_be_drawn_without_priority
-            .sort_by_key(|a| a.x);
+        self.pixel_fifo = Some(PixelFifo::new(
+            ly,
+            scx,
+            Self::select_sprites(oam_ram, ly, sprite_height),
+            self.window,
+        ));
     }
 
-    fn lcd_transfer(&mut self, canvas: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
-        if self.cycles_accumulated < 172 {
-            return;
-        }
-
-        self.cycles_accumulated = 0;
-
-        {
-            self.io_registers.write().set_stat_mode(STATMode::HBlank);
-        }
-
-        let lcdc;
-        let scx;
-        let scy;
-        let bgp;
-        let screen_y;
-        let sprite_palette0;
-        let sprite_palette1;
-        let sprite_size;
+    /**
+     * The first sprites in OAM order covering the line, up to the hardware limit. Only Y is
+     * looked at, so sprites hidden at X = 0 or past the right edge take their place all the same.
+     */
+    fn select_sprites(
+        oam_entries: impl Iterator<Item = OamEntry>,
+        ly: Byte,
+        sprite_height: u16,
+    ) -> Vec<OamEntry> {
+        let line = ly as u16 + 16;
+
+        oam_entries
+            .filter(|oam_entry| {
+                (oam_entry.y as u16..oam_entry.y as u16 + sprite_height).contains(&line)
+            })
+            .take(Self::SPRITES_PER_LINE)
+            .collect()
+    }
 
-        {
+    /**
+     * Draws mode 3 up to `now`, so that an I/O register written at `now` only changes the dots
+     * drawn from then on. Ending the mode is left to the next update.
+     */
+    pub fn catch_up(&mut self, now: u64, canvas: &mut RgbaImage) {
+        let drawing = {
             let io_registers = self.io_registers.read();
 
-            lcdc = io_registers.lcdc;
-            scx = io_registers.scx;
-            scy = io_registers.scy;
-            bgp = io_registers.bgp;
-
-            screen_y = io_registers.ly.value as u16;
-            sprite_palette0 = io_registers.obp1;
-            sprite_palette1 = io_registers.obp2;
-
-            sprite_size = if io_registers.lcdc.obj_sprite_size {
-                16i16
-            } else {
-                8i16
-            };
-        }
-
-        if !lcdc.lcd_control_operation {
-            return;
-        }
-
-        let bg_tile_map_start_location = if lcdc.bg_tile_map_display_select {
-            0x9C00
-        } else {
-            0x9800
+            io_registers.lcdc.lcd_control_operation
+                && io_registers.stat.mode() == STATMode::LCDTransfer
         };
 
-        let window_tile_map_start_location = if lcdc.window_tile_map_display_select {
-            0x9C00
-        } else {
-            0x9800
-        };
-
-        let screen_y_with_offset = scy as u16 + screen_y;
-
-        let mut previous_bg_tile_map_location = 0u16;
-        let mut tile_bytes = (0, 0);
-
-        let mut screen_row_no_priority: [Option<DisplayPixel>; Gpu::PIXEL_WIDTH as usize] =
-            [None; Gpu::PIXEL_WIDTH as usize];
-        let mut screen_row_with_priority: [Option<DisplayPixel>; Gpu::PIXEL_WIDTH as usize] =
-            [None; Gpu::PIXEL_WIDTH as usize];
-
-        if lcdc.obj_sprite_display {
-            self.draw_sprites_in_row(
-                false,
-                screen_y,
-                sprite_palette0,
-                sprite_palette1,
-                sprite_size,
-                &mut screen_row_no_priority,
-            );
-
-            self.draw_sprites_in_row(
-                true,
-                screen_y,
-                sprite_palette0,
-                sprite_palette1,
-                sprite_size,
-                &mut screen_row_with_priority,
-            );
-        }
-
-        for screen_x in 0..(Gpu::PIXEL_WIDTH as u16) {
-            let mut pixel_to_write = *screen_row_no_priority.get(screen_x as usize).unwrap();
-
-            if let Some(ptw) = pixel_to_write {
-                canvas.put_pixel(screen_x as u32, screen_y as u32, Rgba(ptw));
-
-                continue;
-            }
-
-            pixel_to_write = *screen_row_with_priority.get(screen_x as usize).unwrap();
-
-            if lcdc.bg_display {
-                let screen_x_with_offset = ((screen_x as u8).wrapping_add(scx)) as u16;
-                let tile_x;
-                let bg_tile_map_location;
-                let tile_row;
-
-                let wy;
-                let wx;
-
-                {
-                    let io_registers = self.io_registers.read();
-                    wy = io_registers.wy;
-                    wx = io_registers.wx;
-                }
-
-                // Window
-                if lcdc.window_display && wy <= screen_y as Byte && wx <= (screen_x + 7) as Byte {
-                    let last_window_rendered_position_x: u16 = screen_x + 7 - wx as u16;
-
-                    let last_window_rendered_position_y = screen_y - wy as u16;
-
-                    bg_tile_map_location = window_tile_map_start_location
-                        + (((last_window_rendered_position_y / Gpu::PIXELS_PER_TILE)
-                            * Gpu::BACKGROUND_MAP_TILE_SIZE_X)
-                            % (Gpu::BACKGROUND_MAP_TILE_SIZE_X * Gpu::BACKGROUND_MAP_TILE_SIZE_Y))
-                        + (last_window_rendered_position_x / Gpu::PIXELS_PER_TILE);
-
-                    tile_x = last_window_rendered_position_x % 8;
-                    tile_row = last_window_rendered_position_y % 8;
-                } else {
-                    // Background
-                    bg_tile_map_location = bg_tile_map_start_location
-                        + (((screen_y_with_offset / Gpu::PIXELS_PER_TILE)
-                            * Gpu::BACKGROUND_MAP_TILE_SIZE_X)
-                            % (Gpu::BACKGROUND_MAP_TILE_SIZE_X * Gpu::BACKGROUND_MAP_TILE_SIZE_Y))
-                        + (screen_x_with_offset / Gpu::PIXELS_PER_TILE);
-
-                    tile_x = screen_x_with_offset % 8;
-                    tile_row = screen_y_with_offset % 8;
-                }
-
-                if previous_bg_tile_map_location != bg_tile_map_location {
-                    let bg_tile_map = { self.memory.read().read_byte(bg_tile_map_location) };
-
-                    let bg_data_location = match lcdc.bg_and_window_tile_data_select {
-                        true => 0x8000 + bg_tile_map as Word * Gpu::TILE_SIZE_BYTES as Word,
-                        false => {
-                            (if bg_tile_map >= 0b10000000 {
-                                0x8800
-                            } else {
-                                0x9000
-                            }) + (bg_tile_map & 0b01111111) as Word * Gpu::TILE_SIZE_BYTES as Word
-                        }
-                    };
-
-                    tile_bytes = self.read_tile_row(bg_data_location, tile_row);
-
-                    previous_bg_tile_map_location = bg_tile_map_location;
-                }
-
-                let pixel = self.read_pixel_from_tile(tile_x, tile_bytes);
-
-                if pixel != 0x0 || pixel_to_write.is_none() {
-                    let color = Color::from_pixel(pixel, bgp);
-
-                    pixel_to_write = Some(color.to_rgba());
-                }
-            }
-
-            if let Some(ptw) = pixel_to_write {
-                canvas.put_pixel(screen_x as u32, screen_y as u32, Rgba(ptw));
-            }
+        if drawing {
+            self.draw_transfer(now, canvas);
         }
     }
 
-    fn read_pixel_from_tile(&self, bit_offset: u16, (byte1, byte2): (Byte, Byte)) -> Byte {
-        let bit_pos = 7 - bit_offset;
-
-        let pixel_bit1 = (byte1 >> bit_pos) & 0b1;
-        let pixel_bit0 = (byte2 >> bit_pos) & 0b1;
-
-        ((pixel_bit1 << 1) | pixel_bit0) & 0b11
-    }
+    /**
+     * Draws the dots of the line up to `now`. Returns when to carry on: the earliest the line can
+     * be finished, as register writes catch up with it on their own, or at the end of the H-Blank
+     * that follows the line.
+     */
+    fn lcd_transfer(&mut self, now: u64, canvas: &mut RgbaImage) -> u64 {
+        if !self.draw_transfer(now, canvas) {
+            let pixel_fifo = self
+                .pixel_fifo
+                .as_ref()
+                .expect("Mode 3 is only entered with a line to draw");
+
+            return self.transfer_start + (pixel_fifo.dots() + pixel_fifo.min_dots_left()) as u64;
+        }
 
-    fn read_tile_row(&self, tile_address: Word, row: u16) -> (Byte, Byte) {
-        let word = { self.memory.read().read_word(tile_address + row * 2) };
+        let pixel_fifo = self
+            .pixel_fifo
+            .take()
+            .expect("Mode 3 is only entered with a line to draw");
+        self.window = pixel_fifo.window();
+        self.io_registers.write().set_stat_mode(STATMode::HBlank);
 
-        word_to_two_bytes(word)
+        self.transfer_start + Self::TRANSFER_AND_HBLANK_DOTS
     }
 
-    fn draw_sprites_in_row(
-        &self,
-        priority: bool,
-        screen_y: u16,
-        palette0: Byte,
-        palette1: Byte,
-        sprite_size: i16,
-        screen_row: &mut [Option<DisplayPixel>],
-    ) {
-        const SPRITE_TILES_ADDR_START: u16 = 0x8000;
-
-        let sprites_to_be_drawn = match priority {
-            true => &self.sprites_to_be_drawn_with_priority,
-            false => &self.sprites_to_be_drawn_without_priority,
+    /// Runs the dots before `now` not run yet. Returns whether the line is finished.
+    fn draw_transfer(&mut self, now: u64, canvas: &mut RgbaImage) -> bool {
+        let pixel_fifo = self
+            .pixel_fifo
+            .as_mut()
+            .expect("Mode 3 is only entered with a line to draw");
+        let canvas = if self.blank_frame {
+            &mut self.hidden_canvas
+        } else {
+            canvas
         };
 
-        let mut screen_x = -1;
-
-        for sprite in sprites_to_be_drawn {
-            screen_x = max(screen_x + 1, sprite.x as i16 - Gpu::PIXELS_PER_TILE as i16);
+        let memory = self.memory.read();
+        let io_registers = self.io_registers.read();
+        let mut finished = pixel_fifo.is_finished();
 
-            let current_pixel_y: i16 =
-                screen_y as i16 + (Gpu::PIXELS_PER_TILE * 2) as i16 - sprite.y as i16;
-
-            let sprite_addr =
-                SPRITE_TILES_ADDR_START + sprite.tile_number as u16 * Gpu::TILE_SIZE_BYTES as u16;
-
-            let row = if sprite.flip_y() {
-                sprite_size - 1 - current_pixel_y
-            } else {
-                current_pixel_y
-            } as Word;
-
-            let tile_row = self.read_tile_row(sprite_addr, row);
+        while !finished && self.transfer_start + (pixel_fifo.dots() as u64) < now {
+            finished = pixel_fifo.tick(&memory, &io_registers, canvas);
+        }
 
-            let limit = min(sprite.x as i16, Gpu::PIXEL_WIDTH as i16);
-            let mut sprite_end = screen_x;
+        finished
+    }
+}
 
-            let palette = if !sprite.palette() {
-                palette0
-            } else {
-                palette1
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            for current_screen_x in screen_x..limit {
-                let current_pixel_x: i16 =
-                    current_screen_x + Gpu::PIXELS_PER_TILE as i16 - sprite.x as i16;
+    #[test]
+    fn test_selects_first_ten_sprites_on_the_line_in_oam_order() {
+        let oam_entries = (0..40).map(|index| {
+            // Every other sprite is on line 0, the first one hidden at X = 0
+            let y = if index % 2 == 0 { 16 } else { 40 };
 
-                if !(0..8).contains(&current_pixel_x) {
-                    continue;
-                }
+            OamEntry::with_bytes(y, index * 4, index, 0)
+        });
 
-                let pixel = self.read_pixel_from_tile(
-                    if sprite.flip_x() {
-                        7 - current_pixel_x
-                    } else {
-                        current_pixel_x
-                    } as Word,
-                    tile_row,
-                );
-
-                if pixel == 0 {
-                    continue;
-                }
+        let sprites = Gpu::select_sprites(oam_entries, 0, 8);
 
-                let color = Color::from_pixel(pixel, palette);
+        assert_eq!(
+            sprites
+                .iter()
+                .map(|sprite| sprite.tile_number)
+                .collect::<Vec<_>>(),
+            vec![0, 2, 4, 6, 8, 10, 12, 14, 16, 18]
+        );
+    }
 
-                screen_row[current_screen_x as usize] = Some(color.to_rgba());
-                sprite_end = current_screen_x;
-            }
+    #[test]
+    fn test_tall_sprites_cover_sixteen_lines() {
+        let oam_entries = || {
+            [
+                OamEntry::with_bytes(2, 8, 0, 0),
+                OamEntry::with_bytes(1, 8, 1, 0),
+            ]
+            .into_iter()
+        };
 
-            screen_x = sprite_end;
-        }
+        assert_eq!(Gpu::select_sprites(oam_entries(), 1, 8).len(), 0);
+        assert_eq!(Gpu::select_sprites(oam_entries(), 1, 16)[0].tile_number, 0);
     }
 }
@@ -0,0 +1,938 @@
+use std::collections::VecDeque;
+
+use image::{Rgba, RgbaImage};
+
+use crate::gpu::Gpu;
+use crate::gpu::color::Color;
+use crate::io::registers::IORegisters;
+use crate::memory::Memory;
+use crate::memory::oam_entry::OamEntry;
+use crate::{Byte, Word};
+
+/// The first tile of each line is fetched twice, the first time only to be thrown away.
+const DISCARDED_FETCH_DOTS: u8 = 6;
+/// Dots a sprite fetch always takes, on top of waiting for the background fetcher.
+const SPRITE_FETCH_DOTS: u8 = 6;
+/// The background fetcher is done with a tile this many pixels before its end.
+const FETCHER_DONE_PIXELS: u8 = 2;
+/// Dots the fetcher spends reading each of the tile number and both bitplanes.
+const FETCH_STEP_DOTS: u8 = 2;
+
+/**
+ * Window state carried over from line to line. The window only shows once LY has matched WY during
+ * the frame, and its rows come from an internal counter that only advances on lines it was drawn
+ * on, so hiding it for a few lines does not skip any of its rows.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct Window {
+    pub y_triggered: bool,
+    pub line: Byte,
+    // WX = 166 left the window on at the end of the previous line, so it covers all of this one
+    pub from_left_edge: bool,
+}
+
+impl Window {
+    /// The window reaches past the right edge at 166, and starts right at the left one at 7.
+    const WX_RIGHT_EDGE: Byte = 166;
+    const WX_LEFT_EDGE: Byte = 7;
+}
+
+#[derive(Clone, Copy)]
+struct BackgroundPixel {
+    color: Byte,
+    // CGB mode only, from the BG map attributes
+    palette: Byte,
+    priority: bool,
+}
+
+#[derive(Clone, Copy)]
+struct SpritePixel {
+    color: Byte,
+    // OBP0/OBP1 on DMG, one of the 8 OBJ colour palettes in CGB mode
+    palette: Byte,
+    behind_background: bool,
+    // Position in OAM among the sprites of the line, which decides overlaps in CGB mode
+    oam_order: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FetcherStep {
+    TileNumber,
+    TileDataLow,
+    TileDataHigh,
+    Push,
+}
+
+/**
+ * Background and window fetcher: reads a tile number and both bitplanes of its row, then waits for
+ * the FIFO to run empty to push the eight pixels. Registers are read as each step happens, so
+ * writes in the middle of a line take effect from the next tile on. In CGB mode the attributes
+ * of the tile come from VRAM bank 1, at the same position of the map as its number.
+ */
+struct Fetcher {
+    step: FetcherStep,
+    step_dots: u8,
+    window: bool,
+    // Tiles pushed since the line or the window started
+    tile_x: Byte,
+    tile_row_address: Word,
+    attributes: Byte,
+    low: Byte,
+    high: Byte,
+}
+
+impl Fetcher {
+    fn new(window: bool) -> Self {
+        Self {
+            step: FetcherStep::TileNumber,
+            step_dots: 0,
+            window,
+            tile_x: 0,
+            tile_row_address: 0,
+            attributes: 0,
+            low: 0,
+            high: 0,
+        }
+    }
+
+    fn tick(
+        &mut self,
+        memory: &Memory,
+        io_registers: &IORegisters,
+        line: Byte,
+        background: &mut VecDeque<BackgroundPixel>,
+    ) {
+        if self.step == FetcherStep::Push {
+            if background.is_empty() {
+                let flip_x = self.attributes & 0x20 == 0x20;
+
+                background.extend((0..8).map(|pixel| {
+                    let bit = if flip_x { pixel } else { 7 - pixel };
+
+                    BackgroundPixel {
+                        color: pixel_color(self.low, self.high, bit),
+                        palette: self.attributes & 0x07,
+                        priority: self.attributes & 0x80 == 0x80,
+                    }
+                }));
+
+                self.tile_x = self.tile_x.wrapping_add(1);
+                self.step = FetcherStep::TileNumber;
+            }
+
+            return;
+        }
+
+        self.step_dots += 1;
+
+        if self.step_dots < FETCH_STEP_DOTS {
+            return;
+        }
+
+        self.step_dots = 0;
+
+        match self.step {
+            FetcherStep::TileNumber => {
+                self.tile_row_address = self.tile_row_address(memory, io_registers, line);
+                self.step = FetcherStep::TileDataLow;
+            }
+            FetcherStep::TileDataLow => {
+                self.low = memory.peek_vram(self.vram_bank(), self.tile_row_address);
+                self.step = FetcherStep::TileDataHigh;
+            }
+            FetcherStep::TileDataHigh => {
+                self.high = memory.peek_vram(self.vram_bank(), self.tile_row_address + 1);
+                self.step = FetcherStep::Push;
+            }
+            FetcherStep::Push => {}
+        }
+    }
+
+    fn vram_bank(&self) -> usize {
+        (self.attributes as usize >> 3) & 1
+    }
+
+    /// Address of the row of the next tile to draw, `line` counting from the top of the window
+    /// when drawing it. Reads the attributes of the tile as well.
+    fn tile_row_address(
+        &mut self,
+        memory: &Memory,
+        io_registers: &IORegisters,
+        line: Byte,
+    ) -> Word {
+        let lcdc = &io_registers.lcdc;
+
+        let (tile_map, column, row) = if self.window {
+            (lcdc.window_tile_map_display_select, self.tile_x, line)
+        } else {
+            (
+                lcdc.bg_tile_map_display_select,
+                (io_registers.scx / 8).wrapping_add(self.tile_x),
+                line.wrapping_add(io_registers.scy),
+            )
+        };
+
+        let tile_map_address = if tile_map { 0x9C00 } else { 0x9800 }
+            + (row / 8) as Word * Gpu::BACKGROUND_MAP_TILE_SIZE_X
+            + (column as Word % Gpu::BACKGROUND_MAP_TILE_SIZE_X);
+        let tile_number = memory.peek_vram(0, tile_map_address);
+        self.attributes = if io_registers.is_cgb_mode() {
+            memory.peek_vram(1, tile_map_address)
+        } else {
+            0
+        };
+        let tile_row = if self.attributes & 0x40 == 0x40 {
+            7 - row % 8
+        } else {
+            row % 8
+        };
+
+        let tile_address = if lcdc.bg_and_window_tile_data_select {
+            0x8000 + tile_number as Word * Gpu::TILE_SIZE_BYTES as Word
+        } else {
+            0x9000u16.wrapping_add_signed(tile_number as i8 as i16 * Gpu::TILE_SIZE_BYTES as i16)
+        };
+
+        tile_address + tile_row as Word * 2
+    }
+}
+
+/**
+ * Mode 3 of one scanline, run a dot at a time: the fetcher fills the background FIFO, which shifts
+ * a pixel out to the LCD on every dot it is not empty. The fine horizontal scroll drops pixels at
+ * the start of the line, the window restarts the fetcher and each sprite stalls the FIFO while it
+ * is fetched, so the mode lasts longer than its minimum of 172 dots as on hardware. Palettes are
+ * applied as pixels are shifted out.
+ */
+pub struct PixelFifo {
+    ly: Byte,
+    dots: u16,
+    // Dots left of a fetch being thrown away
+    stalled_dots: Byte,
+    // Pixels shifted out to the LCD so far
+    x: Byte,
+    // Background pixels still to drop for the fine horizontal scroll
+    discarded_pixels: Byte,
+    window: Window,
+    background: VecDeque<BackgroundPixel>,
+    sprite_pixels: VecDeque<Option<SpritePixel>>,
+    fetcher: Fetcher,
+    // Sprites on the line not fetched yet, by X, along with their order in OAM
+    sprites: VecDeque<(usize, OamEntry)>,
+    // Sprite being fetched, with the dots left of it
+    sprite_fetch: Option<((usize, OamEntry), Byte)>,
+    // Fine horizontal scroll, and the last tile a sprite already waited on the fetcher for
+    fine_scroll: Byte,
+    waited_tile: Option<(bool, i16)>,
+}
+
+impl PixelFifo {
+    /**
+     * Starts a line with the sprites selected for it in OAM order. The fine horizontal scroll is
+     * only read here.
+     */
+    pub fn new(ly: Byte, scx: Byte, sprites: Vec<OamEntry>, window: Window) -> Self {
+        let mut sprites: Vec<_> = sprites.into_iter().enumerate().collect();
+        sprites.sort_by_key(|(_, sprite)| sprite.x);
+
+        Self {
+            ly,
+            dots: 0,
+            stalled_dots: DISCARDED_FETCH_DOTS,
+            x: 0,
+            discarded_pixels: scx % 8,
+            window,
+            background: VecDeque::with_capacity(16),
+            sprite_pixels: VecDeque::with_capacity(8),
+            fetcher: Fetcher::new(false),
+            sprites: sprites.into(),
+            sprite_fetch: None,
+            fine_scroll: scx % 8,
+            waited_tile: None,
+        }
+    }
+
+    pub fn dots(&self) -> u16 {
+        self.dots
+    }
+
+    /// Dots the line takes at least to be finished, one per pixel still to shift out.
+    pub fn min_dots_left(&self) -> u16 {
+        Gpu::PIXEL_WIDTH.saturating_sub(self.x) as u16
+    }
+
+    /// Whether the last pixel of the line has been shifted out.
+    pub fn is_finished(&self) -> bool {
+        self.x >= Gpu::PIXEL_WIDTH
+    }
+
+    /// Window state for the next line, once this one is finished.
+    pub fn window(&self) -> Window {
+        self.window
+    }
+
+    /**
+     * Runs one dot. Returns whether it shifted out the last pixel of the line.
+     */
+    pub fn tick(
+        &mut self,
+        memory: &Memory,
+        io_registers: &IORegisters,
+        canvas: &mut RgbaImage,
+    ) -> bool {
+        self.dots += 1;
+
+        if self.stalled_dots > 0 {
+            self.stalled_dots -= 1;
+            return false;
+        }
+
+        if self.sprite_fetch.is_some() || self.start_sprite_fetch(io_registers) {
+            self.fetch_sprite(memory, io_registers);
+            return false;
+        }
+
+        self.start_window(io_registers);
+
+        let line = self.fetcher_line();
+        self.fetcher
+            .tick(memory, io_registers, line, &mut self.background);
+
+        let Some(background) = self.background.pop_front() else {
+            return false;
+        };
+
+        if self.discarded_pixels > 0 {
+            self.discarded_pixels -= 1;
+            return false;
+        }
+
+        let sprite = self.sprite_pixels.pop_front().flatten();
+
+        canvas.put_pixel(
+            self.x as u32,
+            self.ly as u32,
+            Rgba(Self::mix(background, sprite, io_registers)),
+        );
+        self.x += 1;
+
+        if self.x < Gpu::PIXEL_WIDTH {
+            return false;
+        }
+
+        self.finish_window(io_registers);
+
+        true
+    }
+
+    fn fetcher_line(&self) -> Byte {
+        if self.fetcher.window {
+            self.window.line
+        } else {
+            self.ly
+        }
+    }
+
+    /// Takes the next sprite when the pixel about to be shifted out is its first one.
+    fn start_sprite_fetch(&mut self, io_registers: &IORegisters) -> bool {
+        while self
+            .sprites
+            .front()
+            .is_some_and(|(_, sprite)| sprite.x <= self.x + 8)
+        {
+            let sprite = self.sprites.pop_front();
+
+            if let Some(sprite) = sprite
+                && io_registers.lcdc.obj_sprite_display
+            {
+                let dots = self.sprite_fetch_dots(&sprite.1, io_registers);
+                self.sprite_fetch = Some((sprite, dots));
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /**
+     * The whole pipeline stalls while a sprite is fetched. Besides its own dots, the first sprite
+     * over a background or window tile waits for the fetcher to be done with that tile, which
+     * takes longer the further left on the tile the sprite starts.
+     */
+    fn sprite_fetch_dots(&mut self, sprite: &OamEntry, io_registers: &IORegisters) -> Byte {
+        // Position of the leftmost pixel of the sprite among the tiles being fetched
+        let position = if self.fetcher.window {
+            sprite.x as i16 - io_registers.wx as i16 - 1
+        } else {
+            sprite.x as i16 + self.fine_scroll as i16
+        };
+        let tile = (self.fetcher.window, position.div_euclid(8));
+
+        if self.waited_tile == Some(tile) {
+            return SPRITE_FETCH_DOTS;
+        }
+
+        self.waited_tile = Some(tile);
+        // Pixels of the tile right of the leftmost one of the sprite
+        let pixels_left = 7 - position.rem_euclid(8) as Byte;
+
+        SPRITE_FETCH_DOTS + pixels_left.saturating_sub(FETCHER_DONE_PIXELS)
+    }
+
+    fn fetch_sprite(&mut self, memory: &Memory, io_registers: &IORegisters) {
+        let Some((_, dots)) = self.sprite_fetch.as_mut() else {
+            return;
+        };
+
+        *dots -= 1;
+
+        if *dots > 0 {
+            return;
+        }
+
+        if let Some(((oam_order, sprite), _)) = self.sprite_fetch.take() {
+            self.merge_sprite(oam_order, &sprite, memory, io_registers);
+        }
+    }
+
+    /**
+     * Sprite pixels only go where no earlier sprite left an opaque one. In CGB mode the one first
+     * in OAM wins instead, whatever their X.
+     */
+    fn merge_sprite(
+        &mut self,
+        oam_order: usize,
+        sprite: &OamEntry,
+        memory: &Memory,
+        io_registers: &IORegisters,
+    ) {
+        let cgb_mode = io_registers.is_cgb_mode();
+        let height = if io_registers.lcdc.obj_sprite_size {
+            16
+        } else {
+            8
+        };
+        let line = self.ly as i16 + 16 - sprite.y as i16;
+
+        if !(0..height).contains(&line) {
+            return;
+        }
+
+        let row = if sprite.flip_y() {
+            height - 1 - line
+        } else {
+            line
+        } as Word;
+        // Tall sprites use an even tile and the one after it
+        let tile_number = if height == 16 {
+            sprite.tile_number & 0xFE
+        } else {
+            sprite.tile_number
+        };
+        let address = 0x8000 + tile_number as Word * Gpu::TILE_SIZE_BYTES as Word + row * 2;
+        let (bank, palette) = if cgb_mode {
+            (sprite.vram_bank(), sprite.cgb_palette())
+        } else {
+            (0, sprite.palette() as Byte)
+        };
+        let low = memory.peek_vram(bank, address);
+        let high = memory.peek_vram(bank, address + 1);
+
+        self.sprite_pixels.resize(8, None);
+
+        for pixel in 0..8 {
+            // Pixels left of the current position have already been shifted out
+            let Ok(slot) = usize::try_from(sprite.x as i16 - 8 + pixel - self.x as i16) else {
+                continue;
+            };
+
+            let bit = if sprite.flip_x() { pixel } else { 7 - pixel } as Byte;
+            let color = pixel_color(low, high, bit);
+
+            let replaces = self.sprite_pixels[slot]
+                .is_none_or(|pixel| cgb_mode && oam_order < pixel.oam_order);
+
+            if color != 0 && replaces {
+                self.sprite_pixels[slot] = Some(SpritePixel {
+                    color,
+                    palette,
+                    behind_background: sprite.priority(),
+                    oam_order,
+                });
+            }
+        }
+    }
+
+    /**
+     * Once the window is reached the FIFO is cleared and the fetcher starts over on its tiles.
+     * Below a WX of 7 the window starts left of the screen, so its first pixels are dropped.
+     */
+    fn start_window(&mut self, io_registers: &IORegisters) {
+        let wx = io_registers.wx;
+        let from_left_edge =
+            self.x == 0 && (self.window.from_left_edge || wx < Window::WX_LEFT_EDGE);
+
+        if self.fetcher.window
+            || self.discarded_pixels > 0
+            || !io_registers.lcdc.window_display
+            || !self.window.y_triggered
+            || (!from_left_edge && self.x + Window::WX_LEFT_EDGE != wx)
+        {
+            return;
+        }
+
+        if self.x == 0 {
+            // At the left edge the first background fetch is thrown away as well
+            if self.background.is_empty() {
+                self.stalled_dots = DISCARDED_FETCH_DOTS;
+            }
+
+            if !self.window.from_left_edge {
+                self.discarded_pixels = Window::WX_LEFT_EDGE.saturating_sub(wx);
+            }
+        }
+
+        self.background.clear();
+        self.fetcher = Fetcher::new(true);
+    }
+
+    /// Moves the window to its next row if it was drawn on this line.
+    fn finish_window(&mut self, io_registers: &IORegisters) {
+        self.window.from_left_edge =
+            self.fetcher.window && io_registers.wx == Window::WX_RIGHT_EDGE;
+
+        if self.fetcher.window {
+            self.window.line = self.window.line.wrapping_add(1);
+        }
+    }
+
+    fn mix(
+        background: BackgroundPixel,
+        sprite: Option<SpritePixel>,
+        io_registers: &IORegisters,
+    ) -> [Byte; 4] {
+        if io_registers.is_cgb_mode() {
+            return Self::mix_cgb(background, sprite, io_registers);
+        }
+
+        let lcdc = &io_registers.lcdc;
+        // With the background off, it is blank, and never above sprites
+        let color = if lcdc.bg_display { background.color } else { 0 };
+
+        match sprite {
+            Some(sprite)
+                if lcdc.obj_sprite_display && !(sprite.behind_background && color != 0) =>
+            {
+                let palette = if sprite.palette == 1 {
+                    io_registers.obp2
+                } else {
+                    io_registers.obp1
+                };
+
+                Color::from_pixel(sprite.color, palette).to_rgba()
+            }
+            _ if !lcdc.bg_display => Color::white().to_rgba(),
+            _ => Color::from_pixel(color, io_registers.bgp).to_rgba(),
+        }
+    }
+
+    /**
+     * In CGB mode LCDC bit 0 no longer hides the background, but takes the priority away from it
+     * and its attributes, so that sprites are always on top.
+     */
+    fn mix_cgb(
+        background: BackgroundPixel,
+        sprite: Option<SpritePixel>,
+        io_registers: &IORegisters,
+    ) -> [Byte; 4] {
+        let lcdc = &io_registers.lcdc;
+
+        match sprite {
+            Some(sprite)
+                if lcdc.obj_sprite_display
+                    && (background.color == 0
+                        || !lcdc.bg_display
+                        || !(sprite.behind_background || background.priority)) =>
+            {
+                io_registers
+                    .obj_palettes
+                    .color(sprite.palette, sprite.color)
+                    .to_rgba()
+            }
+            _ => io_registers
+                .bg_palettes
+                .color(background.palette, background.color)
+                .to_rgba(),
+        }
+    }
+}
+
+fn pixel_color(low: Byte, high: Byte, bit: Byte) -> Byte {
+    ((high >> bit) & 1) << 1 | ((low >> bit) & 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::address::Address;
+    use crate::cartridge::Cartridge;
+    use crate::cartridge::cartridge_header::CartridgeHeader;
+    use crate::cartridge::cartridge_memory_sector::CartridgeMemorySector;
+    use crate::memory::memory_sector::WriteMemory;
+    use crate::model::Model;
+    use image::ImageBuffer;
+    use std::sync::Arc;
+    use test_case::test_case;
+
+    fn create_memory() -> Memory {
+        let data = vec![0; 0x8000];
+        let header = CartridgeHeader::new_from_data(&data);
+        let cartridge = Cartridge::new(CartridgeMemorySector::new_from_data(data), header);
+
+        let mut memory = Memory::new(Arc::default(), cartridge, None, Model::Dmg);
+        // Tile 0: first row of colour 1, the rest of colour 0. Tile 1: all colour 3.
+        for address in 0x8000..0x8020 {
+            memory.write_byte(address, 0);
+        }
+        memory.write_byte(0x8000, 0xFF);
+        for address in 0x8010..0x8020 {
+            memory.write_byte(address, 0xFF);
+        }
+        for address in 0x9800..0x9C00 {
+            memory.write_byte(address, 0);
+        }
+        // Window map at 0x9C00 with tile 1 first, then tile 0
+        for address in 0x9C00..0xA000 {
+            memory.write_byte(address, 0);
+        }
+        memory.write_byte(0x9C00, 1);
+
+        memory
+    }
+
+    fn create_io_registers(lcdc: Byte, scx: Byte, wx: Byte) -> IORegisters {
+        let mut io_registers = IORegisters::default();
+        io_registers.write_byte(Address::LCDC, lcdc);
+        io_registers.write_byte(Address::SCX_SCROLL_X, scx);
+        io_registers.write_byte(Address::WX_WINDOW_X_POSITION, wx);
+        io_registers.write_byte(Address::BGP_BG_WIN_PALETTE, 0xE4);
+        io_registers.write_byte(Address::OBP1_OBJ_PALETTE, 0xE4);
+
+        io_registers
+    }
+
+    /**
+     * Same tiles as `create_memory` in bank 0, and in bank 1 a tile 0 whose first row only has its
+     * leftmost pixel of colour 1 and its last row all of colour 1. Every tile of the BG map has
+     * `attributes`.
+     */
+    fn create_cgb_memory(attributes: Byte) -> Memory {
+        let mut memory = Memory::new(Arc::default(), Cartridge::default(), None, Model::Cgb);
+        for address in 0x8000..0x8020 {
+            memory.write_byte(address, 0);
+        }
+        memory.write_byte(0x8000, 0xFF);
+        for address in 0x8010..0x8020 {
+            memory.write_byte(address, 0xFF);
+        }
+
+        memory.write_byte(Address::VBK_VRAM_BANK, 1);
+        for address in 0x8000..0x8010 {
+            memory.write_byte(address, 0);
+        }
+        memory.write_byte(0x8000, 0x80);
+        memory.write_byte(0x800E, 0xFF);
+        for address in 0x9800..0x9C00 {
+            memory.write_byte(address, attributes);
+        }
+        memory.write_byte(Address::VBK_VRAM_BANK, 0);
+
+        memory
+    }
+
+    /// Colour 1 of BG palette 2 and colour 3 of OBJ palette 0 are red, colour 1 of OBJ palette 1
+    /// is blue, and everything else is white.
+    fn create_cgb_io_registers(lcdc: Byte) -> IORegisters {
+        let mut io_registers = IORegisters::power_on(Model::Cgb, true);
+        io_registers.write_byte(Address::LCDC, lcdc);
+
+        for (index_register, index, color) in [
+            (Address::BCPS_BG_PALETTE_INDEX, 0x12, 0x001F),
+            (Address::OCPS_OBJ_PALETTE_INDEX, 0x06, 0x001F),
+            (Address::OCPS_OBJ_PALETTE_INDEX, 0x0A, 0x7C00),
+        ] {
+            io_registers.write_byte(index_register, 0x80 | index);
+            io_registers.write_byte(index_register + 1, color as Byte);
+            io_registers.write_byte(index_register + 1, (color >> 8) as Byte);
+        }
+
+        io_registers
+    }
+
+    fn create_canvas() -> RgbaImage {
+        ImageBuffer::new(Gpu::PIXEL_WIDTH as u32, Gpu::PIXEL_HEIGHT as u32)
+    }
+
+    fn draw_window_line(
+        memory: &Memory,
+        io_registers: &IORegisters,
+        ly: Byte,
+        sprites: Vec<OamEntry>,
+        window: Window,
+        canvas: &mut RgbaImage,
+    ) -> PixelFifo {
+        let mut pixel_fifo = PixelFifo::new(ly, io_registers.scx, sprites, window);
+
+        while !pixel_fifo.tick(memory, io_registers, canvas) {
+            assert!(pixel_fifo.dots() < 456, "Line never finished");
+        }
+
+        pixel_fifo
+    }
+
+    fn draw_line(
+        memory: &Memory,
+        io_registers: &IORegisters,
+        sprites: Vec<OamEntry>,
+    ) -> (u16, RgbaImage) {
+        let mut canvas = create_canvas();
+        let window = Window {
+            y_triggered: true,
+            ..Window::default()
+        };
+        let pixel_fifo = draw_window_line(memory, io_registers, 0, sprites, window, &mut canvas);
+
+        (pixel_fifo.dots(), canvas)
+    }
+
+    #[test_case(0x91, 0, 0, 172 ; "no scroll")]
+    #[test_case(0x91, 3, 0, 175 ; "fine scroll")]
+    #[test_case(0x91, 0x0F, 0, 179 ; "fine scroll past a tile")]
+    #[test_case(0xB1, 0, 7, 178 ; "window from the left edge")]
+    #[test_case(0xB1, 0, 87, 178 ; "window in the middle")]
+    fn test_mode_3_length(lcdc: Byte, scx: Byte, wx: Byte, dots: u16) {
+        let memory = create_memory();
+        let io_registers = create_io_registers(lcdc, scx, wx);
+
+        assert_eq!(draw_line(&memory, &io_registers, vec![]).0, dots);
+    }
+
+    #[test_case(0, &[20], 179 ; "middle of a tile")]
+    #[test_case(0, &[8], 183 ; "start of a tile")]
+    #[test_case(0, &[0], 183 ; "left of the screen")]
+    #[test_case(3, &[20], 181 ; "fine scroll")]
+    #[test_case(0, &[20, 21], 185 ; "two on the same tile")]
+    #[test_case(0, &[20, 28], 186 ; "two on different tiles")]
+    fn test_mode_3_length_with_sprites(scx: Byte, sprite_xs: &[Byte], dots: u16) {
+        let memory = create_memory();
+        let io_registers = create_io_registers(0x93, scx, 0);
+        let sprites = sprite_xs
+            .iter()
+            .map(|&x| OamEntry::with_bytes(16, x, 1, 0))
+            .collect();
+
+        assert_eq!(draw_line(&memory, &io_registers, sprites).0, dots);
+    }
+
+    #[test]
+    fn test_sprites_stall_the_fifo() {
+        let memory = create_memory();
+        let io_registers = create_io_registers(0x93, 0, 0);
+
+        let (dots, canvas) = draw_line(
+            &memory,
+            &io_registers,
+            vec![OamEntry::with_bytes(16, 20, 1, 0)],
+        );
+
+        assert_eq!(dots, 179);
+        assert_eq!(canvas.get_pixel(11, 0).0, Color::light_grey().to_rgba());
+        assert_eq!(canvas.get_pixel(12, 0).0, Color::black().to_rgba());
+        assert_eq!(canvas.get_pixel(19, 0).0, Color::black().to_rgba());
+        assert_eq!(canvas.get_pixel(20, 0).0, Color::light_grey().to_rgba());
+    }
+
+    #[test]
+    fn test_sprites_behind_background_only_show_over_colour_0() {
+        let memory = create_memory();
+        let io_registers = create_io_registers(0x93, 0, 0);
+
+        let (_, canvas) = draw_line(
+            &memory,
+            &io_registers,
+            vec![OamEntry::with_bytes(16, 20, 1, 0x80)],
+        );
+        assert_eq!(canvas.get_pixel(12, 0).0, Color::light_grey().to_rgba());
+
+        let io_registers = create_io_registers(0x92, 0, 0);
+        let (_, canvas) = draw_line(
+            &memory,
+            &io_registers,
+            vec![OamEntry::with_bytes(16, 20, 1, 0x80)],
+        );
+        assert_eq!(canvas.get_pixel(12, 0).0, Color::black().to_rgba());
+        assert_eq!(canvas.get_pixel(0, 0).0, Color::white().to_rgba());
+    }
+
+    #[test_case(16, 20, 12, Color::light_grey() ; "lower x wins")]
+    #[test_case(20, 20, 12, Color::light_grey() ; "same x, first in OAM wins")]
+    #[test_case(24, 20, 16, Color::black() ; "higher x loses")]
+    fn test_overlapping_sprites_priority(first_x: Byte, second_x: Byte, pixel: u32, color: Color) {
+        let memory = create_memory();
+        let io_registers = create_io_registers(0x93, 0, 0);
+
+        // Both sprites cover the pixel, the first one with colour 1 and the second with colour 3
+        let (_, canvas) = draw_line(
+            &memory,
+            &io_registers,
+            vec![
+                OamEntry::with_bytes(16, first_x, 0, 0),
+                OamEntry::with_bytes(16, second_x, 1, 0),
+            ],
+        );
+
+        assert_eq!(canvas.get_pixel(pixel, 0).0, color.to_rgba());
+    }
+
+    #[test]
+    fn test_tall_sprites_ignore_bit_0_of_the_tile() {
+        let memory = create_memory();
+        let io_registers = create_io_registers(0x97, 0, 0);
+
+        let (_, canvas) = draw_line(
+            &memory,
+            &io_registers,
+            vec![OamEntry::with_bytes(16, 20, 1, 0)],
+        );
+
+        assert_eq!(canvas.get_pixel(12, 0).0, Color::light_grey().to_rgba());
+    }
+
+    #[test_case(7, 8 ; "at the left edge")]
+    #[test_case(3, 4 ; "partly left of the screen")]
+    #[test_case(0, 1 ; "wx 0")]
+    fn test_window_start(wx: Byte, first_tile_width: u32) {
+        let memory = create_memory();
+        let io_registers = create_io_registers(0xF1, 0, wx);
+
+        let (_, canvas) = draw_line(&memory, &io_registers, vec![]);
+
+        assert_eq!(
+            canvas.get_pixel(first_tile_width - 1, 0).0,
+            Color::black().to_rgba()
+        );
+        assert_eq!(
+            canvas.get_pixel(first_tile_width, 0).0,
+            Color::light_grey().to_rgba()
+        );
+    }
+
+    #[test]
+    fn test_window_line_only_advances_when_drawn() {
+        let memory = create_memory();
+        let mut canvas = create_canvas();
+        let window = Window {
+            y_triggered: true,
+            line: 3,
+            ..Window::default()
+        };
+
+        let io_registers = create_io_registers(0xD1, 0, 7);
+        let pixel_fifo = draw_window_line(&memory, &io_registers, 10, vec![], window, &mut canvas);
+        assert_eq!(pixel_fifo.window().line, 3);
+
+        let io_registers = create_io_registers(0xF1, 0, 7);
+        let pixel_fifo = draw_window_line(&memory, &io_registers, 11, vec![], window, &mut canvas);
+        assert_eq!(pixel_fifo.window().line, 4);
+
+        let window = Window {
+            y_triggered: false,
+            ..window
+        };
+        let pixel_fifo = draw_window_line(&memory, &io_registers, 12, vec![], window, &mut canvas);
+        assert_eq!(pixel_fifo.window().line, 3);
+    }
+
+    #[test]
+    fn test_window_at_wx_166_covers_the_next_line() {
+        let memory = create_memory();
+        let io_registers = create_io_registers(0xF1, 0, 166);
+        let mut canvas = create_canvas();
+        let window = Window {
+            y_triggered: true,
+            ..Window::default()
+        };
+
+        let pixel_fifo = draw_window_line(&memory, &io_registers, 0, vec![], window, &mut canvas);
+        assert_eq!(canvas.get_pixel(158, 0).0, Color::light_grey().to_rgba());
+        assert_eq!(canvas.get_pixel(159, 0).0, Color::black().to_rgba());
+
+        let window = pixel_fifo.window();
+        draw_window_line(&memory, &io_registers, 1, vec![], window, &mut canvas);
+        assert_eq!(canvas.get_pixel(0, 1).0, Color::black().to_rgba());
+        assert_eq!(canvas.get_pixel(8, 1).0, Color::white().to_rgba());
+    }
+
+    #[test_case(0x02, true, true ; "bank 0")]
+    #[test_case(0x0A, true, false ; "bank 1")]
+    #[test_case(0x2A, false, true ; "flipped horizontally")]
+    #[test_case(0x4A, true, true ; "flipped vertically")]
+    fn test_cgb_bg_map_attributes(attributes: Byte, first_red: bool, last_red: bool) {
+        let memory = create_cgb_memory(attributes);
+        let io_registers = create_cgb_io_registers(0x91);
+        let red = |is_red: bool| {
+            if is_red {
+                Color::new(255, 0, 0).to_rgba()
+            } else {
+                Color::white().to_rgba()
+            }
+        };
+
+        let (_, canvas) = draw_line(&memory, &io_registers, vec![]);
+
+        assert_eq!(canvas.get_pixel(0, 0).0, red(first_red));
+        assert_eq!(canvas.get_pixel(7, 0).0, red(last_red));
+    }
+
+    #[test]
+    fn test_cgb_overlapping_sprites_first_in_oam_wins() {
+        let memory = create_cgb_memory(0);
+        let io_registers = create_cgb_io_registers(0x93);
+
+        // The first sprite is all colour 1 with OBJ palette 1, the second of colour 3
+        let (_, canvas) = draw_line(
+            &memory,
+            &io_registers,
+            vec![
+                OamEntry::with_bytes(16, 24, 0, 0x01),
+                OamEntry::with_bytes(16, 20, 1, 0x00),
+            ],
+        );
+
+        assert_eq!(canvas.get_pixel(12, 0).0, Color::new(255, 0, 0).to_rgba());
+        assert_eq!(canvas.get_pixel(16, 0).0, Color::new(0, 0, 255).to_rgba());
+    }
+
+    #[test_case(0x93, 0x00, 0x00, true ; "no priority")]
+    #[test_case(0x93, 0x80, 0x00, false ; "bg map priority")]
+    #[test_case(0x93, 0x00, 0x80, false ; "oam priority")]
+    #[test_case(0x92, 0x80, 0x80, true ; "master priority off")]
+    fn test_cgb_sprite_priority(lcdc: Byte, attributes: Byte, flags: Byte, sprite_shown: bool) {
+        let memory = create_cgb_memory(attributes);
+        let io_registers = create_cgb_io_registers(lcdc);
+
+        // The background has colour 1 of BG palette 0 on the first row, so white
+        let (_, canvas) = draw_line(
+            &memory,
+            &io_registers,
+            vec![OamEntry::with_bytes(16, 20, 1, flags)],
+        );
+
+        let expected = if sprite_shown {
+            Color::new(255, 0, 0)
+        } else {
+            Color::white()
+        };
+        assert_eq!(canvas.get_pixel(12, 0).0, expected.to_rgba());
+    }
+}
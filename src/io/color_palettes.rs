@@ -0,0 +1,91 @@
+use crate::gpu::color::Color;
+use crate::{Byte, Word};
+
+/**
+ * Palette RAM of CGB, 8 palettes of 4 colours, 2 bytes each in little endian, accessed through
+ * an index register (BCPS/OCPS) and a data register (BCPD/OCPD).
+ */
+#[derive(Clone)]
+pub struct ColorPalettes {
+    data: [Byte; Self::SIZE],
+    index: Byte,
+    auto_increment: bool,
+}
+
+impl ColorPalettes {
+    const SIZE: usize = 0x40;
+
+    /// Bit 6 is unused and always reads as set.
+    pub fn index_value(&self) -> Byte {
+        0x40 | if self.auto_increment { 0x80 } else { 0 } | self.index
+    }
+
+    pub fn update_index(&mut self, value: Byte) {
+        self.index = value & 0x3F;
+        self.auto_increment = value & 0x80 == 0x80;
+    }
+
+    pub fn data(&self) -> Byte {
+        self.data[self.index as usize]
+    }
+
+    /// Reads do not move the index, only writes do when auto-increment is set.
+    pub fn update_data(&mut self, value: Byte) {
+        self.data[self.index as usize] = value;
+
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+
+    pub fn color(&self, palette: Byte, color: Byte) -> Color {
+        let position = (palette as usize & 7) * 8 + (color as usize & 3) * 2;
+
+        Color::from_rgb555(self.data[position] as Word | (self.data[position + 1] as Word) << 8)
+    }
+}
+
+impl Default for ColorPalettes {
+    fn default() -> Self {
+        Self {
+            data: [0xFF; Self::SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writes_auto_increment_the_index() {
+        let mut palettes = ColorPalettes::default();
+        palettes.update_index(0xBE);
+
+        palettes.update_data(0x1F);
+        palettes.update_data(0x7C);
+        palettes.update_data(0x00);
+
+        assert_eq!(palettes.index_value(), 0xC1);
+        palettes.update_index(0x3E);
+        assert_eq!(palettes.data(), 0x1F);
+        assert_eq!(palettes.index_value(), 0x7E);
+        assert_eq!(palettes.color(7, 3).to_rgba(), [255, 0, 255, 255]);
+        palettes.update_index(0x00);
+        assert_eq!(palettes.data(), 0x00);
+    }
+
+    #[test]
+    fn test_colors_are_little_endian_rgb555() {
+        let mut palettes = ColorPalettes::default();
+        palettes.update_index(0x80 | 0x0A);
+
+        palettes.update_data(0xE0);
+        palettes.update_data(0x03);
+
+        assert_eq!(palettes.color(1, 1).to_rgba(), [0, 255, 0, 255]);
+        assert_eq!(palettes.color(1, 0).to_rgba(), [255, 255, 255, 255]);
+    }
+}
@@ -1,25 +1,34 @@
-use crate::Byte;
+use crate::{Byte, Word};
 
+/**
+ * Upper byte of the internal counter, which goes up on every cycle. It is derived from the cycle
+ * count instead of being stepped.
+ */
 #[derive(Default)]
-#[readonly::make]
 pub struct Div {
-    pub value: Byte,
-    remaining_div_cycles: u16,
+    // Value of the internal counter at cycle 0
+    origin: Word,
 }
 
 impl Div {
-    const STEP_CYCLES: u16 = 0x100;
-    pub fn step(&mut self, last_instruction_cycles: u8) {
-        self.remaining_div_cycles += last_instruction_cycles as u16;
-
-        self.value = self
-            .value
-            .wrapping_add((self.remaining_div_cycles / Self::STEP_CYCLES) as u8);
-        self.remaining_div_cycles %= Self::STEP_CYCLES
+    /**
+     * Starts from a given internal counter, whose upper byte is the visible DIV value.
+     */
+    pub fn new(counter: Word) -> Self {
+        Self { origin: counter }
     }
 
-    pub fn reset_value(&mut self) {
-        self.value = 0;
+    pub fn value(&self, now: u64) -> Byte {
+        (self.counter(now) >> 8) as Byte
+    }
+
+    /// Writes clear the visible value, the lower byte keeps counting.
+    pub fn reset_value(&mut self, now: u64) {
+        self.origin = self.origin.wrapping_sub(self.counter(now) & 0xFF00);
+    }
+
+    fn counter(&self, now: u64) -> Word {
+        self.origin.wrapping_add(now as Word)
     }
 }
 
@@ -29,48 +38,53 @@ mod tests {
 
     #[test]
     fn it_does_not_increase_in_its_maximum_value() {
-        let mut div = Div::default();
-        div.step(0xFF);
+        let div = Div::default();
 
-        assert_eq!(div.value, 0);
+        assert_eq!(div.value(0xFF), 0);
     }
+
     #[test]
     fn it_increases_with_maximum_value_plus_1() {
-        let mut div = Div::default();
-        div.step(0xFF);
-        div.step(0x01);
+        let div = Div::default();
 
-        assert_eq!(div.value, 1);
+        assert_eq!(div.value(0x100), 1);
     }
 
     #[test]
     fn it_keeps_increasing() {
-        let mut div = Div::default();
-        div.step(0xFF);
-        div.step(0xFF);
-        div.step(0xFF);
+        let div = Div::default();
 
-        assert_eq!(div.value, 2);
+        assert_eq!(div.value(0xFF * 3), 2);
+    }
+
+    #[test]
+    fn it_wraps_around() {
+        let div = Div::default();
+
+        assert_eq!(div.value(0x10100), 1);
+    }
+
+    #[test]
+    fn it_keeps_the_phase_of_the_initial_counter() {
+        let div = Div::new(0xABCC);
+        assert_eq!(div.value(0), 0xAB);
+        assert_eq!(div.value(0x33), 0xAB);
+        assert_eq!(div.value(0x34), 0xAC);
     }
 
     #[test]
     fn it_resets_value() {
         let mut div = Div::default();
-        div.step(0xFF);
-        div.step(0x01);
-        div.reset_value();
+        div.reset_value(0x100);
 
-        assert_eq!(div.value, 0);
+        assert_eq!(div.value(0x100), 0);
     }
 
     #[test]
     fn it_resets_value_but_keeps_counting_cycles_internally() {
         let mut div = Div::default();
-        div.step(0xFF);
-        div.step(0xFF);
-        div.reset_value();
-        div.step(0xFF);
+        div.reset_value(0xFF * 2);
 
-        assert_eq!(div.value, 1);
+        assert_eq!(div.value(0xFF * 3), 1);
     }
 }
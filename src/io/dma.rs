@@ -4,38 +4,88 @@ use crate::{Byte, Word};
 #[readonly::make]
 pub struct Dma {
     pub(crate) value: Byte,
-    remaining_cycles: Byte,
+    // Next byte to copy while a transfer runs
+    position: Option<Word>,
+    // Written, a new transfer takes over on the next M-cycle
+    starting: bool,
 }
 
 impl Dma {
-    pub fn step(&mut self, cycles: u8) -> bool {
-        if self.remaining_cycles == 0 {
-            return false;
+    /// Bytes copied, the whole OAM.
+    pub const TRANSFER_BYTES: Word = 0xA0;
+    /// Cycles each byte takes, as well as the delay from the write to the first one.
+    pub const BYTE_CYCLES: u64 = 4;
+
+    /**
+     * Holds a value without starting a transfer, as left behind by the boot ROM.
+     */
+    pub fn new(value: Byte) -> Self {
+        Self {
+            value,
+            ..Self::default()
         }
+    }
 
-        self.remaining_cycles = self.remaining_cycles.saturating_sub(cycles);
+    /**
+     * Starts a transfer after a cycle of delay. A transfer already running keeps OAM to itself
+     * until then.
+     */
+    pub fn start(&mut self, value: Byte) {
+        self.value = value;
+        self.starting = true;
+    }
 
-        if self.remaining_cycles == 0 {
-            return true;
-        }
+    pub fn is_active(&self) -> bool {
+        self.position.is_some()
+    }
 
-        false
+    pub fn source(&self) -> Word {
+        (self.value as Word) << 8
     }
 
-    pub fn update(&mut self, value: Byte) {
-        self.value = value;
-        self.remaining_cycles = 160;
+    /**
+     * Moves the transfer to its next byte. Returns the OAM offset to copy it to, unless there is
+     * no transfer left.
+     */
+    pub fn next_byte(&mut self) -> Option<Word> {
+        if std::mem::take(&mut self.starting) {
+            self.position = Some(0);
+        }
+
+        let position = self.position?;
+        self.position = Some(position + 1).filter(|next| *next < Self::TRANSFER_BYTES);
+
+        Some(position)
     }
 }
 
-impl From<&Dma> for Word {
-    fn from(original: &Dma) -> Self {
-        let mut value = original.value;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if value > 0xDF {
-            value &= 0xDF;
-        }
+    #[test]
+    fn test_transfer_copies_every_byte_once() {
+        let mut dma = Dma::default();
+        dma.start(0xC1);
+
+        let positions: Vec<_> = std::iter::from_fn(|| dma.next_byte()).collect();
+
+        assert_eq!(positions, (0..Dma::TRANSFER_BYTES).collect::<Vec<_>>());
+        assert_eq!(dma.source(), 0xC100);
+        assert!(!dma.is_active());
+    }
+
+    #[test]
+    fn test_restart_keeps_the_transfer_running_until_it_takes_over() {
+        let mut dma = Dma::default();
+        dma.start(0xC0);
+        dma.next_byte();
+        dma.next_byte();
+
+        dma.start(0xD0);
 
-        (value as Word) << 8 & 0xFF00
+        assert!(dma.is_active());
+        assert_eq!(dma.next_byte(), Some(0));
+        assert_eq!(dma.source(), 0xD000);
     }
 }
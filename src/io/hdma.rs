@@ -0,0 +1,152 @@
+use crate::{Byte, Word};
+
+/**
+ * VRAM DMA of CGB, copying blocks of 16 bytes to VRAM: all of them at once in a general purpose
+ * transfer, or one per H-Blank in an H-Blank transfer.
+ */
+#[derive(Clone)]
+pub struct Hdma {
+    source: Word,
+    destination: Word,
+    // Blocks left minus one, as HDMA5 reads, $7F once done
+    length: Byte,
+    running: bool,
+    hblank: bool,
+}
+
+impl Hdma {
+    pub const BLOCK_BYTES: Word = 0x10;
+    /// Cycles the CPU is halted for while each block is copied, the same at both speeds.
+    pub const BLOCK_CYCLES: u64 = 32;
+
+    pub fn update_source_high(&mut self, value: Byte) {
+        self.source = (value as Word) << 8 | (self.source & 0x00F0);
+    }
+
+    /// The lower 4 bits are ignored, as blocks are aligned.
+    pub fn update_source_low(&mut self, value: Byte) {
+        self.source = (self.source & 0xFF00) | (value & 0xF0) as Word;
+    }
+
+    /// Only the offset into VRAM is kept.
+    pub fn update_destination_high(&mut self, value: Byte) {
+        self.destination = ((value & 0x1F) as Word) << 8 | (self.destination & 0x00F0);
+    }
+
+    pub fn update_destination_low(&mut self, value: Byte) {
+        self.destination = (self.destination & 0x1F00) | (value & 0xF0) as Word;
+    }
+
+    /// Bit 7 is clear while a transfer runs, so it reads $FF once the last block is copied.
+    pub fn value(&self) -> Byte {
+        if self.running {
+            self.length
+        } else {
+            0x80 | self.length
+        }
+    }
+
+    /**
+     * Starts a transfer of `(value & 0x7F) + 1` blocks, during H-Blanks if bit 7 is set. Writing
+     * with bit 7 clear while an H-Blank transfer runs stops it instead.
+     */
+    pub fn start(&mut self, value: Byte) {
+        let hblank = value & 0x80 == 0x80;
+
+        if self.is_hblank_active() && !hblank {
+            self.running = false;
+            return;
+        }
+
+        self.length = value & 0x7F;
+        self.hblank = hblank;
+        self.running = true;
+    }
+
+    /// A block is waiting for the next H-Blank.
+    pub fn is_hblank_active(&self) -> bool {
+        self.running && self.hblank
+    }
+
+    /// A general purpose transfer was started, to be done at once.
+    pub fn is_general_pending(&self) -> bool {
+        self.running && !self.hblank
+    }
+
+    /**
+     * Moves the transfer to its next block. Returns the source and VRAM address to copy it
+     * between, unless there is no transfer left.
+     */
+    pub fn next_block(&mut self) -> Option<(Word, Word)> {
+        if !self.running {
+            return None;
+        }
+
+        let block = (self.source, 0x8000 | self.destination);
+
+        self.source = self.source.wrapping_add(Self::BLOCK_BYTES);
+        self.destination = (self.destination + Self::BLOCK_BYTES) & 0x1FF0;
+        self.length = self.length.wrapping_sub(1) & 0x7F;
+        self.running = self.length != 0x7F;
+
+        Some(block)
+    }
+}
+
+impl Default for Hdma {
+    fn default() -> Self {
+        Self {
+            source: 0,
+            destination: 0,
+            length: 0x7F,
+            running: false,
+            hblank: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_hdma() -> Hdma {
+        let mut hdma = Hdma::default();
+        hdma.update_source_high(0xC1);
+        hdma.update_source_low(0x2F);
+        hdma.update_destination_high(0xE0);
+        hdma.update_destination_low(0x45);
+
+        hdma
+    }
+
+    #[test]
+    fn test_general_purpose_transfer_copies_every_block() {
+        let mut hdma = create_hdma();
+
+        hdma.start(0x02);
+        assert!(hdma.is_general_pending());
+
+        let blocks: Vec<_> = std::iter::from_fn(|| hdma.next_block()).collect();
+
+        assert_eq!(
+            blocks,
+            vec![(0xC120, 0x8040), (0xC130, 0x8050), (0xC140, 0x8060)]
+        );
+        assert_eq!(hdma.value(), 0xFF);
+    }
+
+    #[test]
+    fn test_hblank_transfer_can_be_stopped() {
+        let mut hdma = create_hdma();
+
+        hdma.start(0x85);
+        assert!(hdma.is_hblank_active());
+        hdma.next_block();
+        assert_eq!(hdma.value(), 0x04);
+
+        hdma.start(0x00);
+        assert!(!hdma.is_hblank_active());
+        assert_eq!(hdma.value(), 0x84);
+        assert_eq!(hdma.next_block(), None);
+    }
+}
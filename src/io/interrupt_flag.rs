@@ -44,6 +44,10 @@ impl InterruptFlag {
     pub fn set_timer_overflow(&mut self, value: bool) {
         self.timer_overflow = value;
     }
+
+    pub fn set_serial_io_transfer_complete(&mut self, value: bool) {
+        self.serial_io_transfer_complete = value;
+    }
 }
 
 impl From<&InterruptFlag> for Byte {
@@ -72,9 +72,21 @@ impl JoypadHandler {
             Key::R => {
                 self.runtime_config.write().set_reset(true);
             }
+            Key::S => {
+                // Soft reset combination most games check for
+                let mut io_registers = self.io_registers.write();
+                io_registers.p1.soft_reset = true;
+                io_registers.interrupt_flag.set_p10_p13_transition(true);
+            }
             Key::D => {
                 self.runtime_config.write().toggle_debug();
             }
+            Key::H => {
+                self.runtime_config.write().request_crash_report();
+            }
+            Key::P => {
+                self.runtime_config.write().toggle_pause();
+            }
             _ => {}
         };
     }
@@ -90,6 +102,7 @@ impl JoypadHandler {
             Key::Right => io_registers.p1.right = false,
             Key::Up => io_registers.p1.up = false,
             Key::Down => io_registers.p1.down = false,
+            Key::S => io_registers.p1.soft_reset = false,
             Key::Space => self.runtime_config.write().user_speed_multiplier = 1,
             _ => {}
         }
@@ -116,6 +129,9 @@ pub struct Joypad {
     // P15 - P13
     pub start: bool,
 
+    // A, B, Select and Start held at once by a single key, apart from their own keys
+    pub soft_reset: bool,
+
     p14: bool,
     p15: bool,
 }
@@ -133,6 +149,8 @@ impl Joypad {
             select: false,
             start: false,
 
+            soft_reset: false,
+
             p14: false,
             p15: false,
         }
@@ -147,10 +165,10 @@ impl Joypad {
         value |= (!self.p14 as Byte) << 4;
 
         if self.p15 {
-            value |= (!(self.start) as Byte) << 3;
-            value |= (!(self.select) as Byte) << 2;
-            value |= (!(self.b) as Byte) << 1;
-            value |= !(self.a) as Byte;
+            value |= (!(self.start || self.soft_reset) as Byte) << 3;
+            value |= (!(self.select || self.soft_reset) as Byte) << 2;
+            value |= (!(self.b || self.soft_reset) as Byte) << 1;
+            value |= !(self.a || self.soft_reset) as Byte;
         } else if self.p14 {
             value |= (!(self.down) as Byte) << 3;
             value |= (!(self.up) as Byte) << 2;
@@ -166,3 +184,21 @@ impl Joypad {
         self.p15 = new_value & 0b100000 != 0b100000;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soft_reset_combination_keeps_buttons_held_on_their_own() {
+        let mut joypad = Joypad::new();
+        joypad.parse_byte(0x10);
+        joypad.a = true;
+
+        joypad.soft_reset = true;
+        assert_eq!(joypad.to_byte(), 0x10);
+
+        joypad.soft_reset = false;
+        assert_eq!(joypad.to_byte(), 0x1E);
+    }
+}
@@ -15,10 +15,6 @@ impl LY {
     }
 
     pub fn has_reached_end_of_screen(&self) -> bool {
-        self.value >= 143
-    }
-
-    pub fn has_reached_end_of_vblank(&self) -> bool {
-        self.value > 153
+        self.value >= 144
     }
 }
@@ -1,6 +1,8 @@
 pub mod audio_registers;
+pub mod color_palettes;
 mod div;
-mod dma;
+pub mod dma;
+pub mod hdma;
 mod interrupt_enable;
 mod interrupt_flag;
 pub mod joypad;
@@ -1,10 +1,11 @@
+use crate::audio::CYCLES_1_512_SEC;
 use crate::audio::apu::Apu;
 use crate::bus::address::Address;
-use crate::debug::{
-    DebugReason, Debuggable, IO_READ_WATCHPOINTS, IO_WRITE_WATCHPOINTS, OutputDebug,
-};
+use crate::debug::Debuggable;
+use crate::io::color_palettes::ColorPalettes;
 use crate::io::div::Div;
 use crate::io::dma::Dma;
+use crate::io::hdma::Hdma;
 use crate::io::interrupt_enable::InterruptEnable;
 use crate::io::interrupt_flag::InterruptFlag;
 use crate::io::joypad::Joypad;
@@ -16,6 +17,8 @@ use crate::io::tima::Tima;
 use crate::io::timer_control::TimerControl;
 use crate::io::wave_pattern_ram::WavePatternRam;
 use crate::memory::memory_sector::{ReadMemory, WriteMemory};
+use crate::model::Model;
+use crate::scheduler::{Event, Scheduler};
 use crate::{Byte, Word};
 use std::collections::BTreeMap;
 
@@ -23,8 +26,10 @@ pub struct IORegisters {
     pub p1: Joypad,
     serial_transfer_data: Byte,
     sio_control: SioControl,
-    pub div: Div,
-    pub tima: Tima,
+    // Bits still to shift out in the current serial transfer
+    serial_bits_left: u8,
+    div: Div,
+    tima: Tima,
     pub tma: Byte,
     pub timer_control: TimerControl,
     pub interrupt_flag: InterruptFlag,
@@ -47,85 +52,326 @@ pub struct IORegisters {
     pub obp2: Byte,
     pub wy: Byte,
     pub wx: Byte,
+    // FF68 - FF6B, CGB only
+    pub bg_palettes: ColorPalettes,
+    pub obj_palettes: ColorPalettes,
+    // FF4D, the next STOP switches the CPU speed
+    speed_switch_armed: bool,
+    // FF51 - FF55
+    pub hdma: Hdma,
 
     pub interrupt_enable: InterruptEnable,
+
+    // Bytes sent through the serial port, only kept when requested
+    serial_output: Option<Vec<Byte>>,
+
+    pub scheduler: Scheduler,
+
+    model: Model,
+    // Internal LCD STAT interrupt line, see `update_stat_line`
+    stat_line: bool,
+    // Line 0 has started after turning the LCD on, and the PPU is yet to hear about it
+    lcd_turned_on: bool,
+    // Line the LCD was turned off at outside of V-Blank, for the frontend to warn about
+    lcd_off_warning: Option<Byte>,
 }
 
 impl IORegisters {
-    pub fn step(&mut self, last_instruction_cycles: u8) -> Option<Word> {
-        let mut to_return = None;
-
-        if self.dma.step(last_instruction_cycles) {
-            to_return = Some(Word::from(&self.dma));
+    const SERIAL_BIT_CYCLES: u64 = 512;
+    // Dots into line 0 mode 3 starts at once the LCD is turned on, the line being 4 dots short
+    const LCD_ON_HBLANK_CYCLES: u64 = 76;
+
+    pub fn power_on(model: Model, bootstrap: bool) -> Self {
+        if bootstrap {
+            Self {
+                model,
+                ..Self::default()
+            }
+        } else {
+            Self::post_boot(model)
         }
+    }
 
-        self.div.step(last_instruction_cycles);
+    /**
+     * Register values as the boot ROM of each model leaves them. Audio registers already
+     * default to their post-boot values.
+     */
+    pub fn post_boot(model: Model) -> Self {
+        let mut io_registers = Self {
+            div: Div::new(model.post_boot_div_counter()),
+            model,
+            ..Self::default()
+        };
+
+        io_registers.interrupt_flag.update(0xE1);
+
+        if model.is_cgb() {
+            io_registers.sio_control.update(0x7F);
+        } else {
+            io_registers.dma = Dma::new(0xFF);
+        }
 
-        if !self.timer_control.started {
-            self.tima.reset_cycles();
-            return to_return;
+        if model == Model::Dmg0 {
+            io_registers.stat.set_mode(STATMode::VBlank);
+            io_registers.ly.value = 0x91;
+        } else {
+            // The boot ROM hands over right when LY wraps to 0, which this PPU only knows as the
+            // end of an H-Blank
+            io_registers.stat.set_mode(STATMode::HBlank);
         }
 
-        let tima_cycles_overflowed = self
-            .tima
-            .step(last_instruction_cycles, self.timer_control.get_divider());
+        io_registers.compare_ly();
+
+        io_registers.restart_ppu();
 
-        if tima_cycles_overflowed {
+        io_registers
+    }
+
+    /// Catches up with a timer overflow and schedules the next one.
+    pub fn overflow_timer(&mut self) {
+        self.sync_timer();
+        self.schedule_timer_overflow();
+    }
+
+    fn sync_timer(&mut self) {
+        if self.tima.sync(
+            self.scheduler.cpu_now(),
+            self.timer_control.divider(),
+            self.tma,
+        ) {
             self.interrupt_flag.set_timer_overflow(true);
-            self.tima.value = self.tma;
         }
+    }
+
+    fn schedule_timer_overflow(&mut self) {
+        match self.timer_control.divider() {
+            Some(divider) => self
+                .scheduler
+                .schedule_cpu(Event::TimerOverflow, self.tima.cycles_to_reload(divider)),
+            None => self.scheduler.cancel(Event::TimerOverflow),
+        }
+    }
 
-        self.apu.step();
+    /**
+     * Timer registers change how TIMA counts from now on, so it is brought up to date with the
+     * old settings first.
+     */
+    fn update_timer(&mut self, update: impl FnOnce(&mut Self)) {
+        self.sync_timer();
+        update(self);
+        self.schedule_timer_overflow();
+    }
 
-        to_return
+    /// The PPU carries on with its current mode from the start.
+    fn restart_ppu(&mut self) {
+        if self.lcdc.lcd_control_operation {
+            self.scheduler
+                .schedule(Event::PpuMode, self.stat.mode().cycles());
+        }
     }
 
-    pub fn set_stat_mode(&mut self, mode: STATMode) {
-        match mode {
-            STATMode::HBlank => {
-                if self.stat.mode_0 {
-                    self.interrupt_flag.set_lcd_stat(true);
-                }
-            }
+    fn update_lcdc(&mut self, value: Byte) {
+        let was_on = self.lcdc.lcd_control_operation;
+        self.lcdc.update(value);
 
-            STATMode::VBlank => {
-                if self.stat.mode_1 {
-                    self.interrupt_flag.set_lcd_stat(true);
+        match (was_on, self.lcdc.lcd_control_operation) {
+            (true, false) => {
+                if self.stat.mode() != STATMode::VBlank {
+                    self.lcd_off_warning = Some(self.ly.value);
                 }
 
-                self.interrupt_flag.set_vblank(true);
+                // STAT reads mode 0 while the LCD is off
+                self.scheduler.cancel(Event::PpuMode);
+                self.stat.set_mode(STATMode::HBlank);
+                self.ly_reset_wo_interrupt();
+                self.stat_line = false;
             }
-            STATMode::SearchOamRam => {
-                if self.stat.mode_2 {
-                    self.interrupt_flag.set_lcd_stat(true);
-                }
+            (false, true) => {
+                // Line 0 starts without an OAM scan: STAT reads mode 0 until mode 3 starts, a bit
+                // earlier than on the other lines
+                self.lcd_turned_on = true;
+                self.scheduler
+                    .schedule(Event::PpuMode, Self::LCD_ON_HBLANK_CYCLES);
+                self.compare_ly();
             }
             _ => {}
         }
+    }
+
+    pub fn take_lcd_turned_on(&mut self) -> bool {
+        std::mem::take(&mut self.lcd_turned_on)
+    }
+
+    /// The line the LCD was last turned off at outside of V-Blank, which can damage a real LCD.
+    pub fn take_lcd_off_warning(&mut self) -> Option<Byte> {
+        self.lcd_off_warning.take()
+    }
+
+    pub fn capture_serial(&mut self) {
+        self.serial_output.get_or_insert_with(Vec::new);
+    }
+
+    pub fn serial_output(&self) -> &[Byte] {
+        self.serial_output.as_deref().unwrap_or_default()
+    }
+
+    /**
+     * There is never a link cable partner: a transfer started with the internal clock shifts in
+     * $FF, one bit every 512 cycles.
+     */
+    fn update_serial_control(&mut self, value: Byte) {
+        self.sio_control.update(value);
+
+        if value & 0b10000001 != 0b10000001 {
+            self.scheduler.cancel(Event::SerialBit);
+            return;
+        }
+
+        if let Some(serial_output) = self.serial_output.as_mut() {
+            serial_output.push(self.serial_transfer_data);
+        }
+
+        self.serial_bits_left = 8;
+        self.scheduler
+            .schedule_cpu(Event::SerialBit, Self::SERIAL_BIT_CYCLES);
+    }
+
+    pub fn shift_serial_bit(&mut self) {
+        self.serial_transfer_data = self.serial_transfer_data << 1 | 1;
+        self.serial_bits_left -= 1;
+
+        if self.serial_bits_left > 0 {
+            self.scheduler
+                .schedule_cpu(Event::SerialBit, Self::SERIAL_BIT_CYCLES);
+            return;
+        }
+
+        self.sio_control.update(self.sio_control.value & 0b01111111);
+        self.interrupt_flag.set_serial_io_transfer_complete(true);
+    }
+
+    pub fn set_stat_mode(&mut self, mode: STATMode) {
+        if mode == STATMode::VBlank {
+            self.interrupt_flag.set_vblank(true);
+
+            // The mode 2 source also fires as line 144 starts
+            if self.stat.mode_2 && !self.stat_line {
+                self.interrupt_flag.set_lcd_stat(true);
+            }
+        }
 
         self.stat.set_mode(mode);
+        self.update_stat_line();
     }
 
-    fn determine_ly_interrupt(&mut self) {
-        let ly = self.ly.value;
+    /**
+     * The LCD STAT interrupt is requested when the internal line goes from low to high, so a
+     * source becoming active while another one already keeps it high is blocked.
+     */
+    fn update_stat_line(&mut self) {
+        let stat_line = self.lcdc.lcd_control_operation && self.stat.interrupt_line();
 
-        let new_value = ly == self.lyc;
+        if stat_line && !self.stat_line {
+            self.interrupt_flag.set_lcd_stat(true);
+        }
 
-        self.stat.coincidence_flag = new_value;
+        self.stat_line = stat_line;
+    }
 
-        if self.stat.lyc_ly_coincidence && new_value {
+    /**
+     * On DMG, a write to STAT enables every source for a cycle, which requests the interrupt
+     * during H-Blank, V-Blank or LY = LYC whatever gets written.
+     */
+    fn update_stat(&mut self, value: Byte) {
+        if !self.model.is_cgb()
+            && self.lcdc.lcd_control_operation
+            && !self.stat_line
+            && (matches!(self.stat.mode(), STATMode::HBlank | STATMode::VBlank)
+                || self.stat.coincidence_flag)
+        {
             self.interrupt_flag.set_lcd_stat(true);
         }
+
+        self.stat.update(value);
+        self.update_stat_line();
+    }
+
+    pub fn is_cgb_mode(&self) -> bool {
+        self.model.is_cgb_mode()
+    }
+
+    /**
+     * STOP was run: the CPU switches to the other speed if it was armed through KEY1. Returns
+     * whether it did. DIV is cleared all the same.
+     */
+    pub fn switch_speed(&mut self) -> bool {
+        self.div.reset_value(self.scheduler.cpu_now());
+
+        if !self.is_cgb_mode() || !std::mem::take(&mut self.speed_switch_armed) {
+            return false;
+        }
+
+        let double_speed = !self.scheduler.is_double_speed();
+        self.scheduler.set_double_speed(double_speed);
+
+        true
+    }
+
+    /// STAT reads mode 0, in H-Blank or while the LCD is off.
+    pub fn is_in_hblank(&self) -> bool {
+        self.stat.mode() == STATMode::HBlank
+    }
+
+    /// The PPU has VRAM to itself while it draws a line.
+    pub fn is_vram_accessible(&self) -> bool {
+        !self.lcdc.lcd_control_operation || self.stat.mode() != STATMode::LCDTransfer
+    }
+
+    /// OAM is taken by the PPU from the OAM scan until the line is drawn, and by OAM DMA.
+    pub fn is_oam_accessible(&self) -> bool {
+        let ppu_reading = self.lcdc.lcd_control_operation
+            && matches!(
+                self.stat.mode(),
+                STATMode::SearchOamRam | STATMode::LCDTransfer
+            );
+
+        !ppu_reading && !self.dma.is_active()
+    }
+
+    /**
+     * Row of OAM the PPU is reading during its OAM scan, one every four dots. The CPU ticks after
+     * each of its accesses, so this is the row of the M-cycle the access happens in, not the one
+     * the instruction started in. None when no scan is going on, and always on CGB, which does not
+     * suffer from the OAM bug.
+     */
+    pub fn oam_scan_row(&self) -> Option<usize> {
+        if self.model.is_cgb()
+            || !self.lcdc.lcd_control_operation
+            || self.stat.mode() != STATMode::SearchOamRam
+        {
+            return None;
+        }
+
+        let scan_end = self.scheduler.deadline(Event::PpuMode)?;
+        let dots =
+            (self.scheduler.now() + STATMode::SearchOamRam.cycles()).checked_sub(scan_end)?;
+
+        Some(dots as usize / 4)
+    }
+
+    fn compare_ly(&mut self) {
+        self.stat.coincidence_flag = self.ly.value == self.lyc;
+        self.update_stat_line();
     }
 
     pub fn ly_increment(&mut self) {
         self.ly.increment();
-        self.determine_ly_interrupt();
+        self.compare_ly();
     }
 
     pub fn ly_reset(&mut self) {
         self.ly.reset();
-        self.determine_ly_interrupt();
+        self.compare_ly();
     }
 
     pub fn ly_reset_wo_interrupt(&mut self) {
@@ -135,16 +381,40 @@ impl IORegisters {
 
 impl Debuggable for IORegisters {
     fn get_debug_values(&self) -> BTreeMap<&str, String> {
-        self.apu.get_debug_values()
+        let mut values = self.apu.get_debug_values();
+
+        values.extend([
+            (
+                "DIV",
+                format!("{:X}", self.read_byte(Address::DIV_DIVIDER_REGISTER)),
+            ),
+            (
+                "TIMA",
+                format!("{:X}", self.read_byte(Address::TIMA_TIMER_COUNTER)),
+            ),
+            ("TMA", format!("{:X}", self.tma)),
+            ("IF", format!("{:X}", Byte::from(&self.interrupt_flag))),
+            ("IE", format!("{:X}", self.interrupt_enable.value)),
+            ("LCDC", format!("{:X}", Byte::from(&self.lcdc))),
+            ("STAT", format!("{:X}", Byte::from(&self.stat))),
+            ("LY", format!("{:X}", self.ly.value)),
+            ("LYC", format!("{:X}", self.lyc)),
+            ("SCY", format!("{:X}", self.scy)),
+            ("SCX", format!("{:X}", self.scx)),
+            ("DMA", format!("{:X}", self.dma.value)),
+        ]);
+
+        values
     }
 }
 
 impl Default for IORegisters {
     fn default() -> Self {
-        Self {
+        let mut io_registers = Self {
             p1: Joypad::new(),
             serial_transfer_data: 0,
             sio_control: SioControl::default(),
+            serial_bits_left: 0,
             div: Div::default(),
             tima: Tima::default(),
             tma: 0,
@@ -166,27 +436,43 @@ impl Default for IORegisters {
             obp2: 0xFF,
             wy: 0x00,
             wx: 0x00,
+            bg_palettes: ColorPalettes::default(),
+            obj_palettes: ColorPalettes::default(),
+            speed_switch_armed: false,
+            hdma: Hdma::default(),
             interrupt_enable: InterruptEnable::default(),
-        }
+            serial_output: None,
+
+            scheduler: Scheduler::default(),
+
+            model: Model::default(),
+            stat_line: false,
+            lcd_turned_on: false,
+            lcd_off_warning: None,
+        };
+
+        io_registers
+            .scheduler
+            .schedule(Event::FrameSequencer, CYCLES_1_512_SEC);
+        io_registers.restart_ppu();
+        io_registers.compare_ly();
+
+        io_registers
     }
 }
 
 impl ReadMemory for IORegisters {
     fn read_byte(&self, position: Word) -> Byte {
-        let mut output_debug = OutputDebug::new_with_reason(DebugReason::IORead(position));
-        let debug_watchpoint = IO_READ_WATCHPOINTS.contains(&position);
-
-        if debug_watchpoint {
-            output_debug.push_situation("Content", self.get_debug_values());
-            output_debug.print();
-        }
-
         match position {
             Address::P1_JOYPAD => self.p1.to_byte(),
             Address::SB_SERIAL_TRANSFER_DATA => self.serial_transfer_data,
             Address::SC_SIO_CONTROL => self.sio_control.value,
-            Address::DIV_DIVIDER_REGISTER => self.div.value,
-            Address::TIMA_TIMER_COUNTER => self.tima.value,
+            Address::DIV_DIVIDER_REGISTER => self.div.value(self.scheduler.cpu_now()),
+            Address::TIMA_TIMER_COUNTER => self.tima.value(
+                self.scheduler.cpu_now(),
+                self.timer_control.divider(),
+                self.tma,
+            ),
             Address::TMA_TIMER_MODULO => self.tma,
             Address::IF_INTERRUPT_FLAG => (&self.interrupt_flag).into(),
 
@@ -198,14 +484,37 @@ impl ReadMemory for IORegisters {
             Address::STAT => (&self.stat).into(),
             Address::SCY_SCROLL_Y => self.scy,
             Address::SCX_SCROLL_X => self.scx,
-            0xFF44 => self.ly.value,
-            0xFF45 => self.lyc,
+            Address::LY_LCDC_Y_COORDINATE => self.ly.value,
+            Address::LYC_LY_COMPARE => self.lyc,
             Address::DMA => self.dma.value,
             Address::BGP_BG_WIN_PALETTE => self.bgp,
             Address::OBP1_OBJ_PALETTE => self.obp1,
             Address::OBP2_OBJ_PALETTE => self.obp2,
             Address::WY_WINDOW_Y_POSITION => self.wy,
             Address::WX_WINDOW_X_POSITION => self.wx,
+            Address::KEY1_SPEED_SWITCH
+            | Address::HDMA5_LENGTH_MODE_START
+            | Address::BCPS_BG_PALETTE_INDEX..=Address::OCPD_OBJ_PALETTE_DATA
+                if !self.is_cgb_mode() =>
+            {
+                0xFF
+            }
+            Address::KEY1_SPEED_SWITCH => {
+                let speed = if self.scheduler.is_double_speed() {
+                    0x80
+                } else {
+                    0
+                };
+
+                0x7E | speed | self.speed_switch_armed as Byte
+            }
+            // Source and destination cannot be read back
+            Address::HDMA1_SOURCE_HIGH..=Address::HDMA4_DESTINATION_LOW => 0xFF,
+            Address::HDMA5_LENGTH_MODE_START => self.hdma.value(),
+            Address::BCPS_BG_PALETTE_INDEX => self.bg_palettes.index_value(),
+            Address::BCPD_BG_PALETTE_DATA => self.bg_palettes.data(),
+            Address::OCPS_OBJ_PALETTE_INDEX => self.obj_palettes.index_value(),
+            Address::OCPD_OBJ_PALETTE_DATA => self.obj_palettes.data(),
             Address::IE_INTERRUPT_ENABLE => self.interrupt_enable.value,
 
             _ => {
@@ -218,62 +527,178 @@ impl ReadMemory for IORegisters {
 
 impl WriteMemory for IORegisters {
     fn write_byte(&mut self, position: Word, value: Byte) {
-        let mut output_debug = OutputDebug::new_with_reason(DebugReason::IOWrite(position, value));
-        let debug_watchpoint = IO_WRITE_WATCHPOINTS.contains(&position);
-
-        if debug_watchpoint {
-            output_debug.push_situation("Before", self.get_debug_values());
-        }
-
         match position {
             Address::P1_JOYPAD => self.p1.parse_byte(value),
             Address::SB_SERIAL_TRANSFER_DATA => self.serial_transfer_data = value,
-            Address::SC_SIO_CONTROL => self.sio_control.update(value),
+            Address::SC_SIO_CONTROL => self.update_serial_control(value),
             Address::UNUSED_FF03 => {
                 println!("Attempt to write at an unused RAM position {position:X}",)
             }
-            Address::DIV_DIVIDER_REGISTER => self.div.reset_value(),
-            Address::TIMA_TIMER_COUNTER => self.tima.value = value,
-            Address::TMA_TIMER_MODULO => self.tma = value,
-            Address::TAC_TIMER_CONTROL => self.timer_control.update(value),
+            Address::DIV_DIVIDER_REGISTER => self.div.reset_value(self.scheduler.cpu_now()),
+            Address::TIMA_TIMER_COUNTER => {
+                self.update_timer(|io| io.tima.set_value(io.scheduler.cpu_now(), value))
+            }
+            Address::TMA_TIMER_MODULO => self.update_timer(|io| {
+                io.tma = value;
+                io.tima.set_tma(io.scheduler.cpu_now(), value);
+            }),
+            Address::TAC_TIMER_CONTROL => self.update_timer(|io| io.timer_control.update(value)),
             0xFF08..=0xFF0E => {
                 println!("Attempt to write at an unused RAM position {position:X}",)
             }
             Address::IF_INTERRUPT_FLAG => self.interrupt_flag.update(value),
-            Address::APU_START..=Address::APU_END => self.apu.write_byte(position, value),
+            Address::APU_START..=Address::APU_END => {
+                self.apu.write_byte(position, value);
+                self.scheduler.schedule(Event::ApuWrite, 0);
+            }
             0xFF30..=0xFF3F => {
                 self.wave_pattern_ram.write_byte(position - 0xFF30, value);
                 self.apu.audio_3_reg_written.wave_pattern = true;
+                self.scheduler.schedule(Event::ApuWrite, 0);
             }
-            Address::LCDC => self.lcdc.update(value),
-            Address::STAT => self.stat.update(value),
+            Address::LCDC => self.update_lcdc(value),
+            Address::STAT => self.update_stat(value),
             Address::SCY_SCROLL_Y => self.scy = value,
             Address::SCX_SCROLL_X => self.scx = value,
-            0xFF44 => self.ly.value = value,
-            0xFF45 => self.lyc = value,
-            Address::DMA => self.dma.update(value),
+            Address::LY_LCDC_Y_COORDINATE => self.ly.value = value,
+            Address::LYC_LY_COMPARE => {
+                self.lyc = value;
+                self.compare_ly();
+            }
+            Address::DMA => {
+                self.dma.start(value);
+                self.scheduler.schedule_cpu(Event::OamDma, Dma::BYTE_CYCLES);
+            }
             Address::BGP_BG_WIN_PALETTE => self.bgp = value,
             Address::OBP1_OBJ_PALETTE => self.obp1 = value,
             Address::OBP2_OBJ_PALETTE => self.obp2 = value,
             Address::WY_WINDOW_Y_POSITION => self.wy = value,
             Address::WX_WINDOW_X_POSITION => self.wx = value,
+            Address::KEY1_SPEED_SWITCH
+            | Address::HDMA1_SOURCE_HIGH..=Address::HDMA5_LENGTH_MODE_START
+            | Address::BCPS_BG_PALETTE_INDEX..=Address::OCPD_OBJ_PALETTE_DATA
+                if !self.is_cgb_mode() => {}
+            Address::KEY1_SPEED_SWITCH => self.speed_switch_armed = value & 1 == 1,
+            Address::HDMA1_SOURCE_HIGH => self.hdma.update_source_high(value),
+            Address::HDMA2_SOURCE_LOW => self.hdma.update_source_low(value),
+            Address::HDMA3_DESTINATION_HIGH => self.hdma.update_destination_high(value),
+            Address::HDMA4_DESTINATION_LOW => self.hdma.update_destination_low(value),
+            // The copy itself is up to `Memory`, which can reach the source
+            Address::HDMA5_LENGTH_MODE_START => self.hdma.start(value),
+            Address::BCPS_BG_PALETTE_INDEX => self.bg_palettes.update_index(value),
+            Address::BCPD_BG_PALETTE_DATA => self.bg_palettes.update_data(value),
+            Address::OCPS_OBJ_PALETTE_INDEX => self.obj_palettes.update_index(value),
+            Address::OCPD_OBJ_PALETTE_DATA => self.obj_palettes.update_data(value),
             Address::IE_INTERRUPT_ENABLE => self.interrupt_enable.update(value),
             Address::UNUSED_FF27..=Address::UNUSED_FF2F => {
                 println!("Attempt to write at an unused RAM position {position:X}")
             }
             _ => panic!("Write address not supported for IORegisters"),
         }
-
-        if debug_watchpoint {
-            output_debug.push_situation("After", self.get_debug_values());
-            output_debug.print()
-        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test_case::test_case;
+
+    #[test_case(Model::Dmg0, 0x18, 0x7E, 0x81, 0x91)]
+    #[test_case(Model::Dmg, 0xAB, 0x7E, 0x84, 0x00)]
+    #[test_case(Model::Cgb, 0x1E, 0x7F, 0x84, 0x00)]
+    fn test_post_boot_registers(model: Model, div: Byte, sc: Byte, stat: Byte, ly: Byte) {
+        let io_registers = IORegisters::post_boot(model);
+
+        assert_eq!(io_registers.read_byte(Address::DIV_DIVIDER_REGISTER), div);
+        assert_eq!(io_registers.read_byte(Address::SC_SIO_CONTROL), sc);
+        assert_eq!(io_registers.read_byte(Address::STAT), stat);
+        assert_eq!(io_registers.read_byte(Address::LY_LCDC_Y_COORDINATE), ly);
+        assert_eq!(io_registers.read_byte(Address::IF_INTERRUPT_FLAG), 0xE1);
+        assert_eq!(io_registers.read_byte(Address::LCDC), 0x91);
+    }
+
+    fn lcd_stat_requested(io_registers: &mut IORegisters) -> bool {
+        let requested = io_registers.interrupt_flag.lcd_stat;
+        io_registers.interrupt_flag.set_lcd_stat(false);
+
+        requested
+    }
+
+    #[test]
+    fn test_stat_interrupt_on_rising_edge_only() {
+        let mut io_registers = IORegisters::power_on(Model::Cgb, true);
+        io_registers.write_byte(Address::LCDC, 0x91);
+
+        // H-Blank and LY = LYC sources, the line goes high right away
+        io_registers.write_byte(Address::STAT, 0x48);
+        assert!(lcd_stat_requested(&mut io_registers));
+
+        // LY = LYC keeps the line high through the next H-Blank
+        io_registers.set_stat_mode(STATMode::SearchOamRam);
+        io_registers.set_stat_mode(STATMode::HBlank);
+        assert!(!lcd_stat_requested(&mut io_registers));
+
+        io_registers.ly_increment();
+        io_registers.set_stat_mode(STATMode::SearchOamRam);
+        io_registers.set_stat_mode(STATMode::HBlank);
+        assert!(lcd_stat_requested(&mut io_registers));
+    }
+
+    #[test]
+    fn test_turning_the_lcd_off_outside_vblank_is_recorded() {
+        let mut io_registers = IORegisters::power_on(Model::Dmg, true);
+        io_registers.write_byte(Address::LCDC, 0x91);
+        io_registers.ly_increment();
+
+        io_registers.write_byte(Address::LCDC, 0x11);
+        assert_eq!(io_registers.take_lcd_off_warning(), Some(1));
+        assert_eq!(io_registers.take_lcd_off_warning(), None);
+    }
+
+    #[test_case(Model::Dmg, true)]
+    #[test_case(Model::Cgb, false)]
+    fn test_stat_write_during_hblank(model: Model, requested: bool) {
+        let mut io_registers = IORegisters::power_on(model, true);
+        io_registers.write_byte(Address::LCDC, 0x91);
+        io_registers.write_byte(Address::LYC_LY_COMPARE, 1);
+
+        io_registers.write_byte(Address::STAT, 0);
+
+        assert_eq!(lcd_stat_requested(&mut io_registers), requested);
+    }
+
+    #[test]
+    fn test_serial_transfer_is_captured_and_completes() {
+        let mut io_registers = IORegisters::default();
+        io_registers.capture_serial();
+
+        for byte in b"Passed" {
+            io_registers.write_byte(Address::SB_SERIAL_TRANSFER_DATA, *byte);
+            io_registers.write_byte(Address::SC_SIO_CONTROL, 0x81);
+
+            for _ in 0..8 {
+                assert_eq!(io_registers.read_byte(Address::SC_SIO_CONTROL) & 0x80, 0x80);
+                assert_eq!(
+                    io_registers.scheduler.deadline(Event::SerialBit),
+                    Some(io_registers.scheduler.now() + 512)
+                );
+
+                io_registers.shift_serial_bit();
+            }
+
+            assert_eq!(io_registers.read_byte(Address::SC_SIO_CONTROL) & 0x80, 0);
+        }
+
+        assert_eq!(io_registers.serial_output(), b"Passed");
+        assert_eq!(
+            io_registers.read_byte(Address::SB_SERIAL_TRANSFER_DATA),
+            0xFF
+        );
+        assert_eq!(
+            io_registers.read_byte(Address::IF_INTERRUPT_FLAG) & 0b1000,
+            0b1000
+        );
+    }
 
     #[test]
     fn test_when_sound_is_turned_off_wave_pattern_register_is_writable() {
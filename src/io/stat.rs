@@ -1,6 +1,6 @@
 use crate::Byte;
 
-#[derive(Clone, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub enum STATMode {
     #[default]
     HBlank,
@@ -9,6 +9,18 @@ pub enum STATMode {
     LCDTransfer,
 }
 
+impl STATMode {
+    /// Cycles the PPU spends in the mode, V-Blank counting a single line.
+    pub fn cycles(&self) -> u64 {
+        match self {
+            STATMode::HBlank => 204,
+            STATMode::VBlank => 456,
+            STATMode::SearchOamRam => 80,
+            STATMode::LCDTransfer => 172,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Stat {
     pub lyc_ly_coincidence: bool,
@@ -45,20 +57,22 @@ impl Stat {
         self.mode = mode
     }
 
+    /// Only the interrupt sources can be written, the mode and coincidence flag are read-only.
     pub fn update(&mut self, value: Byte) {
         self.lyc_ly_coincidence = value & 0b1000000 == 0b1000000;
         self.mode_2 = value & 0b100000 == 0b100000;
         self.mode_1 = value & 0b10000 == 0b10000;
         self.mode_0 = value & 0b1000 == 0b1000;
-        self.coincidence_flag = value & 0b100 == 0b100;
-
-        self.mode = match value & 0b11 {
-            0b00 => STATMode::HBlank,
-            0b01 => STATMode::VBlank,
-            0b10 => STATMode::SearchOamRam,
-            0b11 => STATMode::LCDTransfer,
-            _ => panic!("Unrecognized STAT mode"),
-        };
+    }
+
+    /**
+     * State of the internal STAT interrupt line: any enabled source being active keeps it high.
+     */
+    pub fn interrupt_line(&self) -> bool {
+        (self.mode_0 && self.mode == STATMode::HBlank)
+            || (self.mode_1 && self.mode == STATMode::VBlank)
+            || (self.mode_2 && self.mode == STATMode::SearchOamRam)
+            || (self.lyc_ly_coincidence && self.coincidence_flag)
     }
 }
 
@@ -77,7 +91,7 @@ impl From<&Stat> for Byte {
 #[cfg(test)]
 mod tests {
     use crate::Byte;
-    use crate::io::stat::Stat;
+    use crate::io::stat::{STATMode, Stat};
 
     #[test]
     fn test_ok() {
@@ -86,7 +100,34 @@ mod tests {
         for number in 0..=0b1111111 {
             item.update(number);
 
-            assert_eq!(Byte::from(&item), number | 0b10000000);
+            assert_eq!(Byte::from(&item), number & 0b1111000 | 0b10000000);
         }
     }
+
+    #[test]
+    fn test_mode_and_coincidence_are_read_only() {
+        let mut item = Stat::default();
+        item.set_mode(STATMode::VBlank);
+        item.coincidence_flag = true;
+
+        item.update(0b0000010);
+
+        assert_eq!(Byte::from(&item), 0b10000101);
+    }
+
+    #[test]
+    fn test_interrupt_line_follows_enabled_sources() {
+        let mut item = Stat::default();
+        assert!(!item.interrupt_line());
+
+        item.update(0b1000);
+        assert!(item.interrupt_line());
+
+        item.set_mode(STATMode::LCDTransfer);
+        assert!(!item.interrupt_line());
+
+        item.update(0b1000000);
+        item.coincidence_flag = true;
+        assert!(item.interrupt_line());
+    }
 }
@@ -1,26 +1,221 @@
 use crate::Byte;
 
+/**
+ * Timer counter, derived from the cycles gone by since it was last written or reloaded instead of
+ * being stepped. Cycles short of the next increment are carried over, also when the clock changes.
+ *
+ * After overflowing it reads $00 for an M-cycle, then TMA is loaded and the interrupt raised.
+ */
 #[derive(Default)]
 pub struct Tima {
-    pub value: Byte,
-    remaining_timer_cycles: u32,
+    value: Byte,
+    remaining_timer_cycles: u64,
+    synced_at: u64,
+    // Cycle TMA is going to be loaded at, after an overflow
+    reload_at: Option<u64>,
+    // Cycle TMA was last loaded at, when writes to TIMA are lost
+    reloaded_at: Option<u64>,
 }
 
 impl Tima {
-    pub fn reset_cycles(&mut self) {
-        self.remaining_timer_cycles = 0;
+    /// Cycles from an overflow until TMA is loaded.
+    const RELOAD_DELAY: u64 = 4;
+
+    /**
+     * Value and leftover cycles at `now`, with the number of overflows since the last sync and
+     * the cycle of the last of them.
+     */
+    fn count(&self, now: u64, divider: u64, tma: Byte) -> (Byte, u64, u64, Option<u64>) {
+        let cycles = self.remaining_timer_cycles + now.saturating_sub(self.synced_at);
+        let increments = cycles / divider;
+        let to_overflow = 0x100 - self.value as u64;
+
+        if increments < to_overflow {
+            return (
+                (self.value as u64 + increments) as Byte,
+                cycles % divider,
+                0,
+                None,
+            );
+        }
+
+        let period = 0x100 - tma as u64;
+        let since_overflow = (increments - to_overflow) % period;
+        let last_overflow = increments - since_overflow;
+        let overflowed_at = self.synced_at + last_overflow * divider - self.remaining_timer_cycles;
+
+        (
+            (tma as u64 + since_overflow) as Byte,
+            cycles % divider,
+            (increments - to_overflow) / period + 1,
+            Some(overflowed_at),
+        )
+    }
+
+    fn is_reloading(reload_at: Option<u64>, now: u64) -> bool {
+        reload_at.is_some_and(|reload_at| now < reload_at)
+    }
+
+    pub fn value(&self, now: u64, divider: Option<u64>, tma: Byte) -> Byte {
+        let (value, reload_at) = match divider {
+            Some(divider) => {
+                let (value, _, _, overflowed_at) = self.count(now, divider, tma);
+
+                (
+                    value,
+                    overflowed_at
+                        .map(|cycle| cycle + Self::RELOAD_DELAY)
+                        .or(self.reload_at),
+                )
+            }
+            None => (self.value, self.reload_at),
+        };
+
+        if Self::is_reloading(reload_at, now) {
+            0
+        } else {
+            value
+        }
+    }
+
+    /**
+     * Brings the counter up to `now`, `divider` being `None` while the timer is stopped. Returns
+     * whether TMA has been loaded since, raising the interrupt.
+     */
+    pub fn sync(&mut self, now: u64, divider: Option<u64>, tma: Byte) -> bool {
+        let mut raised = self.reload_at.is_some_and(|reload_at| reload_at <= now);
+
+        match divider {
+            Some(divider) => {
+                let (value, remaining_timer_cycles, overflows, overflowed_at) =
+                    self.count(now, divider, tma);
+                self.value = value;
+                self.remaining_timer_cycles = remaining_timer_cycles;
+
+                raised |= overflows > 1;
+                if let Some(overflowed_at) = overflowed_at {
+                    self.reload_at = Some(overflowed_at + Self::RELOAD_DELAY);
+                }
+            }
+            None => self.remaining_timer_cycles = 0,
+        }
+
+        self.synced_at = now;
+
+        if let Some(reload_at) = self.reload_at.filter(|reload_at| *reload_at <= now) {
+            self.reload_at = None;
+            self.reloaded_at = Some(reload_at);
+            raised = true;
+        }
+
+        raised
+    }
+
+    /**
+     * Has to be synced first. Written while it reads $00 after overflowing, the reload is
+     * cancelled; written as TMA is being loaded, TMA wins.
+     */
+    pub fn set_value(&mut self, now: u64, value: Byte) {
+        if self.reloaded_at == Some(now) {
+            return;
+        }
+
+        self.reload_at = None;
+        self.value = value;
+    }
+
+    /// Has to be synced first. TMA written before it is loaded, or as it is, is the one loaded.
+    pub fn set_tma(&mut self, now: u64, tma: Byte) {
+        if self.reload_at.is_some() || self.reloaded_at == Some(now) {
+            self.value = tma;
+        }
+    }
+
+    /// Cycles from the last sync until TMA is next loaded, raising the interrupt.
+    pub fn cycles_to_reload(&self, divider: u64) -> u64 {
+        match self.reload_at {
+            Some(reload_at) => reload_at - self.synced_at,
+            None => {
+                (0x100 - self.value as u64) * divider - self.remaining_timer_cycles
+                    + Self::RELOAD_DELAY
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_counts_cycles_carrying_leftovers() {
+        let mut tima = Tima::default();
+
+        assert!(!tima.sync(20, Some(16), 0));
+        assert_eq!(tima.value(20, Some(16), 0), 1);
+        assert_eq!(tima.value(31, Some(16), 0), 1);
+        assert_eq!(tima.value(32, Some(16), 0), 2);
+    }
+
+    #[test]
+    fn it_reloads_from_tma_on_overflow() {
+        let mut tima = Tima::default();
+        tima.set_value(0, 0xFE);
+
+        assert_eq!(tima.cycles_to_reload(16), 36);
+        assert!(tima.sync(48, Some(16), 0x80));
+        assert_eq!(tima.value(48, Some(16), 0x80), 0x81);
     }
 
-    pub fn step(&mut self, last_instruction_cycles: u8, divider: u32) -> bool {
-        self.remaining_timer_cycles += last_instruction_cycles as u32;
+    #[test]
+    fn it_reads_0_for_an_m_cycle_before_reloading() {
+        let mut tima = Tima::default();
+        tima.set_value(0, 0xFF);
 
-        let to_add = (self.remaining_timer_cycles / divider) as u8;
-        self.remaining_timer_cycles %= divider;
+        assert_eq!(tima.value(16, Some(16), 0x80), 0x00);
+        assert!(!tima.sync(16, Some(16), 0x80));
+        assert_eq!(tima.value(16, Some(16), 0x80), 0x00);
+        assert_eq!(tima.cycles_to_reload(16), 4);
+
+        assert!(tima.sync(20, Some(16), 0x80));
+        assert_eq!(tima.value(20, Some(16), 0x80), 0x80);
+    }
+
+    #[test]
+    fn it_cancels_the_reload_when_written_while_reading_0() {
+        let mut tima = Tima::default();
+        tima.set_value(0, 0xFF);
+
+        tima.sync(16, Some(16), 0x80);
+        tima.set_value(16, 0x10);
+
+        assert!(!tima.sync(20, Some(16), 0x80));
+        assert_eq!(tima.value(20, Some(16), 0x80), 0x10);
+    }
+
+    #[test]
+    fn it_loads_tma_over_writes_on_the_reload_cycle() {
+        let mut tima = Tima::default();
+        tima.set_value(0, 0xFF);
+
+        assert!(tima.sync(20, Some(16), 0x80));
+        tima.set_value(20, 0x10);
+        assert_eq!(tima.value(20, Some(16), 0x80), 0x80);
+
+        tima.set_tma(20, 0x90);
+        assert_eq!(tima.value(20, Some(16), 0x90), 0x90);
+    }
 
-        let addition_result = self.value.overflowing_add(to_add);
+    #[test]
+    fn it_does_not_count_while_stopped() {
+        let mut tima = Tima::default();
+        tima.sync(10, Some(16), 0);
+        tima.sync(100, None, 0);
 
-        self.value = addition_result.0;
+        assert_eq!(tima.value(200, None, 0), 0);
 
-        addition_result.1
+        // Starting again begins a whole new period
+        assert_eq!(tima.value(115, Some(16), 0), 0);
+        assert_eq!(tima.value(116, Some(16), 0), 1);
     }
 }
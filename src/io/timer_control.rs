@@ -13,13 +13,18 @@ impl TimerControl {
         self.input_clock_select = value & 0b11;
     }
 
-    pub fn get_divider(&self) -> u32 {
-        match self.input_clock_select {
+    /// Cycles per increment of TIMA, none while the timer is stopped.
+    pub fn divider(&self) -> Option<u64> {
+        if !self.started {
+            return None;
+        }
+
+        Some(match self.input_clock_select {
             0 => 1024,
             1 => 16,
             2 => 64,
             3 => 256,
             _ => panic!("Invalid input clock select"),
-        }
+        })
     }
 }
@@ -8,42 +8,137 @@ mod cartridge;
 mod configuration;
 mod cpu;
 mod debug;
+mod disassembler;
+mod emulator;
 mod gpu;
 mod io;
 mod memory;
+mod model;
+mod motherboard;
+mod scheduler;
+mod test_runner;
 mod utils;
 
 use crate::audio::AudioUnit;
 use crate::audio::audio_unit_output::CpalAudioUnitOutput;
 use crate::cartridge::Cartridge;
-use crate::configuration::{Configuration, RuntimeConfig};
+use crate::configuration::{
+    Configuration, DisassembleConfiguration, Mode, RuntimeConfig, TestConfiguration,
+};
+use crate::debug::breakpoints::Breakpoints;
+use crate::debug::code_data_log::CodeDataLog;
+use crate::debug::crash_report::panic_message;
+use crate::debug::debugger::DebuggerCommand;
+use crate::debug::profiler::Profiler;
+use crate::debug::trace::TraceLogger;
+use crate::debug::{DebugReason, Debuggable, OutputDebug};
+use crate::disassembler::ROM_BANK_SIZE;
+use crate::disassembler::listing::{CodeMap, write_listing};
+use crate::disassembler::symbols::SymbolTable;
+use crate::emulator::Emulator;
 use crate::gpu::color::Color;
-use crate::io::registers::IORegisters;
-use crate::memory::bootstrap_rom::BootstrapRom;
-use cpu::Cpu;
 use gpu::Gpu;
-use image::ImageBuffer;
 use io::joypad::JoypadHandler;
-use memory::Memory;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use piston_window::*;
+use std::fs::File;
+use std::io::{BufRead, BufWriter};
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
 use std::sync::{Arc, mpsc};
 
 const APP_NAME: &str = "RustieGB";
 const WINDOW_SIZE_MULTIPLIER: u32 = 4;
 
+/// What the emulation thread gets asked to do.
+enum EmulationCommand {
+    // The window is ready for another frame
+    Frame,
+    Debugger(DebuggerCommand),
+}
+
 type Byte = u8;
 type Word = u16;
 type SignedByte = i8;
 
 fn main() {
-    let configuration = Configuration::from_command(APP_NAME);
+    match Mode::from_command(APP_NAME) {
+        Mode::Emulate(configuration) => emulate(configuration),
+        Mode::Disassemble(configuration) => disassemble(configuration),
+        Mode::Test(configuration) => run_tests(configuration),
+    }
+}
+
+fn run_tests(configuration: TestConfiguration) {
+    let results = test_runner::run_directory(
+        Path::new(&configuration.directory),
+        configuration.model,
+        configuration.cycles,
+    );
+
+    test_runner::write_table(&mut std::io::stdout().lock(), &results)
+        .expect("Could not write test results");
+
+    if let Some(report_path) = configuration.report_path.as_deref() {
+        let mut report =
+            BufWriter::new(File::create(report_path).expect("Could not create report"));
+
+        test_runner::write_report(&mut report, &results).expect("Could not write report");
+    }
+
+    let expected = configuration.expected_path.as_deref().map(|path| {
+        test_runner::read_passed_from_report(
+            &std::fs::read_to_string(path).expect("Could not read expected report"),
+        )
+    });
+
+    let regressions = test_runner::regressions(&results, expected.as_ref());
+
+    if !regressions.is_empty() {
+        println!("Regressions: {}", regressions.join(", "));
+        std::process::exit(1);
+    }
+}
+
+fn disassemble(configuration: DisassembleConfiguration) {
+    let cartridge = Cartridge::new_from_path(configuration.rom_file.as_str());
+    let data = cartridge.data.as_slice();
+
+    let symbols = match configuration.symbols_path.as_deref() {
+        Some(path) => SymbolTable::load(path).expect("Could not read symbols file"),
+        None => SymbolTable::default(),
+    };
+
+    let mut code_map = CodeMap::new(data.len());
+
+    if let Some(path) = configuration.code_data_log_path.as_deref() {
+        code_map.apply_code_data_log(data, &CodeDataLog::load_or_new(Path::new(path), data.len()));
+    }
+
+    code_map.trace(data);
+
+    let banks: Vec<u16> = match configuration.bank {
+        Some(bank) => vec![bank],
+        None => (0..data.len().div_ceil(ROM_BANK_SIZE) as u16).collect(),
+    };
+
+    let mut output = BufWriter::new(std::io::stdout().lock());
+
+    write_listing(
+        &mut output,
+        data,
+        &code_map,
+        &symbols,
+        &banks,
+        configuration.range,
+    )
+    .expect("Could not write disassembly");
+}
+
+fn emulate(configuration: Configuration) {
     let runtime_config = Arc::new(RwLock::new(RuntimeConfig::default()));
 
     // --- Read ROM
-    let bootstrap_rom =
-        BootstrapRom::new_from_optional_path(configuration.bootstrap_path.as_deref());
-
     let cartridge = Cartridge::new_from_path(configuration.rom_file.as_str());
 
     if configuration.debug_header {
@@ -51,119 +146,213 @@ fn main() {
     }
 
     let window_title = format!("{} - {}", cartridge.header.title, APP_NAME);
+    let crash_report_path = Path::new(&configuration.rom_file).with_extension("crash.log");
 
     // --- Setting up GB components
-    let io_registers = Arc::new(RwLock::new(IORegisters::default()));
-    let memory = Arc::new(RwLock::new(Memory::new(
-        io_registers.clone(),
+    let mut emulator = Emulator::new(
         cartridge,
-        bootstrap_rom,
-    )));
-    let joypad_handler = JoypadHandler::new(io_registers.clone(), runtime_config.clone());
+        configuration.bootstrap_path.as_deref(),
+        configuration.model,
+    );
+
+    let symbols = match configuration.symbols_path.as_deref() {
+        Some(path) => SymbolTable::load(path).expect("Could not read symbols file"),
+        None => SymbolTable::default(),
+    };
+    emulator.cpu.load_symbols(symbols.clone());
+
+    let profile_path = configuration.profile_path.clone();
+    let profiler = profile_path.as_ref().map(|_| {
+        let profiler = Arc::new(Mutex::new(Profiler::default()));
+        emulator.cpu.set_profiler(profiler.clone());
+
+        profiler
+    });
+
+    for breakpoint in configuration.breakpoints.iter().cloned() {
+        emulator.cpu.add_breakpoint(breakpoint);
+    }
+
+    if let Some(path) = configuration.breakpoints_path.as_deref() {
+        for breakpoint in
+            Breakpoints::load(Path::new(path)).expect("Could not read breakpoints file")
+        {
+            emulator.cpu.add_breakpoint(breakpoint);
+        }
+    }
+
+    let code_data_log_path = configuration.code_data_log_path.clone();
+    if let Some(path) = code_data_log_path.as_deref() {
+        let mut memory = emulator.memory.write();
+        let code_data_log = CodeDataLog::load_or_new(Path::new(path), memory.rom_size());
+        memory.enable_code_data_log(code_data_log);
+    }
 
-    let canvas = Arc::new(RwLock::new(ImageBuffer::new(
-        Gpu::PIXEL_WIDTH as u32,
-        Gpu::PIXEL_HEIGHT as u32,
-    )));
+    let io_registers = emulator.io_registers.clone();
+    let memory = emulator.memory.clone();
+    let canvas = emulator.canvas.clone();
+    let joypad_handler = JoypadHandler::new(io_registers.clone(), runtime_config.clone());
 
-    let memory_thread = memory.clone();
-    let io_registers_thread = io_registers.clone();
-    let canvas_thread = canvas.clone();
     let runtime_config_thread = runtime_config.clone();
     let (sx, rx) = mpsc::channel();
 
-    std::thread::spawn(move || {
-        let mut cpu = Cpu::new(
-            memory_thread.clone(),
-            io_registers_thread.clone(),
-            configuration.bootstrap_path.is_some(),
-        );
-        let mut gpu = Gpu::new(memory_thread.clone(), io_registers_thread.clone());
+    if configuration.debugger {
+        runtime_config.write().set_paused(true);
+        read_debugger_commands(sx.clone());
+    }
 
+    std::thread::spawn(move || {
         let audio_unit_output = CpalAudioUnitOutput::new();
 
-        let mut audio_unit = AudioUnit::new(audio_unit_output, io_registers_thread.clone());
+        let mut audio_unit = AudioUnit::new(audio_unit_output, emulator.io_registers.clone());
 
-        'main_loop: loop {
-            while runtime_config_thread.read().cpu_has_available_ccycles() {
-                if runtime_config_thread.read().has_been_reset() {
-                    let mut rcw = runtime_config_thread.write();
+        let mut trace_logger = configuration.trace_file.as_deref().map(|path| {
+            TraceLogger::new(
+                path,
+                configuration.trace_start.clone(),
+                configuration.trace_stop.clone(),
+            )
+            .expect("Could not create trace file")
+        });
 
-                    cpu.reset();
-                    rcw.reset_available_ccycles();
-                    rcw.set_reset(false);
+        let write_crash_report = |emulator: &Emulator, reason: &str| match emulator
+            .write_crash_report(&crash_report_path, reason)
+        {
+            Ok(()) => println!(
+                "{reason}, crash report written to {}",
+                crash_report_path.display()
+            ),
+            Err(error) => println!("Could not write {}: {error}", crash_report_path.display()),
+        };
+        let mut lock_up_reported = false;
 
-                    continue 'main_loop;
-                }
+        loop {
+            // The window thread only changes these between frames
+            let (mut available_cycles, debug, muted) = {
+                let mut rcw = runtime_config_thread.write();
 
-                let last_instruction_cycles =
-                    cpu.step(runtime_config_thread.read().is_debug(), configuration.trace);
+                if rcw.has_been_reset() {
+                    emulator.reset();
+                    audio_unit.reset();
+                    lock_up_reported = false;
 
-                {
-                    runtime_config_thread.write().available_cycles -=
-                        last_instruction_cycles as i32;
+                    rcw.reset_available_ccycles();
+                    rcw.set_reset(false);
                 }
 
-                let check_vblank;
-                let check_lcd_stat;
-                let check_timer_overflow;
-                let check_joystick;
-
-                {
-                    memory_thread.write().step(last_instruction_cycles);
+                if rcw.take_crash_report_request() {
+                    write_crash_report(&emulator, "Crash report requested");
                 }
-                {
-                    let io_registers = io_registers_thread.read();
 
-                    check_vblank =
-                        io_registers.interrupt_enable.vblank && io_registers.interrupt_flag.vblank;
+                let available_cycles = if rcw.is_paused() {
+                    0
+                } else {
+                    rcw.available_cycles
+                };
 
-                    check_lcd_stat = io_registers.interrupt_enable.lcd_stat
-                        && io_registers.interrupt_flag.lcd_stat;
+                (available_cycles, rcw.is_debug(), rcw.muted)
+            };
+            let mut cycles_run = 0;
 
-                    check_timer_overflow = io_registers.interrupt_enable.timer_overflow
-                        && io_registers.interrupt_flag.timer_overflow;
+            while available_cycles > 0 {
+                if let Some(trace_logger) = trace_logger.as_mut()
+                    && emulator.cpu.is_about_to_run()
+                {
+                    trace_logger.log(
+                        &emulator.cpu.registers,
+                        &emulator.memory.read(),
+                        emulator.frame(),
+                    );
+                }
 
-                    check_joystick = io_registers.interrupt_enable.p10_13_transition
-                        && io_registers.interrupt_flag.p10_13_transition;
+                let last_instruction_cycles =
+                    match std::panic::catch_unwind(AssertUnwindSafe(|| {
+                        emulator.step(debug, configuration.trace)
+                    })) {
+                        Ok(cycles) => cycles,
+                        Err(panic) => {
+                            write_crash_report(
+                                &emulator,
+                                &format!("Panic: {}", panic_message(&*panic)),
+                            );
+                            std::panic::resume_unwind(panic);
+                        }
+                    };
+
+                if let Some(frame_sequencer_ticks) = emulator.take_audio_update() {
+                    audio_unit.step(frame_sequencer_ticks, muted);
                 }
 
-                {
-                    gpu.step(last_instruction_cycles, &mut canvas_thread.write());
+                if debug && let Some(line) = emulator.io_registers.write().take_lcd_off_warning() {
+                    let mut output_debug =
+                        OutputDebug::new_with_reason(DebugReason::LcdOffOutsideVBlank(line));
+                    output_debug.push_situation(
+                        "IO registers",
+                        emulator.io_registers.read().get_debug_values(),
+                    );
+                    output_debug.print();
                 }
 
-                let muted = { runtime_config_thread.read().muted };
+                let stack_overflow = emulator.cpu.take_stack_overflow();
+                if debug && let Some(overflow) = stack_overflow {
+                    let mut output_debug =
+                        OutputDebug::new_with_reason(DebugReason::StackOverflow(overflow));
+                    output_debug.push_situation("CPU", emulator.cpu.registers.get_debug_values());
+                    output_debug.print();
+                }
 
-                audio_unit.step(last_instruction_cycles, muted);
+                if let Some(hit) = emulator.cpu.take_breakpoint_hit() {
+                    let mut output_debug =
+                        OutputDebug::new_with_reason(DebugReason::Breakpoint(hit));
+                    output_debug.push_situation("CPU", emulator.cpu.registers.get_debug_values());
+                    output_debug.print();
+                    println!("{}\n", emulator.cpu.backtrace().join("\n"));
 
-                if check_vblank {
-                    cpu.vblank_interrupt();
+                    runtime_config_thread.write().set_paused(true);
+                    break;
+                }
 
-                    continue;
+                if !lock_up_reported && emulator.is_locked_up() {
+                    write_crash_report(&emulator, "CPU locked up");
+                    lock_up_reported = true;
                 }
 
-                if check_lcd_stat {
-                    cpu.lcd_stat_interrupt();
+                if let Some(reason) = emulator.cpu.take_lock_reason() {
+                    let mut output_debug = OutputDebug::new_with_reason(reason);
+                    output_debug
+                        .push_situation("Locked", emulator.cpu.registers.get_debug_values());
+                    output_debug.print();
+                    println!("{}\n", emulator.cpu.backtrace().join("\n"));
 
-                    continue;
+                    runtime_config_thread.write().set_paused(true);
+                    break;
                 }
 
-                if check_timer_overflow {
-                    cpu.timer_overflow_interrupt();
+                available_cycles -= last_instruction_cycles as i32;
+                cycles_run += last_instruction_cycles as i32;
+            }
 
-                    continue;
-                }
+            // The screen and the debugger look at the rest of the console
+            emulator.cpu.catch_up();
+
+            {
+                runtime_config_thread.write().available_cycles -= cycles_run;
+            }
 
-                // TODO: Serial transfer
+            if let Some(trace_logger) = trace_logger.as_mut() {
+                trace_logger.flush();
+            }
 
-                if check_joystick {
-                    cpu.p10_p13_transition_interrupt();
+            match rx.recv().expect("Could not receive from thread") {
+                EmulationCommand::Frame => {}
+                EmulationCommand::Debugger(command) => {
+                    let resume = command
+                        .execute(&mut emulator, &mut std::io::stdout())
+                        .expect("Could not write debugger output");
 
-                    continue;
+                    runtime_config_thread.write().set_paused(!resume);
                 }
             }
-
-            rx.recv().expect("Could not receive from thread");
         }
     });
 
@@ -232,7 +421,77 @@ fn main() {
             });
 
             runtime_config.write().reset_available_ccycles();
-            sx.send(1).expect("Could not send to thread");
+            sx.send(EmulationCommand::Frame)
+                .expect("Could not send to thread");
         });
     }
+
+    memory.read().flush_save_ram();
+
+    if let Some(path) = code_data_log_path {
+        memory
+            .read()
+            .save_code_data_log(Path::new(&path))
+            .expect("Could not write code/data log");
+    }
+
+    if let (Some(profiler), Some(profile_path)) = (profiler, profile_path) {
+        write_profile(&profiler.lock(), &symbols, Path::new(&profile_path));
+    }
+}
+
+/**
+ * Reads debugger commands from the standard input on their own thread. An empty line repeats the
+ * last command, as in gdb.
+ */
+fn read_debugger_commands(sx: mpsc::Sender<EmulationCommand>) {
+    println!("Paused, enter \"help\" for debugger commands");
+
+    std::thread::spawn(move || {
+        let mut last_command = None;
+
+        for line in std::io::stdin().lock().lines() {
+            let line = line.expect("Could not read from the standard input");
+
+            let command = match line.trim() {
+                "" => last_command.clone(),
+                line => match line.parse::<DebuggerCommand>() {
+                    Ok(command) => Some(command),
+                    Err(error) => {
+                        println!("{error}");
+                        continue;
+                    }
+                },
+            };
+
+            if let Some(command) = command {
+                last_command = Some(command.clone());
+
+                if sx.send(EmulationCommand::Debugger(command)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+fn write_profile(profiler: &Profiler, symbols: &SymbolTable, path: &Path) {
+    let folded_path = path.with_extension("folded");
+
+    let mut report = BufWriter::new(File::create(path).expect("Could not create profile"));
+    profiler
+        .write_report(&mut report, symbols)
+        .expect("Could not write profile");
+
+    let mut folded =
+        BufWriter::new(File::create(&folded_path).expect("Could not create folded profile"));
+    profiler
+        .write_folded(&mut folded, symbols)
+        .expect("Could not write folded profile");
+
+    println!(
+        "Profile written to {} and {}",
+        path.display(),
+        folded_path.display()
+    );
 }
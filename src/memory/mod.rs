@@ -1,19 +1,26 @@
+use crate::bus::Bus;
 use crate::bus::address::Address;
 use crate::cartridge::Cartridge;
+use crate::cartridge::cartridge_header::CartridgeHeader;
+use crate::debug::breakpoints::{AccessKind, MemoryAccess};
+use crate::debug::code_data_log::CodeDataLog;
+use crate::io::hdma::Hdma;
 use crate::io::registers::IORegisters;
 use crate::memory::bootstrap_rom::BootstrapRom;
-use crate::memory::internal_ram_8k_memory_sector::InternalRam8kMemorySector;
+use crate::memory::internal_ram_4k_memory_sector::InternalRam4kMemorySector;
 use crate::memory::internal_ram_memory_sector::InternalRamMemorySector;
 use crate::memory::memory_sector::{ReadMemory, WriteMemory};
-use crate::memory::oam_memory_sector::{OAM_MEMORY_SECTOR_SIZE, OamMemorySector};
+use crate::memory::oam_memory_sector::{OamCorruption, OamMemorySector};
 use crate::memory::video_ram_8k_memory_sector::VideoRam8kMemorySector;
-use crate::utils::math::{two_bytes_to_word, word_to_two_bytes};
-use crate::{Byte, SignedByte, Word};
-use parking_lot::RwLock;
+use crate::model::Model;
+use crate::utils::math::two_bytes_to_word;
+use crate::{Byte, Word};
+use parking_lot::{Mutex, RwLock};
+use std::path::Path;
 use std::sync::Arc;
 
 pub mod bootstrap_rom;
-pub mod internal_ram_8k_memory_sector;
+pub mod internal_ram_4k_memory_sector;
 pub mod internal_ram_memory_sector;
 pub mod memory_sector;
 pub mod oam_entry;
@@ -27,15 +34,36 @@ pub struct Memory {
 
     cartridge: Cartridge,
 
-    video_ram: VideoRam8kMemorySector,
-    switchable_ram_bank: InternalRam8kMemorySector,
-    internal_ram_8k: InternalRam8kMemorySector,
-    pub oam_ram: OamMemorySector,
+    // Bank 1 is only reachable in CGB mode, where it holds more tiles and the BG map attributes
+    video_ram: [VideoRam8kMemorySector; 2],
+    video_ram_bank: usize,
+    // Bank 0 at $C000, and the one SVBK selects at $D000, from 1 to 7 in CGB mode
+    work_ram: [InternalRam4kMemorySector; 8],
+    work_ram_bank: usize,
+    // Behind a lock as reads can corrupt it, see `corrupt_oam`
+    pub oam_ram: Mutex<OamMemorySector>,
 
     pub io_registers: Arc<RwLock<IORegisters>>,
 
     // FF80 - FFFE
     internal_ram: InternalRamMemorySector,
+
+    // Behind a lock as ROM reads are logged through shared references
+    code_data_log: Option<Mutex<CodeDataLog>>,
+
+    watched_ranges: Vec<(AccessKind, Word, Word)>,
+    accesses: Mutex<Vec<MemoryAccess>>,
+
+    // Lets the program reach VRAM and OAM whatever the PPU is doing, for debugging
+    ignore_access_restrictions: bool,
+
+    // Address and value of the last byte copied by the running OAM DMA transfer
+    dma_bus: Option<(Word, Byte)>,
+
+    // Cycles VRAM DMA has taken from the CPU, which it still has to wait for
+    hdma_stall: u64,
+
+    cgb_mode: bool,
 }
 
 impl Memory {
@@ -43,20 +71,54 @@ impl Memory {
         io_registers: Arc<RwLock<IORegisters>>,
         cartridge: Cartridge,
         bootstrap_rom: Option<BootstrapRom>,
+        model: Model,
     ) -> Self {
-        Self {
+        let load_logo = bootstrap_rom.is_none() && model.leaves_logo_in_vram();
+
+        let mut memory = Self {
             bootstrap_rom,
             cartridge,
-            video_ram: VideoRam8kMemorySector::default(),
-            switchable_ram_bank: InternalRam8kMemorySector::default(),
-            internal_ram_8k: InternalRam8kMemorySector::default(),
+            video_ram: Default::default(),
+            video_ram_bank: 0,
+            work_ram: Default::default(),
+            work_ram_bank: 1,
             io_registers,
             internal_ram: InternalRamMemorySector::default(),
-            oam_ram: OamMemorySector::default(),
+            oam_ram: Mutex::new(OamMemorySector::default()),
+            code_data_log: None,
+            watched_ranges: Vec::new(),
+            accesses: Mutex::new(Vec::new()),
+            ignore_access_restrictions: false,
+            dma_bus: None,
+            hdma_stall: 0,
+            cgb_mode: model.is_cgb_mode(),
+        };
+
+        if load_logo {
+            memory.load_boot_logo();
         }
+
+        memory
     }
 
     pub fn read_byte(&self, position: Word) -> Byte {
+        if let Some(code_data_log) = &self.code_data_log
+            && let Some(offset) = self.rom_offset(position)
+        {
+            code_data_log.lock().mark_data(offset);
+        }
+
+        let value = self.bus_read(position);
+        self.record_access(AccessKind::Read, position, value);
+        self.corrupt_oam(position, OamCorruption::Read);
+
+        value
+    }
+
+    /**
+     * Same as `read_byte` without counting as an access of the program, for debugging tools.
+     */
+    pub fn peek_byte(&self, position: Word) -> Byte {
         // Bootstrap rom
         if position < Address::CARTRIDGE_START
             && let Some(bootstrap_rom) = &self.bootstrap_rom
@@ -66,39 +128,80 @@ impl Memory {
 
         match position {
             0..=0x7FFF => self.cartridge.read_byte(position),
-            0x8000..=0x9FFF => self.video_ram.read_byte(position - 0x8000),
+            0x8000..=0x9FFF => self.peek_vram(self.video_ram_bank, position),
             0xA000..=0xBFFF => self.cartridge.read_byte(position),
-            0xC000..=0xDFFF => self.internal_ram_8k.read_byte(position - 0xC000),
-            0xE000..=0xFDFF => self.internal_ram_8k.read_byte(position - 0xE000),
-            0xFE00..=0xFE9F => self.oam_ram.read_byte(position - 0xFE00),
+            0xC000..=0xFDFF => self.read_work_ram(position),
+            0xFE00..=0xFE9F => self.oam_ram.lock().read_byte(position - 0xFE00),
             Address::IO_REGISTERS_START..=Address::IO_REGISTERS_END => {
                 self.io_registers.read().read_byte(position)
             }
+            Address::VBK_VRAM_BANK if self.cgb_mode => 0xFE | self.video_ram_bank as Byte,
+            Address::SVBK_WRAM_BANK if self.cgb_mode => 0xF8 | self.work_ram_bank as Byte,
+            Address::KEY1_SPEED_SWITCH
+            | Address::HDMA1_SOURCE_HIGH..=Address::HDMA5_LENGTH_MODE_START
+            | Address::BCPS_BG_PALETTE_INDEX..=Address::OCPD_OBJ_PALETTE_DATA => {
+                self.io_registers.read().read_byte(position)
+            }
             0xFF80..=0xFFFE => self.internal_ram.read_byte(position - 0xFF80),
             Address::IE_INTERRUPT_ENABLE => self.io_registers.read().read_byte(position),
             _ => 0xFF,
         }
     }
 
-    pub fn read_signed_byte(&self, position: Word) -> SignedByte {
-        self.read_byte(position) as SignedByte
+    /**
+     * Reads VRAM from either bank, whichever VBK selects, for the PPU to fetch tiles and BG map
+     * attributes.
+     */
+    pub fn peek_vram(&self, bank: usize, position: Word) -> Byte {
+        self.video_ram[bank].read_byte(position - 0x8000)
     }
 
-    pub fn read_word(&self, position: Word) -> Word {
-        two_bytes_to_word(self.read_byte(position + 1), self.read_byte(position))
+    pub fn peek_word(&self, position: Word) -> Word {
+        two_bytes_to_word(self.peek_byte(position + 1), self.peek_byte(position))
     }
 
     pub fn write_byte(&mut self, position: Word, value: Byte) {
+        self.record_access(AccessKind::Write, position, value);
+        self.corrupt_oam(position, OamCorruption::Write);
+
+        if self.dma_conflict(position).is_none() && self.is_accessible(position) {
+            self.poke_byte(position, value);
+        }
+    }
+
+    /**
+     * Same as `write_byte` without counting as an access of the program, nor being blocked by the
+     * PPU, for debugging tools.
+     */
+    pub fn poke_byte(&mut self, position: Word, value: Byte) {
         match position {
             0..=0x7FFF => self.cartridge.write_byte(position, value),
-            0x8000..=0x9FFF => self.video_ram.write_byte(position - 0x8000, value),
+            0x8000..=0x9FFF => {
+                self.video_ram[self.video_ram_bank].write_byte(position - 0x8000, value)
+            }
             0xA000..=0xBFFF => self.cartridge.write_byte(position, value),
-            0xC000..=0xDFFF => self.internal_ram_8k.write_byte(position - 0xC000, value),
-            0xE000..=0xFDFF => self.internal_ram_8k.write_byte(position - 0xE000, value),
-            0xFE00..=0xFE9F => self.oam_ram.write_byte(position - 0xFE00, value),
+            0xC000..=0xFDFF => {
+                let (bank, offset) = self.work_ram_offset(position);
+                self.work_ram[bank].write_byte(offset, value)
+            }
+            0xFE00..=0xFE9F => self.oam_ram.get_mut().write_byte(position - 0xFE00, value),
             Address::IO_REGISTERS_START..=Address::IO_REGISTERS_END => {
                 self.io_registers.write().write_byte(position, value)
             }
+            Address::VBK_VRAM_BANK if self.cgb_mode => self.video_ram_bank = value as usize & 1,
+            // Bank 0 cannot be selected twice, it gives bank 1 instead
+            Address::SVBK_WRAM_BANK if self.cgb_mode => {
+                self.work_ram_bank = (value as usize & 7).max(1)
+            }
+            Address::HDMA5_LENGTH_MODE_START => {
+                self.io_registers.write().write_byte(position, value);
+                self.start_hdma();
+            }
+            Address::KEY1_SPEED_SWITCH
+            | Address::HDMA1_SOURCE_HIGH..=Address::HDMA4_DESTINATION_LOW
+            | Address::BCPS_BG_PALETTE_INDEX..=Address::OCPD_OBJ_PALETTE_DATA => {
+                self.io_registers.write().write_byte(position, value)
+            }
             0xFF80..=0xFFFE => self.internal_ram.write_byte(position - 0xFF80, value),
             Address::IE_INTERRUPT_ENABLE => self.io_registers.write().write_byte(position, value),
             _ => {
@@ -107,25 +210,309 @@ impl Memory {
         };
     }
 
-    pub fn write_word(&mut self, position: Word, value: Word) {
-        let bytes = word_to_two_bytes(value);
+    /**
+     * Copies the byte of this M-cycle of the running OAM DMA transfer. Returns whether there are
+     * more to copy.
+     */
+    pub fn transfer_dma_byte(&mut self) -> bool {
+        let (position, source, active) = {
+            let mut io_registers = self.io_registers.write();
+
+            let Some(position) = io_registers.dma.next_byte() else {
+                self.dma_bus = None;
+                return false;
+            };
+
+            (
+                position,
+                io_registers.dma.source() + position,
+                io_registers.dma.is_active(),
+            )
+        };
+
+        let value = self.dma_read(source);
+        self.oam_ram.get_mut().write_byte(position, value);
+        self.dma_bus = active.then_some((source, value));
+
+        active
+    }
+
+    /// OAM DMA reaches work RAM from $E000 up, as echo RAM does, up to $FFFF.
+    fn dma_read(&self, position: Word) -> Byte {
+        match position {
+            0xE000..=0xFFFF => self.read_work_ram(position),
+            _ => self.peek_byte(position),
+        }
+    }
+
+    /// Bank and offset into it of a work RAM address, or one of its echoes from $E000.
+    fn work_ram_offset(&self, position: Word) -> (usize, Word) {
+        let bank = if position & 0x1000 == 0 {
+            0
+        } else {
+            self.work_ram_bank
+        };
+
+        (bank, position & 0x0FFF)
+    }
+
+    fn read_work_ram(&self, position: Word) -> Byte {
+        let (bank, offset) = self.work_ram_offset(position);
 
-        self.write_byte(position, bytes.1);
-        self.write_byte(position + 1, bytes.0);
+        self.work_ram[bank].read_byte(offset)
     }
 
-    pub fn step(&mut self, last_instruction_cycles: u8) {
-        let dma_init_address = {
-            let mut io_registers = self.io_registers.write();
-            io_registers.step(last_instruction_cycles)
+    /// Copies a block of VRAM DMA to the VRAM bank selected. Returns whether there was one.
+    fn transfer_hdma_block(&mut self) -> bool {
+        let Some((source, destination)) = self.io_registers.write().hdma.next_block() else {
+            return false;
+        };
+
+        for offset in 0..Hdma::BLOCK_BYTES {
+            let value = self.hdma_read(source.wrapping_add(offset));
+            self.video_ram[self.video_ram_bank].write_byte(destination - 0x8000 + offset, value);
+        }
+
+        self.hdma_stall += Hdma::BLOCK_CYCLES;
+
+        true
+    }
+
+    /**
+     * VRAM DMA can't read from VRAM, which it writes to, nor from $FE00 up. From $E000 it reads
+     * work RAM, as echo RAM does.
+     */
+    fn hdma_read(&self, position: Word) -> Byte {
+        match position {
+            0x8000..=0x9FFF | 0xFE00..=0xFFFF => 0xFF,
+            0xE000..=0xFDFF => self.read_work_ram(position),
+            _ => self.peek_byte(position),
+        }
+    }
+
+    /**
+     * HDMA5 was written. A general purpose transfer is done at once, the CPU being halted until it
+     * is over. An H-Blank transfer started while the PPU is in H-Blank, or with the LCD off,
+     * copies its first block right away instead of waiting for the next H-Blank.
+     */
+    fn start_hdma(&mut self) {
+        let (general, hblank_now) = {
+            let io_registers = self.io_registers.read();
+
+            (
+                io_registers.hdma.is_general_pending(),
+                io_registers.hdma.is_hblank_active() && io_registers.is_in_hblank(),
+            )
+        };
+
+        if general {
+            while self.transfer_hdma_block() {}
+        } else if hblank_now {
+            self.transfer_hdma_block();
+        }
+    }
+
+    /// An H-Blank has just started, which an H-Blank transfer copies its next block in.
+    pub fn transfer_hblank_hdma(&mut self) {
+        if self.io_registers.read().hdma.is_hblank_active() {
+            self.transfer_hdma_block();
+        }
+    }
+
+    /// Cycles the CPU has to be halted for, for the VRAM DMA transfers done since the last call.
+    pub fn take_hdma_stall(&mut self) -> u64 {
+        std::mem::take(&mut self.hdma_stall)
+    }
+
+    /**
+     * While OAM DMA runs, the bus it reads from, either the VRAM one or the external one, is
+     * taken: the program sees the byte being copied there, and its writes are lost. HRAM and
+     * the registers stay reachable.
+     */
+    fn dma_conflict(&self, position: Word) -> Option<Byte> {
+        let is_vram = |position: Word| (0x8000..=0x9FFF).contains(&position);
+        let (source, value) = self.dma_bus?;
+
+        (position < 0xFE00 && is_vram(position) == is_vram(source)).then_some(value)
+    }
+
+    /// What the program gets when it reads the position, through whoever holds the bus.
+    fn bus_read(&self, position: Word) -> Byte {
+        if let Some(value) = self.dma_conflict(position) {
+            value
+        } else if self.is_accessible(position) {
+            self.peek_byte(position)
+        } else {
+            0xFF
+        }
+    }
+
+    /**
+     * Accesses of the program to VRAM and OAM while the PPU or OAM DMA uses them read $FF and
+     * are ignored when writing.
+     */
+    fn is_accessible(&self, position: Word) -> bool {
+        if self.ignore_access_restrictions {
+            return true;
+        }
+
+        match position {
+            0x8000..=0x9FFF => self.io_registers.read().is_vram_accessible(),
+            0xFE00..=0xFE9F => self.io_registers.read().is_oam_accessible(),
+            _ => true,
+        }
+    }
+
+    pub fn set_ignore_access_restrictions(&mut self, ignore_access_restrictions: bool) {
+        self.ignore_access_restrictions = ignore_access_restrictions;
+    }
+
+    /**
+     * An access to $FE00-$FEFF while the PPU scans OAM, on the models affected by the OAM bug,
+     * corrupts the row being scanned.
+     */
+    fn corrupt_oam(&self, position: Word, corruption: OamCorruption) {
+        if !(0xFE00..=0xFEFF).contains(&position) {
+            return;
+        }
+
+        let Some(row) = self.io_registers.read().oam_scan_row() else {
+            return;
+        };
+
+        self.oam_ram.lock().corrupt(row, corruption);
+    }
+
+    /**
+     * Leaves VRAM as the DMG boot ROM does: the header logo scaled up to 24 tiles from $8010,
+     * the ® tile right after them and both rows of the tile map pointing to them.
+     */
+    fn load_boot_logo(&mut self) {
+        const REGISTERED_TILE: [Byte; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
+        let double_bits = |nibble: Byte| {
+            (0..4).fold(0, |doubled: Byte, bit| {
+                doubled | (((nibble >> bit) & 1) * 0b11) << (bit * 2)
+            })
         };
 
-        if let Some(dma_init_address) = dma_init_address {
-            for i in 0..OAM_MEMORY_SECTOR_SIZE {
-                self.oam_ram
-                    .write_byte(i, self.read_byte(dma_init_address + i));
+        let logo = self.cartridge.header.logo;
+
+        // Each nibble becomes two rows of the low bitplane, the high one is left at 0
+        for (index, byte) in logo.iter().enumerate() {
+            let position = 0x0010 + index as Word * 8;
+
+            for (row, nibble) in [byte >> 4, byte >> 4, byte & 0xF, byte & 0xF]
+                .iter()
+                .enumerate()
+            {
+                self.video_ram[0].write_byte(position + row as Word * 2, double_bits(*nibble));
             }
         }
+
+        for (row, value) in REGISTERED_TILE.iter().enumerate() {
+            self.video_ram[0].write_byte(0x0190 + row as Word * 2, *value);
+        }
+
+        for tile in 0..12 {
+            self.video_ram[0].write_byte(0x1904 + tile, tile as Byte + 1);
+            self.video_ram[0].write_byte(0x1924 + tile, tile as Byte + 13);
+        }
+
+        self.video_ram[0].write_byte(0x1910, 0x19);
+    }
+
+    /**
+     * Rebuilds every memory area as on power-on, keeping the inserted cartridge.
+     */
+    pub fn power_cycle(&mut self, bootstrap_rom: Option<BootstrapRom>, model: Model) {
+        let cartridge = std::mem::take(&mut self.cartridge).power_cycle();
+        let code_data_log = self.code_data_log.take();
+        let watched_ranges = std::mem::take(&mut self.watched_ranges);
+        let ignore_access_restrictions = self.ignore_access_restrictions;
+
+        *self = Self::new(self.io_registers.clone(), cartridge, bootstrap_rom, model);
+        self.code_data_log = code_data_log;
+        self.watched_ranges = watched_ranges;
+        self.ignore_access_restrictions = ignore_access_restrictions;
+    }
+
+    /**
+     * Accesses to these ranges get kept until `take_accesses`, for watchpoints to check them.
+     */
+    pub fn set_watched_ranges(&mut self, ranges: Vec<(AccessKind, Word, Word)>) {
+        self.watched_ranges = ranges;
+        self.accesses.get_mut().clear();
+    }
+
+    pub fn take_accesses(&self) -> Vec<MemoryAccess> {
+        std::mem::take(&mut *self.accesses.lock())
+    }
+
+    fn record_access(&self, kind: AccessKind, address: Word, value: Byte) {
+        let watched = self
+            .watched_ranges
+            .iter()
+            .any(|(watched_kind, start, end)| {
+                *watched_kind == kind && (*start..=*end).contains(&address)
+            });
+
+        if watched {
+            self.accesses.lock().push(MemoryAccess {
+                kind,
+                address,
+                value,
+            });
+        }
+    }
+
+    /**
+     * Starts logging which ROM bytes get executed or read as data, on top of a previous log.
+     */
+    pub fn enable_code_data_log(&mut self, code_data_log: CodeDataLog) {
+        self.code_data_log = Some(Mutex::new(code_data_log));
+    }
+
+    pub fn save_code_data_log(&self, path: &Path) -> std::io::Result<()> {
+        match &self.code_data_log {
+            Some(code_data_log) => code_data_log.lock().save(path),
+            None => Ok(()),
+        }
+    }
+
+    pub fn log_instruction(&self, position: Word, length: Word) {
+        if let Some(code_data_log) = &self.code_data_log
+            && let Some(offset) = self.rom_offset(position)
+        {
+            code_data_log
+                .lock()
+                .mark_instruction(offset, length as usize);
+        }
+    }
+
+    pub fn rom_size(&self) -> usize {
+        self.cartridge.data.as_slice().len()
+    }
+
+    /// ROM data offset of an address, unless the bootstrap ROM or RAM is mapped there.
+    fn rom_offset(&self, position: Word) -> Option<usize> {
+        if position < Address::CARTRIDGE_START && self.bootstrap_rom.is_some() {
+            return None;
+        }
+
+        self.cartridge.rom_offset(position)
+    }
+
+    pub fn flush_save_ram(&self) {
+        self.cartridge.flush_save_ram();
+    }
+
+    pub fn cartridge_header(&self) -> &CartridgeHeader {
+        &self.cartridge.header
+    }
+
+    pub fn current_rom_bank(&self) -> u16 {
+        self.cartridge.selected_rom_bank()
     }
 
     pub fn has_bootstrap_rom(&self) -> bool {
@@ -137,9 +524,399 @@ impl Memory {
     }
 }
 
+impl ReadMemory for Memory {
+    fn read_byte(&self, position: Word) -> Byte {
+        Memory::read_byte(self, position)
+    }
+}
+
+impl WriteMemory for Memory {
+    fn write_byte(&mut self, position: Word, value: Byte) {
+        Memory::write_byte(self, position, value)
+    }
+}
+
+impl Bus for Memory {
+    fn fetch_byte(&self, position: Word) -> Byte {
+        self.bus_read(position)
+    }
+
+    fn peek_byte(&self, position: Word) -> Byte {
+        Memory::peek_byte(self, position)
+    }
+
+    fn log_instruction(&self, position: Word, length: Word) {
+        Memory::log_instruction(self, position, length)
+    }
+
+    fn set_watched_ranges(&mut self, ranges: Vec<(AccessKind, Word, Word)>) {
+        Memory::set_watched_ranges(self, ranges)
+    }
+
+    fn take_accesses(&self) -> Vec<MemoryAccess> {
+        Memory::take_accesses(self)
+    }
+
+    fn has_bootstrap_rom(&self) -> bool {
+        Memory::has_bootstrap_rom(self)
+    }
+
+    fn erase_bootstrap_rom(&mut self) {
+        Memory::erase_bootstrap_rom(self)
+    }
+
+    fn current_rom_bank(&self) -> u16 {
+        Memory::current_rom_bank(self)
+    }
+
+    fn inc_dec(&mut self, value: Word, reading: bool) {
+        let corruption = if reading {
+            OamCorruption::ReadDuringIncDec
+        } else {
+            OamCorruption::Write
+        };
+
+        self.corrupt_oam(value, corruption);
+    }
+
+    fn switch_speed(&mut self) -> bool {
+        self.io_registers.write().switch_speed()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cartridge::cartridge_memory_sector::CartridgeMemorySector;
+    use crate::io::stat::STATMode;
+    use crate::memory::oam_memory_sector::OAM_MEMORY_SECTOR_SIZE;
+    use crate::scheduler::Event;
+    use test_case::test_case;
+
+    fn create_memory_with_logo(model: Model) -> Memory {
+        let mut data = vec![0; 0x8000];
+        data[0x104] = 0xCE;
+        data[0x105] = 0xED;
+
+        let header = CartridgeHeader::new_from_data(&data);
+        let cartridge = Cartridge::new(CartridgeMemorySector::new_from_data(data), header);
+
+        Memory::new(Arc::default(), cartridge, None, model)
+    }
+
+    #[test]
+    fn test_dmg_boot_logo_is_left_in_vram() {
+        let memory = create_memory_with_logo(Model::Dmg);
+
+        // $CE -> rows $F0,$F0,$FC,$FC and $ED -> rows $FC,$FC,$F3,$F3
+        let expected_rows = [0xF0, 0xF0, 0xFC, 0xFC, 0xFC, 0xFC, 0xF3, 0xF3];
+
+        for (row, expected) in expected_rows.iter().enumerate() {
+            let position = 0x8010 + row as Word * 2;
+
+            assert_eq!(memory.read_byte(position), *expected);
+            assert_eq!(memory.read_byte(position + 1), 0x00);
+        }
+
+        assert_eq!(memory.read_byte(0x8190), 0x3C);
+        assert_eq!(memory.read_byte(0x9904), 0x01);
+        assert_eq!(memory.read_byte(0x990F), 0x0C);
+        assert_eq!(memory.read_byte(0x9910), 0x19);
+        assert_eq!(memory.read_byte(0x9924), 0x0D);
+        assert_eq!(memory.read_byte(0x992F), 0x18);
+    }
+
+    #[test]
+    fn test_power_cycle_keeps_cartridge_only() {
+        let mut memory = create_memory_with_logo(Model::Dmg);
+        memory.write_byte(0xC000, 0x42);
+        memory.write_byte(0xFF80, 0x42);
+        memory.write_byte(0x8000, 0x42);
+
+        memory.power_cycle(None, Model::Dmg);
+
+        assert_eq!(memory.read_byte(0xC000), 0x00);
+        assert_eq!(memory.read_byte(0xFF80), 0x00);
+        assert_eq!(memory.read_byte(0x8000), 0x00);
+        assert_eq!(memory.read_byte(0x0104), 0xCE);
+        assert_eq!(memory.read_byte(0x8010), 0xF0);
+    }
+
+    #[test_case(Model::Sgb)]
+    #[test_case(Model::Cgb)]
+    fn test_other_boot_roms_do_not_leave_dmg_logo(model: Model) {
+        let memory = create_memory_with_logo(model);
+
+        assert_eq!(memory.read_byte(0x8010), 0x00);
+        assert_eq!(memory.read_byte(0x9904), 0x00);
+    }
+
+    fn create_memory_scanning_oam(model: Model, dots: u8) -> Memory {
+        let mut io_registers = IORegisters::power_on(model, true);
+        io_registers.set_stat_mode(STATMode::SearchOamRam);
+        io_registers
+            .scheduler
+            .schedule(Event::PpuMode, STATMode::SearchOamRam.cycles());
+        io_registers.scheduler.advance(dots.into());
+
+        let mut memory = Memory::new(
+            Arc::new(RwLock::new(io_registers)),
+            Cartridge::default(),
+            None,
+            model,
+        );
+
+        for position in 0..OAM_MEMORY_SECTOR_SIZE {
+            memory
+                .oam_ram
+                .get_mut()
+                .write_byte(position, position as Byte);
+        }
+
+        memory
+    }
+
+    #[test_case(Model::Dmg, 0x2120 ; "dmg")]
+    #[test_case(Model::Cgb, 0x2928 ; "cgb")]
+    fn test_oam_access_during_oam_scan_corrupts_the_scanned_row(model: Model, first_word: Word) {
+        // Dot 20 of the scan, on row 5
+        let memory = create_memory_scanning_oam(model, 20);
+
+        memory.read_byte(0xFEC0);
+
+        assert_eq!(memory.peek_word(0xFE28), first_word);
+    }
+
+    #[test]
+    fn test_inc_dec_out_of_oam_does_not_corrupt() {
+        let mut memory = create_memory_scanning_oam(Model::Dmg, 20);
+
+        Bus::inc_dec(&mut memory, 0xFDFF, false);
+        Bus::inc_dec(&mut memory, 0xFF00, true);
+
+        assert_eq!(memory.peek_word(0xFE28), 0x2928);
+    }
+
+    #[test_case(STATMode::LCDTransfer, 0x8010, false ; "vram while drawing")]
+    #[test_case(STATMode::SearchOamRam, 0x8010, true ; "vram while scanning oam")]
+    #[test_case(STATMode::SearchOamRam, 0xFE10, false ; "oam while scanning oam")]
+    #[test_case(STATMode::LCDTransfer, 0xFE10, false ; "oam while drawing")]
+    #[test_case(STATMode::HBlank, 0xFE10, true ; "oam during hblank")]
+    fn test_ppu_blocks_vram_and_oam(mode: STATMode, position: Word, accessible: bool) {
+        let mut io_registers = IORegisters::power_on(Model::Cgb, true);
+        io_registers.set_stat_mode(mode);
+        let mut memory = Memory::new(
+            Arc::new(RwLock::new(io_registers)),
+            Cartridge::default(),
+            None,
+            Model::Cgb,
+        );
+        memory.poke_byte(position, 0x42);
+
+        memory.write_byte(position, 0x24);
+
+        let expected = if accessible { 0x24 } else { 0xFF };
+        assert_eq!(memory.read_byte(position), expected);
+        assert_eq!(Bus::fetch_byte(&memory, position), expected);
+
+        memory.set_ignore_access_restrictions(true);
+        assert_eq!(
+            memory.read_byte(position),
+            if accessible { 0x24 } else { 0x42 }
+        );
+    }
+
+    fn create_memory_with_lcd_off() -> Memory {
+        let mut memory = Memory::default();
+        memory.write_byte(Address::LCDC, 0);
+
+        memory
+    }
+
+    #[test]
+    fn test_oam_dma_copies_a_byte_per_transfer_step() {
+        let mut memory = create_memory_with_lcd_off();
+        for position in 0..OAM_MEMORY_SECTOR_SIZE {
+            memory.write_byte(0xC100 + position, position as Byte + 1);
+        }
+
+        memory.write_byte(Address::DMA, 0xC1);
+        assert_eq!(memory.read_byte(0xFE00), 0x00);
+
+        assert!(memory.transfer_dma_byte());
+        assert_eq!(memory.oam_ram.lock().read_byte(0), 0x01);
+        assert_eq!(memory.oam_ram.lock().read_byte(1), 0x00);
+        assert_eq!(memory.read_byte(0xFE00), 0xFF);
+
+        let mut steps = 1;
+        while memory.transfer_dma_byte() {
+            steps += 1;
+        }
+
+        assert_eq!(steps, OAM_MEMORY_SECTOR_SIZE - 1);
+        assert_eq!(memory.read_byte(0xFE00), 0x01);
+        assert_eq!(memory.read_byte(0xFE9F), 0xA0);
+    }
+
+    #[test]
+    fn test_oam_dma_takes_over_the_bus_it_reads_from() {
+        let mut memory = create_memory_with_lcd_off();
+        memory.write_byte(0xC000, 0x42);
+        memory.write_byte(0xC001, 0x43);
+        memory.write_byte(0x8000, 0x24);
+        memory.write_byte(0xFF80, 0x12);
+
+        memory.write_byte(Address::DMA, 0xC0);
+        memory.transfer_dma_byte();
+        memory.write_byte(0xC001, 0x00);
+
+        assert_eq!(memory.read_byte(0xD123), 0x42);
+        assert_eq!(memory.fetch_byte(0x0100), 0x42);
+        assert_eq!(memory.read_byte(0x8000), 0x24);
+        assert_eq!(memory.read_byte(0xFF80), 0x12);
+
+        memory.transfer_dma_byte();
+        assert_eq!(memory.read_byte(0xC234), 0x43);
+    }
+
+    #[test_case(0xE0, 0xC000 ; "echo ram")]
+    #[test_case(0xFE, 0xDE00 ; "oam")]
+    #[test_case(0xFF, 0xDF00 ; "registers")]
+    fn test_oam_dma_from_e000_up_reads_work_ram(source: Byte, work_ram: Word) {
+        let mut memory = create_memory_with_lcd_off();
+        memory.write_byte(work_ram, 0x42);
+
+        memory.write_byte(Address::DMA, source);
+        memory.transfer_dma_byte();
+
+        assert_eq!(memory.oam_ram.lock().read_byte(0), 0x42);
+    }
+
+    #[test_case(Model::Cgb, 0x42, 0x24 ; "cgb")]
+    #[test_case(Model::CgbDmgMode, 0x24, 0x00 ; "dmg mode")]
+    fn test_vbk_switches_vram_banks_in_cgb_mode(model: Model, bank_0: Byte, bank_1: Byte) {
+        let mut memory = Memory::new(Arc::default(), Cartridge::default(), None, model);
+        memory.write_byte(0x8000, 0x42);
+
+        memory.write_byte(Address::VBK_VRAM_BANK, 0x01);
+        memory.write_byte(0x8000, 0x24);
+
+        assert_eq!(memory.read_byte(Address::VBK_VRAM_BANK), 0xFF);
+        assert_eq!(memory.peek_vram(0, 0x8000), bank_0);
+        assert_eq!(memory.peek_vram(1, 0x8000), bank_1);
+    }
+
+    #[test_case(Model::Cgb, 0xF9, 0x42 ; "cgb")]
+    #[test_case(Model::CgbDmgMode, 0xFF, 0x24 ; "dmg mode")]
+    fn test_svbk_switches_work_ram_banks_in_cgb_mode(model: Model, svbk: Byte, bank_1: Byte) {
+        let mut memory = Memory::new(Arc::default(), Cartridge::default(), None, model);
+        memory.write_byte(0xC000, 0x11);
+        memory.write_byte(0xD000, 0x42);
+
+        memory.write_byte(Address::SVBK_WRAM_BANK, 0x02);
+        memory.write_byte(0xD000, 0x24);
+        assert_eq!(memory.read_byte(0xF000), 0x24);
+
+        memory.write_byte(Address::SVBK_WRAM_BANK, 0x00);
+        assert_eq!(memory.read_byte(Address::SVBK_WRAM_BANK), svbk);
+        assert_eq!(memory.read_byte(0xD000), bank_1);
+        assert_eq!(memory.read_byte(0xC000), 0x11);
+    }
+
+    fn create_cgb_memory() -> Memory {
+        let mut memory = Memory::new(
+            Arc::new(RwLock::new(IORegisters::power_on(Model::Cgb, true))),
+            Cartridge::default(),
+            None,
+            Model::Cgb,
+        );
+
+        for position in 0..0x40 {
+            memory.write_byte(0xC100 + position, position as Byte + 1);
+        }
+        memory.write_byte(Address::HDMA1_SOURCE_HIGH, 0xC1);
+        memory.write_byte(Address::HDMA2_SOURCE_LOW, 0x00);
+        memory.write_byte(Address::HDMA3_DESTINATION_HIGH, 0x01);
+        memory.write_byte(Address::HDMA4_DESTINATION_LOW, 0x20);
+        memory.write_byte(Address::VBK_VRAM_BANK, 1);
+
+        memory
+    }
+
+    #[test]
+    fn test_general_purpose_hdma_copies_everything_at_once() {
+        let mut memory = create_cgb_memory();
+
+        memory.write_byte(Address::HDMA5_LENGTH_MODE_START, 0x01);
+
+        assert_eq!(memory.peek_vram(1, 0x8120), 0x01);
+        assert_eq!(memory.peek_vram(1, 0x813F), 0x20);
+        assert_eq!(memory.peek_vram(1, 0x8140), 0x00);
+        assert_eq!(memory.peek_vram(0, 0x8120), 0x00);
+        assert_eq!(memory.read_byte(Address::HDMA5_LENGTH_MODE_START), 0xFF);
+        assert_eq!(memory.take_hdma_stall(), 2 * Hdma::BLOCK_CYCLES);
+    }
+
+    #[test]
+    fn test_hblank_hdma_copies_a_block_per_hblank() {
+        let mut memory = create_cgb_memory();
+        memory
+            .io_registers
+            .write()
+            .set_stat_mode(STATMode::SearchOamRam);
+
+        memory.write_byte(Address::HDMA5_LENGTH_MODE_START, 0x81);
+        assert_eq!(memory.peek_vram(1, 0x8120), 0x00);
+        assert_eq!(memory.take_hdma_stall(), 0);
+
+        memory.transfer_hblank_hdma();
+        assert_eq!(memory.peek_vram(1, 0x812F), 0x10);
+        assert_eq!(memory.peek_vram(1, 0x8130), 0x00);
+        assert_eq!(memory.read_byte(Address::HDMA5_LENGTH_MODE_START), 0x00);
+
+        memory.transfer_hblank_hdma();
+        memory.transfer_hblank_hdma();
+        assert_eq!(memory.peek_vram(1, 0x813F), 0x20);
+        assert_eq!(memory.peek_vram(1, 0x8140), 0x00);
+        assert_eq!(memory.read_byte(Address::HDMA5_LENGTH_MODE_START), 0xFF);
+        assert_eq!(memory.take_hdma_stall(), 2 * Hdma::BLOCK_CYCLES);
+    }
+
+    #[test]
+    fn test_hblank_hdma_started_with_the_lcd_off_copies_a_block_at_once() {
+        let mut memory = create_cgb_memory();
+        memory.write_byte(Address::LCDC, 0x00);
+
+        memory.write_byte(Address::HDMA5_LENGTH_MODE_START, 0x81);
+        assert_eq!(memory.peek_vram(1, 0x812F), 0x10);
+        assert_eq!(memory.peek_vram(1, 0x8130), 0x00);
+        assert_eq!(memory.read_byte(Address::HDMA5_LENGTH_MODE_START), 0x00);
+        assert_eq!(memory.take_hdma_stall(), Hdma::BLOCK_CYCLES);
+    }
+
+    #[test_case(0x80, 0xFF ; "vram")]
+    #[test_case(0xE1, 0x01 ; "echo ram")]
+    #[test_case(0xFE, 0xFF ; "oam")]
+    fn test_hdma_from_invalid_sources(source: Byte, first: Byte) {
+        let mut memory = create_cgb_memory();
+        memory.write_byte(Address::HDMA1_SOURCE_HIGH, source);
+
+        memory.write_byte(Address::HDMA5_LENGTH_MODE_START, 0x00);
+
+        assert_eq!(memory.peek_vram(1, 0x8120), first);
+    }
+
+    #[test]
+    fn test_hdma_destination_wraps_from_9ff0_to_8000() {
+        let mut memory = create_cgb_memory();
+        memory.write_byte(Address::HDMA3_DESTINATION_HIGH, 0x1F);
+        memory.write_byte(Address::HDMA4_DESTINATION_LOW, 0xF0);
+
+        memory.write_byte(Address::HDMA5_LENGTH_MODE_START, 0x01);
+
+        assert_eq!(memory.peek_vram(1, 0x9FF0), 0x01);
+        assert_eq!(memory.peek_vram(1, 0x8000), 0x11);
+    }
 
     #[test]
     fn test_unmapped_addresses() {
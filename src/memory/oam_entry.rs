@@ -33,4 +33,14 @@ impl OamEntry {
     pub fn flip_x(&self) -> bool {
         self.flags & 0b100000 == 0b100000
     }
+
+    /// CGB mode only, the tiles can come from either VRAM bank.
+    pub fn vram_bank(&self) -> usize {
+        (self.flags as usize >> 3) & 1
+    }
+
+    /// CGB mode only, one of the 8 OBJ colour palettes.
+    pub fn cgb_palette(&self) -> Byte {
+        self.flags & 0b111
+    }
 }
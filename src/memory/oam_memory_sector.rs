@@ -1,9 +1,19 @@
 use crate::memory::memory_sector::{MemorySector, ReadMemory, WriteMemory};
 use crate::memory::oam_entry::OamEntry;
+use crate::utils::math::{two_bytes_to_word, word_to_two_bytes};
 use crate::{Byte, Word};
 
 pub const OAM_MEMORY_SECTOR_SIZE: u16 = 0xA0;
 
+/// What the CPU did on the bus while the PPU was reading an OAM row, see `OamMemorySector::corrupt`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OamCorruption {
+    Read,
+    // A write, or a 16-bit increment or decrement of a register pointing to OAM
+    Write,
+    ReadDuringIncDec,
+}
+
 #[derive(Clone)]
 pub struct OamMemorySector {
     data: MemorySector,
@@ -11,6 +21,69 @@ pub struct OamMemorySector {
 }
 
 impl OamMemorySector {
+    /// The OAM scan reads a row of two entries, four words, at a time.
+    pub const ROW_SIZE: Word = 8;
+    const ROWS: usize = (OAM_MEMORY_SECTOR_SIZE / Self::ROW_SIZE) as usize;
+
+    /**
+     * OAM bug of the DMG-family models: the row the PPU is reading gets mangled with the one
+     * before it. Its first word is mixed with words of the previous row, which the other three are
+     * copied from. An increment or decrement on top of a read first mixes the previous row with
+     * the two around it, then copies it over both, unless that reaches outside OAM. The first row
+     * is never corrupted.
+     */
+    pub fn corrupt(&mut self, row: usize, corruption: OamCorruption) {
+        if row == 0 || row >= Self::ROWS {
+            return;
+        }
+
+        if corruption == OamCorruption::ReadDuringIncDec && (4..Self::ROWS - 1).contains(&row) {
+            let a = self.word(row - 2, 0);
+            let b = self.word(row - 1, 0);
+            let c = self.word(row, 0);
+            let d = self.word(row - 1, 2);
+
+            self.set_word(row - 1, 0, (b & (a | c | d)) | (a & c & d));
+            self.copy_row(row - 1, row);
+            self.copy_row(row - 1, row - 2);
+        }
+
+        let a = self.word(row, 0);
+        let b = self.word(row - 1, 0);
+        let c = self.word(row - 1, 2);
+
+        let first_word = match corruption {
+            OamCorruption::Write => ((a ^ c) & (b ^ c)) ^ c,
+            OamCorruption::Read | OamCorruption::ReadDuringIncDec => b | (a & c),
+        };
+
+        self.copy_row(row - 1, row);
+        self.set_word(row, 0, first_word);
+    }
+
+    fn word(&self, row: usize, index: usize) -> Word {
+        let position = (row * Self::ROW_SIZE as usize + index * 2) as Word;
+
+        two_bytes_to_word(
+            self.data.read_byte(position + 1),
+            self.data.read_byte(position),
+        )
+    }
+
+    fn set_word(&mut self, row: usize, index: usize, value: Word) {
+        let position = (row * Self::ROW_SIZE as usize + index * 2) as Word;
+        let (high, low) = word_to_two_bytes(value);
+
+        self.data.write_byte(position, low);
+        self.data.write_byte(position + 1, high);
+    }
+
+    fn copy_row(&mut self, from: usize, to: usize) {
+        for index in 0..4 {
+            self.set_word(to, index, self.word(from, index));
+        }
+    }
+
     fn read_oam_entry(&self, position: Word) -> OamEntry {
         OamEntry::with_bytes(
             self.data.read_byte(position),
@@ -57,3 +130,76 @@ impl Default for OamMemorySector {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn create_oam() -> OamMemorySector {
+        let mut oam = OamMemorySector::default();
+
+        for position in 0..OAM_MEMORY_SECTOR_SIZE {
+            oam.write_byte(position, position as Byte);
+        }
+
+        oam
+    }
+
+    fn row(oam: &OamMemorySector, row: usize) -> [Word; 4] {
+        [0, 1, 2, 3].map(|index| oam.word(row, index))
+    }
+
+    #[test_case(OamCorruption::Write, 0x033F ; "write")]
+    #[test_case(OamCorruption::Read, 0x03FF ; "read")]
+    fn test_corruption_mixes_the_previous_row(corruption: OamCorruption, first_word: Word) {
+        let mut oam = create_oam();
+        oam.set_word(1, 0, 0x3333);
+        oam.set_word(0, 0, 0x00FF);
+        oam.set_word(0, 2, 0x0F0F);
+
+        oam.corrupt(1, corruption);
+
+        assert_eq!(row(&oam, 1), [first_word, 0x0302, 0x0F0F, 0x0706]);
+        assert_eq!(row(&oam, 0), [0x00FF, 0x0302, 0x0F0F, 0x0706]);
+        assert_eq!(row(&oam, 2), [0x1110, 0x1312, 0x1514, 0x1716]);
+    }
+
+    #[test_case(OamCorruption::Write)]
+    #[test_case(OamCorruption::Read)]
+    #[test_case(OamCorruption::ReadDuringIncDec)]
+    fn test_first_row_is_never_corrupted(corruption: OamCorruption) {
+        let mut oam = create_oam();
+
+        oam.corrupt(0, corruption);
+
+        assert_eq!(oam.data.data, create_oam().data.data);
+    }
+
+    #[test]
+    fn test_read_during_inc_dec_spreads_the_previous_row() {
+        let mut oam = create_oam();
+        oam.set_word(3, 0, 0x00FF);
+        oam.set_word(4, 0, 0x0F0F);
+        oam.set_word(5, 0, 0x3333);
+        oam.set_word(4, 2, 0x5555);
+
+        oam.corrupt(5, OamCorruption::ReadDuringIncDec);
+
+        let previous = [0x071F, 0x2322, 0x5555, 0x2726];
+        assert_eq!(row(&oam, 3), previous);
+        assert_eq!(row(&oam, 4), previous);
+        assert_eq!(row(&oam, 5), previous);
+    }
+
+    #[test]
+    fn test_read_during_inc_dec_near_the_edges_is_a_read() {
+        let mut read = create_oam();
+        let mut read_during_inc_dec = create_oam();
+
+        read.corrupt(3, OamCorruption::Read);
+        read_during_inc_dec.corrupt(3, OamCorruption::ReadDuringIncDec);
+
+        assert_eq!(read.data.data, read_during_inc_dec.data.data);
+    }
+}
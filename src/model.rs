@@ -0,0 +1,130 @@
+use crate::Word;
+use crate::cartridge::cartridge_header::CartridgeHeader;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Hardware revision being emulated. Without a bootstrap ROM, it decides the state the boot ROM
+/// of that revision would have left behind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Model {
+    Dmg0,
+    #[default]
+    Dmg,
+    Mgb,
+    Sgb,
+    Sgb2,
+    CgbDmgMode,
+    Cgb,
+}
+
+impl Model {
+    pub const NAMES: [&'static str; 7] = ["dmg0", "dmg", "mgb", "sgb", "sgb2", "cgb-dmg", "cgb"];
+
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::CgbDmgMode | Model::Cgb)
+    }
+
+    /// Colour palettes, the second VRAM bank and the rest of CGB features are available.
+    pub fn is_cgb_mode(&self) -> bool {
+        *self == Model::Cgb
+    }
+
+    /// The CGB boot ROM falls back to DMG compatibility mode unless the header asks for CGB.
+    pub fn for_cartridge(self, header: &CartridgeHeader) -> Self {
+        match self {
+            Model::Cgb if !header.supports_cgb() => Model::CgbDmgMode,
+            model => model,
+        }
+    }
+
+    /// Internal 16 bits counter behind DIV when the boot ROM hands over to the cartridge.
+    /// Only its upper byte is documented for DMG0/DMG/MGB; SGB and CGB boot ROMs take a
+    /// cartridge-dependent time, so these are the values measured with a typical header.
+    pub fn post_boot_div_counter(&self) -> Word {
+        match self {
+            Model::Dmg0 => 0x1830,
+            Model::Dmg | Model::Mgb => 0xABCC,
+            Model::Sgb | Model::Sgb2 => 0xD85C,
+            Model::CgbDmgMode => 0x267C,
+            Model::Cgb => 0x1EA0,
+        }
+    }
+
+    /// Only the monochrome boot ROMs leave the scaled-up logo tiles and tile map in VRAM.
+    /// SGB boot ROMs hand the logo to the SNES and CGB ones use a different layout.
+    pub fn leaves_logo_in_vram(&self) -> bool {
+        matches!(self, Model::Dmg0 | Model::Dmg | Model::Mgb)
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "dmg0" => Ok(Model::Dmg0),
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "sgb" => Ok(Model::Sgb),
+            "sgb2" => Ok(Model::Sgb2),
+            "cgb-dmg" => Ok(Model::CgbDmgMode),
+            "cgb" => Ok(Model::Cgb),
+            _ => Err(format!(
+                "Unknown model {value}, expected one of: {}",
+                Model::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl Display for Model {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let index = match self {
+            Model::Dmg0 => 0,
+            Model::Dmg => 1,
+            Model::Mgb => 2,
+            Model::Sgb => 3,
+            Model::Sgb2 => 4,
+            Model::CgbDmgMode => 5,
+            Model::Cgb => 6,
+        };
+
+        write!(f, "{}", Model::NAMES[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(Model::Dmg0)]
+    #[test_case(Model::Dmg)]
+    #[test_case(Model::Mgb)]
+    #[test_case(Model::Sgb)]
+    #[test_case(Model::Sgb2)]
+    #[test_case(Model::CgbDmgMode)]
+    #[test_case(Model::Cgb)]
+    fn test_name_round_trip(model: Model) {
+        assert_eq!(Model::from_str(&model.to_string()), Ok(model));
+    }
+
+    #[test_case(Model::Cgb, 0x80, Model::Cgb ; "cgb compatible")]
+    #[test_case(Model::Cgb, 0xC0, Model::Cgb ; "cgb only")]
+    #[test_case(Model::Cgb, 0x00, Model::CgbDmgMode ; "dmg cartridge on cgb")]
+    #[test_case(Model::Dmg, 0xC0, Model::Dmg ; "cgb cartridge on dmg")]
+    fn test_model_for_cartridge(model: Model, cgb_flag: u8, expected: Model) {
+        let mut data = vec![0; 0x150];
+        data[0x143] = cgb_flag;
+
+        assert_eq!(
+            model.for_cartridge(&CartridgeHeader::new_from_data(&data)),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_rejects_unknown_model() {
+        assert!(Model::from_str("gba").is_err());
+    }
+}
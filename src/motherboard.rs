@@ -0,0 +1,196 @@
+use crate::audio::CYCLES_1_512_SEC;
+use crate::bus::Bus;
+use crate::bus::address::Address;
+use crate::debug::breakpoints::{AccessKind, MemoryAccess};
+use crate::gpu::Gpu;
+use crate::io::dma::Dma;
+use crate::io::registers::IORegisters;
+use crate::io::stat::STATMode;
+use crate::memory::Memory;
+use crate::memory::memory_sector::{ReadMemory, WriteMemory};
+use crate::scheduler::Event;
+use crate::{Byte, Word};
+use image::{ImageBuffer, RgbaImage};
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+/**
+ * What the CPU is wired to: the memory map plus the components running alongside it. Whenever the
+ * CPU catches up with it, the events that have come due are handled, so the rest of the console
+ * sees each access at the cycle it happens in.
+ */
+pub struct Motherboard {
+    memory: Arc<RwLock<Memory>>,
+    io_registers: Arc<RwLock<IORegisters>>,
+    gpu: Gpu,
+    canvas: Arc<RwLock<RgbaImage>>,
+
+    // What the audio unit has to catch up with
+    frame_sequencer_ticks: u8,
+    audio_update_pending: bool,
+}
+
+impl Motherboard {
+    const HIGH_RAM_START: Word = 0xFF80;
+
+    pub fn new(memory: Arc<RwLock<Memory>>, io_registers: Arc<RwLock<IORegisters>>) -> Self {
+        Self {
+            gpu: Gpu::new(memory.clone(), io_registers.clone()),
+            canvas: Arc::new(RwLock::new(ImageBuffer::new(
+                Gpu::PIXEL_WIDTH as u32,
+                Gpu::PIXEL_HEIGHT as u32,
+            ))),
+            memory,
+            io_registers,
+            frame_sequencer_ticks: 0,
+            audio_update_pending: false,
+        }
+    }
+
+    /// Back to power-on state, once memory and I/O registers have been.
+    pub fn reset(&mut self) {
+        self.gpu = Gpu::new(self.memory.clone(), self.io_registers.clone());
+        self.frame_sequencer_ticks = 0;
+        self.audio_update_pending = false;
+    }
+
+    pub fn canvas(&self) -> Arc<RwLock<RgbaImage>> {
+        self.canvas.clone()
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.gpu.frame()
+    }
+
+    /**
+     * Frame sequencer ticks since the last call, as long as the audio unit has something to catch
+     * up with: ticks or written registers.
+     */
+    pub fn take_audio_update(&mut self) -> Option<u8> {
+        if !std::mem::take(&mut self.audio_update_pending) {
+            return None;
+        }
+
+        Some(std::mem::take(&mut self.frame_sequencer_ticks))
+    }
+
+    fn handle_event(&mut self, event: Event, cycle: u64) {
+        match event {
+            Event::PpuMode => {
+                let (now, mode) = {
+                    let io_registers = self.io_registers.read();
+
+                    (io_registers.scheduler.now(), io_registers.stat.mode())
+                };
+                let next = self.gpu.update(cycle, now, &mut self.canvas.write());
+
+                let hblank_started = {
+                    let mut io_registers = self.io_registers.write();
+                    io_registers.scheduler.schedule_at(Event::PpuMode, next);
+
+                    mode != STATMode::HBlank && io_registers.stat.mode() == STATMode::HBlank
+                };
+
+                if hblank_started {
+                    self.memory.write().transfer_hblank_hdma();
+                }
+            }
+            Event::TimerOverflow => self.io_registers.write().overflow_timer(),
+            Event::OamDma => {
+                if self.memory.write().transfer_dma_byte() {
+                    let mut io_registers = self.io_registers.write();
+                    let next = cycle + io_registers.scheduler.to_cycles(Dma::BYTE_CYCLES);
+
+                    io_registers.scheduler.schedule_at(Event::OamDma, next);
+                }
+            }
+            Event::FrameSequencer => {
+                self.frame_sequencer_ticks = self.frame_sequencer_ticks.saturating_add(1);
+                self.audio_update_pending = true;
+
+                self.io_registers
+                    .write()
+                    .scheduler
+                    .schedule_at(Event::FrameSequencer, cycle + CYCLES_1_512_SEC);
+            }
+            Event::SerialBit => self.io_registers.write().shift_serial_bit(),
+            Event::ApuWrite => self.audio_update_pending = true,
+        }
+    }
+}
+
+impl ReadMemory for Motherboard {
+    fn read_byte(&self, position: Word) -> Byte {
+        self.memory.read().read_byte(position)
+    }
+}
+
+impl WriteMemory for Motherboard {
+    fn write_byte(&mut self, position: Word, value: Byte) {
+        // The line drawn so far must not see the new value
+        if (Address::IO_REGISTERS_START..Self::HIGH_RAM_START).contains(&position) {
+            let now = self.io_registers.read().scheduler.now();
+            self.gpu.catch_up(now, &mut self.canvas.write());
+        }
+
+        self.memory.write().write_byte(position, value)
+    }
+}
+
+impl Bus for Motherboard {
+    fn fetch_byte(&self, position: Word) -> Byte {
+        self.memory.read().fetch_byte(position)
+    }
+
+    fn peek_byte(&self, position: Word) -> Byte {
+        self.memory.read().peek_byte(position)
+    }
+
+    fn tick(&mut self, cycles: u64) -> u64 {
+        let mut due_event = {
+            let mut io_registers = self.io_registers.write();
+            io_registers.scheduler.advance(cycles);
+
+            io_registers.scheduler.pop_due()
+        };
+
+        while let Some((event, cycle)) = due_event {
+            self.handle_event(event, cycle);
+            due_event = self.io_registers.write().scheduler.pop_due();
+        }
+
+        self.io_registers.read().scheduler.cpu_cycles_to_next()
+    }
+
+    fn log_instruction(&self, position: Word, length: Word) {
+        self.memory.read().log_instruction(position, length)
+    }
+
+    fn set_watched_ranges(&mut self, ranges: Vec<(AccessKind, Word, Word)>) {
+        self.memory.write().set_watched_ranges(ranges)
+    }
+
+    fn take_accesses(&self) -> Vec<MemoryAccess> {
+        self.memory.read().take_accesses()
+    }
+
+    fn has_bootstrap_rom(&self) -> bool {
+        self.memory.read().has_bootstrap_rom()
+    }
+
+    fn erase_bootstrap_rom(&mut self) {
+        self.memory.write().erase_bootstrap_rom()
+    }
+
+    fn current_rom_bank(&self) -> u16 {
+        self.memory.read().current_rom_bank()
+    }
+
+    fn inc_dec(&mut self, value: Word, reading: bool) {
+        self.memory.write().inc_dec(value, reading)
+    }
+
+    fn switch_speed(&mut self) -> bool {
+        self.memory.write().switch_speed()
+    }
+}
@@ -0,0 +1,258 @@
+/// Something a component has to do at a known cycle, without being stepped until then.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    PpuMode,
+    TimerOverflow,
+    OamDma,
+    FrameSequencer,
+    SerialBit,
+    // An audio register was written, so the audio unit has to catch up right away
+    ApuWrite,
+}
+
+impl Event {
+    const ALL: [Event; 6] = [
+        Event::PpuMode,
+        Event::TimerOverflow,
+        Event::OamDma,
+        Event::FrameSequencer,
+        Event::SerialBit,
+        Event::ApuWrite,
+    ];
+
+    /// Events of the components running on the CPU clock, twice as fast in double speed mode.
+    const CPU_CLOCKED: [Event; 3] = [Event::TimerOverflow, Event::OamDma, Event::SerialBit];
+}
+
+/**
+ * Cycle counter plus the next cycle each event is due at. The CPU runs ahead until the earliest of
+ * them is reached, so the rest of components only do work when something changes.
+ * Events due at the same cycle come out in declaration order.
+ *
+ * Cycles are those of the normal speed clock, which the PPU and the audio unit always run on. The
+ * CPU clock, which the timer, the serial port and OAM DMA run on, is counted apart as it goes
+ * twice as fast in CGB double speed mode.
+ */
+#[derive(Default)]
+pub struct Scheduler {
+    now: u64,
+    cpu_now: u64,
+    double_speed: bool,
+    deadlines: [Option<u64>; Event::ALL.len()],
+    // Earliest of the deadlines, kept apart to make telling how far it is cheap
+    next: Option<u64>,
+}
+
+impl Scheduler {
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn cpu_now(&self) -> u64 {
+        self.cpu_now
+    }
+
+    /// `cycles` of the CPU clock have gone by.
+    pub fn advance(&mut self, cycles: u64) {
+        self.cpu_now += cycles;
+        self.now += self.to_cycles(cycles);
+    }
+
+    /// Cycles of the CPU clock until the earliest deadline, none once it has been reached.
+    pub fn cpu_cycles_to_next(&self) -> u64 {
+        self.next.map_or(u64::MAX, |next| {
+            self.to_cpu_cycles(next.saturating_sub(self.now))
+        })
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /**
+     * Changes the speed of the CPU clock. Events on it that are still to come get closer or
+     * further away, as they are due after the same number of CPU cycles.
+     */
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        let pending: Vec<_> = Event::CPU_CLOCKED
+            .into_iter()
+            .filter_map(|event| {
+                let cycles = self.deadline(event)?.saturating_sub(self.now);
+
+                Some((event, self.to_cpu_cycles(cycles)))
+            })
+            .collect();
+
+        self.double_speed = double_speed;
+
+        for (event, cpu_cycles) in pending {
+            self.schedule_cpu(event, cpu_cycles);
+        }
+
+        self.update_next();
+    }
+
+    /// Cycles `cpu_cycles` of the CPU clock last.
+    pub fn to_cycles(&self, cpu_cycles: u64) -> u64 {
+        if self.double_speed {
+            cpu_cycles / 2
+        } else {
+            cpu_cycles
+        }
+    }
+
+    /// Cycles of the CPU clock in `cycles`.
+    pub fn to_cpu_cycles(&self, cycles: u64) -> u64 {
+        if self.double_speed {
+            cycles * 2
+        } else {
+            cycles
+        }
+    }
+
+    /// Schedules `event` `cycles` from now, replacing any previous deadline for it.
+    pub fn schedule(&mut self, event: Event, cycles: u64) {
+        self.schedule_at(event, self.now + cycles);
+    }
+
+    /// Same as `schedule`, for events of components running on the CPU clock.
+    pub fn schedule_cpu(&mut self, event: Event, cpu_cycles: u64) {
+        self.schedule(event, self.to_cycles(cpu_cycles));
+    }
+
+    pub fn schedule_at(&mut self, event: Event, cycle: u64) {
+        let previous = self.deadlines[event as usize].replace(cycle);
+
+        // Moving the earliest deadline later can make another event the next one
+        if previous.is_some_and(|previous| Some(previous) == self.next && previous < cycle) {
+            self.update_next();
+        } else {
+            self.next = Some(self.next.map_or(cycle, |next| next.min(cycle)));
+        }
+    }
+
+    pub fn cancel(&mut self, event: Event) {
+        self.deadlines[event as usize] = None;
+        self.update_next();
+    }
+
+    pub fn deadline(&self, event: Event) -> Option<u64> {
+        self.deadlines[event as usize]
+    }
+
+    /**
+     * Takes the earliest event whose cycle has been reached, along with that cycle, so it can
+     * schedule its next occurrence without drifting.
+     */
+    pub fn pop_due(&mut self) -> Option<(Event, u64)> {
+        if self.next? > self.now {
+            return None;
+        }
+
+        let event = Event::ALL
+            .into_iter()
+            .filter(|event| self.deadline(*event).is_some_and(|cycle| cycle <= self.now))
+            .min_by_key(|event| self.deadlines[*event as usize])?;
+        let cycle = self.deadlines[event as usize].take()?;
+
+        self.update_next();
+
+        Some((event, cycle))
+    }
+
+    fn update_next(&mut self) {
+        self.next = self.deadlines.iter().flatten().min().copied();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_come_out_when_due_earliest_first() {
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule(Event::PpuMode, 80);
+        scheduler.schedule(Event::OamDma, 20);
+        scheduler.schedule(Event::TimerOverflow, 200);
+
+        scheduler.advance(16);
+        assert_eq!(scheduler.pop_due(), None);
+
+        scheduler.advance(100);
+        assert_eq!(scheduler.pop_due(), Some((Event::OamDma, 20)));
+        assert_eq!(scheduler.pop_due(), Some((Event::PpuMode, 80)));
+        assert_eq!(scheduler.pop_due(), None);
+        assert_eq!(scheduler.deadline(Event::TimerOverflow), Some(200));
+    }
+
+    #[test]
+    fn test_simultaneous_events_come_out_in_declaration_order() {
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule(Event::SerialBit, 4);
+        scheduler.schedule(Event::PpuMode, 4);
+
+        scheduler.advance(4);
+        assert_eq!(scheduler.pop_due(), Some((Event::PpuMode, 4)));
+        assert_eq!(scheduler.pop_due(), Some((Event::SerialBit, 4)));
+    }
+
+    #[test]
+    fn test_rescheduling_and_cancelling_replace_the_deadline() {
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule(Event::TimerOverflow, 8);
+        scheduler.schedule(Event::TimerOverflow, 32);
+        scheduler.schedule(Event::OamDma, 4);
+        scheduler.cancel(Event::OamDma);
+
+        scheduler.advance(16);
+        assert_eq!(scheduler.pop_due(), None);
+
+        scheduler.advance(16);
+        assert_eq!(scheduler.pop_due(), Some((Event::TimerOverflow, 32)));
+    }
+
+    #[test]
+    fn test_rescheduling_later_moves_the_next_deadline() {
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule(Event::OamDma, 4);
+        scheduler.schedule(Event::TimerOverflow, 12);
+        scheduler.schedule(Event::OamDma, 20);
+
+        assert_eq!(scheduler.next, Some(12));
+    }
+
+    #[test]
+    fn test_double_speed_only_speeds_up_cpu_clocked_events() {
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule_cpu(Event::TimerOverflow, 64);
+        scheduler.schedule(Event::PpuMode, 64);
+        scheduler.advance(16);
+
+        scheduler.set_double_speed(true);
+        scheduler.schedule_cpu(Event::SerialBit, 32);
+        scheduler.advance(48);
+
+        assert_eq!(scheduler.now(), 40);
+        assert_eq!(scheduler.cpu_now(), 64);
+        assert_eq!(scheduler.pop_due(), Some((Event::SerialBit, 32)));
+        assert_eq!(scheduler.pop_due(), Some((Event::TimerOverflow, 40)));
+        assert_eq!(scheduler.deadline(Event::PpuMode), Some(64));
+    }
+
+    #[test]
+    fn test_cpu_cycles_to_next_count_on_the_cpu_clock() {
+        let mut scheduler = Scheduler::default();
+        assert_eq!(scheduler.cpu_cycles_to_next(), u64::MAX);
+
+        scheduler.schedule(Event::PpuMode, 80);
+        scheduler.advance(16);
+        assert_eq!(scheduler.cpu_cycles_to_next(), 64);
+
+        scheduler.set_double_speed(true);
+        assert_eq!(scheduler.cpu_cycles_to_next(), 128);
+
+        scheduler.advance(160);
+        assert_eq!(scheduler.cpu_cycles_to_next(), 0);
+    }
+}
@@ -0,0 +1,515 @@
+use crate::cartridge::Cartridge;
+use crate::cpu::registers::{ByteRegister, CpuRegisters};
+use crate::debug::crash_report::panic_message;
+use crate::emulator::Emulator;
+use crate::model::Model;
+use crate::{Byte, Word};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+
+/// Emulated cycles between two looks at the Blargg memory signature and serial output.
+const CHECK_INTERVAL_CYCLES: u64 = 70224;
+
+const BLARGG_STATUS: Word = 0xA000;
+const BLARGG_SIGNATURE: [Byte; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_RUNNING: Byte = 0x80;
+const BLARGG_TEXT: Word = 0xA004;
+const BLARGG_TEXT_MAX_LENGTH: Word = 0x1000;
+
+const MOONEYE_BREAKPOINT_OPCODE: Byte = 0x40;
+const MOONEYE_PASS_REGISTERS: [Byte; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL_REGISTER: Byte = 0x42;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Timeout,
+}
+
+/// Which of the conventions test ROMs use to report their result gave the outcome.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Detection {
+    Serial,
+    MemorySignature,
+    Mooneye,
+    Crash,
+    None,
+}
+
+pub struct TestResult {
+    pub name: String,
+    pub outcome: Outcome,
+    pub detection: Detection,
+    pub cycles: u64,
+    pub message: String,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Outcome::Passed => "PASS",
+            Outcome::Failed => "FAIL",
+            Outcome::Timeout => "TIMEOUT",
+        };
+
+        write!(f, "{text}")
+    }
+}
+
+impl Display for Detection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Detection::Serial => "serial",
+            Detection::MemorySignature => "memory",
+            Detection::Mooneye => "mooneye",
+            Detection::Crash => "crash",
+            Detection::None => "-",
+        };
+
+        write!(f, "{text}")
+    }
+}
+
+/**
+ * Runs every `.gb` file under the directory, recursively and in name order.
+ */
+pub fn run_directory(directory: &Path, model: Model, cycle_budget: u64) -> Vec<TestResult> {
+    let mut paths = Vec::new();
+    find_roms(directory, &mut paths);
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            let name = path
+                .strip_prefix(directory)
+                .unwrap_or(path)
+                .display()
+                .to_string();
+
+            run_rom(path, name, model, cycle_budget)
+        })
+        .collect()
+}
+
+fn find_roms(directory: &Path, paths: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        println!("Could not read directory {}", directory.display());
+        return;
+    };
+
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            find_roms(&path, paths);
+        } else if path.extension().is_some_and(|extension| extension == "gb") {
+            paths.push(path);
+        }
+    }
+}
+
+/**
+ * Runs a ROM headless until it reports a result or the cycle budget runs out. A panic inside
+ * the emulator counts as a failure of that ROM only.
+ */
+pub fn run_rom(path: &Path, name: String, model: Model, cycle_budget: u64) -> TestResult {
+    let path = path.to_str().expect("Invalid ROM path").to_string();
+
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        run_until_result(&path, model, cycle_budget)
+    }));
+
+    let (outcome, detection, cycles, message) = result
+        .unwrap_or_else(|panic| (Outcome::Failed, Detection::Crash, 0, panic_message(&*panic)));
+
+    TestResult {
+        name,
+        outcome,
+        detection,
+        cycles,
+        message,
+    }
+}
+
+fn run_until_result(
+    path: &str,
+    model: Model,
+    cycle_budget: u64,
+) -> (Outcome, Detection, u64, String) {
+    let mut emulator = Emulator::new(Cartridge::new_from_path(path), None, model);
+    emulator.io_registers.write().capture_serial();
+
+    let has_cartridge_ram = emulator
+        .memory
+        .read()
+        .cartridge_header()
+        .ram_size
+        .in_bytes()
+        > 0;
+
+    let mut cycles: u64 = 0;
+    let mut next_check = CHECK_INTERVAL_CYCLES;
+
+    while cycles < cycle_budget {
+        let mooneye_breakpoint = emulator.cpu.is_fetching()
+            && emulator.memory.read().peek_byte(emulator.cpu.registers.pc)
+                == MOONEYE_BREAKPOINT_OPCODE;
+
+        cycles += emulator.step(false, false) as u64;
+
+        if mooneye_breakpoint && let Some(outcome) = mooneye_outcome(&emulator.cpu.registers) {
+            return (outcome, Detection::Mooneye, cycles, String::new());
+        }
+
+        if cycles < next_check {
+            continue;
+        }
+
+        next_check += CHECK_INTERVAL_CYCLES;
+
+        if let Some((outcome, message)) = serial_outcome(&emulator) {
+            return (outcome, Detection::Serial, cycles, message);
+        }
+
+        if has_cartridge_ram && let Some((outcome, message)) = memory_signature_outcome(&emulator) {
+            return (outcome, Detection::MemorySignature, cycles, message);
+        }
+    }
+
+    let message = serial_text(&emulator);
+
+    (Outcome::Timeout, Detection::None, cycles, message)
+}
+
+fn mooneye_outcome(registers: &CpuRegisters) -> Option<Outcome> {
+    let values = [
+        ByteRegister::B,
+        ByteRegister::C,
+        ByteRegister::D,
+        ByteRegister::E,
+        ByteRegister::H,
+        ByteRegister::L,
+    ]
+    .map(|register| registers.read_byte(&register));
+
+    if values == MOONEYE_PASS_REGISTERS {
+        Some(Outcome::Passed)
+    } else if values.iter().all(|value| *value == MOONEYE_FAIL_REGISTER) {
+        Some(Outcome::Failed)
+    } else {
+        None
+    }
+}
+
+fn serial_text(emulator: &Emulator) -> String {
+    String::from_utf8_lossy(emulator.io_registers.read().serial_output())
+        .trim()
+        .to_string()
+}
+
+fn serial_outcome(emulator: &Emulator) -> Option<(Outcome, String)> {
+    let text = serial_text(emulator);
+
+    if text.contains("Passed") {
+        Some((Outcome::Passed, text))
+    } else if text.contains("Failed") {
+        Some((Outcome::Failed, text))
+    } else {
+        None
+    }
+}
+
+/**
+ * Blargg ROMs with cartridge RAM write $DE,$B0,$61 at $A001, keep $80 at $A000 while running and
+ * then leave the result code there, 0 meaning passed, with a text at $A004.
+ */
+fn memory_signature_outcome(emulator: &Emulator) -> Option<(Outcome, String)> {
+    let memory = emulator.memory.read();
+
+    let signature = [0, 1, 2].map(|offset| memory.read_byte(BLARGG_STATUS + 1 + offset));
+    let status = memory.read_byte(BLARGG_STATUS);
+
+    if signature != BLARGG_SIGNATURE || status == BLARGG_RUNNING {
+        return None;
+    }
+
+    let text: Vec<Byte> = (BLARGG_TEXT..BLARGG_TEXT + BLARGG_TEXT_MAX_LENGTH)
+        .map(|position| memory.read_byte(position))
+        .take_while(|byte| *byte != 0)
+        .collect();
+    let message = String::from_utf8_lossy(&text).trim().to_string();
+
+    match status {
+        0 => Some((Outcome::Passed, message)),
+        _ => Some((Outcome::Failed, message)),
+    }
+}
+
+pub fn write_table<W: Write>(output: &mut W, results: &[TestResult]) -> std::io::Result<()> {
+    let name_width = results
+        .iter()
+        .map(|result| result.name.len())
+        .max()
+        .unwrap_or_default()
+        .max(3);
+
+    writeln!(
+        output,
+        "{:<name_width$}  {:<7}  {:<7}  {:>11}",
+        "ROM", "RESULT", "BY", "CYCLES"
+    )?;
+
+    for result in results {
+        writeln!(
+            output,
+            "{:<name_width$}  {:<7}  {:<7}  {:>11}",
+            result.name,
+            result.outcome.to_string(),
+            result.detection.to_string(),
+            result.cycles
+        )?;
+    }
+
+    let count = |outcome: Outcome| {
+        results
+            .iter()
+            .filter(|result| result.outcome == outcome)
+            .count()
+    };
+
+    writeln!(
+        output,
+        "\n{} passed, {} failed, {} timed out",
+        count(Outcome::Passed),
+        count(Outcome::Failed),
+        count(Outcome::Timeout)
+    )
+}
+
+/**
+ * Tab separated report, one ROM per line, that can be diffed between runs or fed back as the
+ * expected results.
+ */
+pub fn write_report<W: Write>(output: &mut W, results: &[TestResult]) -> std::io::Result<()> {
+    writeln!(output, "rom\tresult\tdetection\tcycles\tmessage")?;
+
+    for result in results {
+        let message: String = result
+            .message
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+
+        writeln!(
+            output,
+            "{}\t{}\t{}\t{}\t{}",
+            result.name, result.outcome, result.detection, result.cycles, message
+        )?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Reads back the ROM names that passed in a report.
+ */
+pub fn read_passed_from_report(contents: &str) -> HashMap<String, bool> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.split('\t');
+
+            Some((
+                columns.next()?.to_string(),
+                columns.next()? == Outcome::Passed.to_string(),
+            ))
+        })
+        .collect()
+}
+
+/**
+ * ROMs that did not pass. With the results of an earlier report, only those that passed there.
+ */
+pub fn regressions<'a>(
+    results: &'a [TestResult],
+    expected: Option<&HashMap<String, bool>>,
+) -> Vec<&'a str> {
+    results
+        .iter()
+        .filter(|result| result.outcome != Outcome::Passed)
+        .filter(|result| expected.is_none_or(|expected| expected.get(&result.name) == Some(&true)))
+        .map(|result| result.name.as_str())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use assert_fs::TempDir;
+    use assert_fs::fixture::{FileWriteBin, PathChild};
+
+    const TEST_ROMS_DIR_VARIABLE: &str = "TEST_ROMS_DIR";
+    const EXPECTED_REPORT: &str = "expected.tsv";
+    const TEST_CYCLE_BUDGET: u64 = Cpu::CLOCK_FREQUENCY as u64 * 2;
+
+    /// ROM jumping from the entry point to the program at $0150.
+    fn create_rom(directory: &TempDir, name: &str, program: &[Byte], with_ram: bool) -> PathBuf {
+        let mut data = vec![0; 0x8000];
+        data[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        data[0x150..0x150 + program.len()].copy_from_slice(program);
+
+        if with_ram {
+            // MBC1 + RAM, 8 KiB
+            data[0x147] = 0x02;
+            data[0x149] = 0x02;
+        }
+
+        let file = directory.child(name);
+        file.write_binary(&data).unwrap();
+
+        file.path().to_path_buf()
+    }
+
+    fn run(path: &Path) -> TestResult {
+        run_rom(path, "test".to_string(), Model::Dmg, TEST_CYCLE_BUDGET)
+    }
+
+    #[test]
+    fn test_detects_mooneye_pass_and_fail() {
+        let directory = TempDir::new().unwrap();
+
+        let pass = create_rom(
+            &directory,
+            "pass.gb",
+            &[
+                0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34, 0x40, 0x18, 0xFE,
+            ],
+            false,
+        );
+        let fail = create_rom(
+            &directory,
+            "fail.gb",
+            &[
+                0x3E, 0x42, 0x47, 0x4F, 0x57, 0x5F, 0x67, 0x6F, 0x40, 0x18, 0xFE,
+            ],
+            false,
+        );
+
+        let result = run(&pass);
+        assert_eq!(result.outcome, Outcome::Passed);
+        assert_eq!(result.detection, Detection::Mooneye);
+
+        let result = run(&fail);
+        assert_eq!(result.outcome, Outcome::Failed);
+        assert_eq!(result.detection, Detection::Mooneye);
+    }
+
+    #[test]
+    fn test_detects_blargg_serial_output() {
+        let directory = TempDir::new().unwrap();
+
+        // LD HL,text; loop: LD A,[HL+]; AND A; JR Z,end; LDH [SB],A; LD A,$81; LDH [SC],A;
+        // JR loop; end: JR end; text: "Passed\0"
+        let mut program = vec![
+            0x21, 0x62, 0x01, 0x2A, 0xA7, 0x28, 0x08, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18,
+            0xF4, 0x18, 0xFE,
+        ];
+        program.extend_from_slice(b"\0Passed\0");
+
+        let result = run(&create_rom(&directory, "serial.gb", &program, false));
+
+        assert_eq!(result.outcome, Outcome::Passed);
+        assert_eq!(result.detection, Detection::Serial);
+        assert_eq!(result.message, "Passed");
+    }
+
+    #[test]
+    fn test_detects_blargg_memory_signature() {
+        let directory = TempDir::new().unwrap();
+
+        // Enable RAM, write the signature, "ok" and a failure code of 1, then loop
+        let program = [
+            0x3E, 0x0A, 0xEA, 0x00, 0x00, 0x3E, 0xDE, 0xEA, 0x01, 0xA0, 0x3E, 0xB0, 0xEA, 0x02,
+            0xA0, 0x3E, 0x61, 0xEA, 0x03, 0xA0, 0x3E, b'o', 0xEA, 0x04, 0xA0, 0x3E, b'k', 0xEA,
+            0x05, 0xA0, 0x3E, 0x01, 0xEA, 0x00, 0xA0, 0x18, 0xFE,
+        ];
+
+        let result = run(&create_rom(&directory, "memory.gb", &program, true));
+
+        assert_eq!(result.outcome, Outcome::Failed);
+        assert_eq!(result.detection, Detection::MemorySignature);
+        assert_eq!(result.message, "ok");
+    }
+
+    #[test]
+    fn test_times_out_without_result() {
+        let directory = TempDir::new().unwrap();
+
+        let result = run(&create_rom(&directory, "loop.gb", &[0x18, 0xFE], false));
+
+        assert_eq!(result.outcome, Outcome::Timeout);
+        assert!(result.cycles >= TEST_CYCLE_BUDGET);
+    }
+
+    #[test]
+    fn test_report_round_trip() {
+        let results = [
+            TestResult {
+                name: "cpu_instrs/01-special.gb".to_string(),
+                outcome: Outcome::Passed,
+                detection: Detection::Serial,
+                cycles: 10,
+                message: "01-special\n\nPassed".to_string(),
+            },
+            TestResult {
+                name: "halt_bug.gb".to_string(),
+                outcome: Outcome::Timeout,
+                detection: Detection::None,
+                cycles: 20,
+                message: String::new(),
+            },
+        ];
+
+        let mut report = Vec::new();
+        write_report(&mut report, &results).unwrap();
+        let report = String::from_utf8(report).unwrap();
+
+        assert!(
+            report.contains("cpu_instrs/01-special.gb\tPASS\tserial\t10\t01-special  Passed\n")
+        );
+
+        let passed = read_passed_from_report(&report);
+        assert_eq!(passed.get("cpu_instrs/01-special.gb"), Some(&true));
+        assert_eq!(passed.get("halt_bug.gb"), Some(&false));
+    }
+
+    /**
+     * Runs the ROMs in `TEST_ROMS_DIR`. With an `expected.tsv` report in that directory, only
+     * ROMs that passed there and do not pass anymore fail the test; otherwise all must pass.
+     */
+    #[test]
+    fn test_rom_directory() {
+        let Ok(directory) = std::env::var(TEST_ROMS_DIR_VARIABLE) else {
+            println!("{TEST_ROMS_DIR_VARIABLE} is not set, skipping the test ROMs");
+            return;
+        };
+
+        let directory = Path::new(&directory);
+        let results = run_directory(directory, Model::Dmg, Cpu::CLOCK_FREQUENCY as u64 * 60);
+
+        write_table(&mut std::io::stdout(), &results).unwrap();
+
+        let expected = std::fs::read_to_string(directory.join(EXPECTED_REPORT))
+            .map(|contents| read_passed_from_report(&contents))
+            .ok();
+
+        let regressions = regressions(&results, expected.as_ref());
+
+        assert!(regressions.is_empty(), "Failing ROMs: {regressions:?}");
+    }
+}